@@ -0,0 +1,73 @@
+/*
+ *  Lookup benchmarks
+ *
+ *  The same set of keys in every structure, and the same
+ *  mix of hits and misses thrown at each: the even
+ *  numbers are stored, so about half the probes miss.
+ *
+ *  cargo bench -p binary-tree --bench search
+ */
+
+use std::collections::BTreeSet;
+use std::hint::black_box;
+
+use binary_tree::{BTree, Bst, EytzingerSet, PersistentBst, Treap};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const PROBES: usize = 1024;
+
+fn probes(len: u32) -> Vec<u32> {
+    let mut seed = 0x2545_f491u32;
+    (0..PROBES)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed % (2 * len)
+        })
+        .collect()
+}
+
+// runs every probe through `contains` and counts the hits,
+// so the lookups can't be optimised away.
+fn hits(probes: &[u32], contains: impl Fn(&u32) -> bool) -> usize {
+    probes.iter().filter(|key| contains(black_box(key))).count()
+}
+
+fn lookups(c: &mut Criterion) {
+    let mut group = c.benchmark_group("contains");
+    group.throughput(Throughput::Elements(PROBES as u64));
+    for len in [1 << 10, 1 << 16, 1 << 20] {
+        let keys: Vec<u32> = (0..len).map(|key| key * 2).collect();
+        let probes = probes(len);
+
+        let slice = keys.as_slice();
+        let eytzinger = EytzingerSet::from_sorted(&keys);
+        let prefetching = EytzingerSet::from_sorted(&keys).with_prefetch(true);
+        let bst = Bst::from_sorted_iter(keys.iter().copied());
+        let btree: BTree<u32, ()> = keys.iter().map(|&key| (key, ())).collect();
+        let treap: Treap<u32, ()> = keys.iter().map(|&key| (key, ())).collect();
+        let persistent: PersistentBst<u32> = keys.iter().copied().collect();
+        let std_set: BTreeSet<u32> = keys.iter().copied().collect();
+
+        let mut bench = |name: &str, contains: &dyn Fn(&u32) -> bool| {
+            group.bench_with_input(BenchmarkId::new(name, len), &probes, |b, probes| {
+                b.iter(|| hits(probes, contains))
+            });
+        };
+        bench("slice::binary_search", &|key| {
+            slice.binary_search(key).is_ok()
+        });
+        bench("EytzingerSet", &|key| eytzinger.contains(key));
+        bench("EytzingerSet+prefetch", &|key| prefetching.contains(key));
+        bench("Bst", &|key| bst.contains(key));
+        bench("BTree", &|key| btree.contains_key(key));
+        bench("Treap", &|key| treap.contains_key(key));
+        bench("PersistentBst", &|key| persistent.contains(key));
+        bench("std::BTreeSet", &|key| std_set.contains(key));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lookups);
+criterion_main!(benches);
@@ -0,0 +1,114 @@
+#![no_main]
+
+// runs random operations on a `Bst` and a `PersistentBst`
+// next to a `BTreeMap` of counts, validating both trees
+// after every step.
+
+use std::collections::BTreeMap;
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
+
+use arbitrary::Arbitrary;
+use binary_tree::{Bst, DuplicatePolicy, PersistentBst};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Insert(u8),
+    InsertN(u8, u8),
+    Remove(u8),
+    RemoveOne(u8),
+    Query(u8, u8),
+    Rebalance,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    policy: u8,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: Input| {
+    let policy = match input.policy % 3 {
+        0 => DuplicatePolicy::Reject,
+        1 => DuplicatePolicy::Count,
+        _ => DuplicatePolicy::Replace,
+    };
+    let counted = policy == DuplicatePolicy::Count;
+    let mut tree = Bst::with_policy(policy);
+    let mut persistent = PersistentBst::new();
+    let mut model: BTreeMap<u8, usize> = BTreeMap::new();
+
+    for op in input.ops {
+        match op {
+            Op::Insert(value) => {
+                let fresh = !model.contains_key(&value);
+                if fresh || counted {
+                    *model.entry(value).or_default() += 1;
+                }
+                assert_eq!(tree.insert(value), fresh);
+                persistent = persistent.insert(value);
+            }
+            Op::InsertN(value, n) => {
+                let fresh = !model.contains_key(&value);
+                let added = match n {
+                    0 => 0,
+                    _ if counted => n as usize,
+                    _ => fresh as usize,
+                };
+                if added > 0 {
+                    *model.entry(value).or_default() += added;
+                }
+                assert_eq!(tree.insert_n(value, n as usize), fresh && n > 0);
+                if n > 0 {
+                    persistent = persistent.insert(value);
+                }
+            }
+            Op::Remove(value) => {
+                let expected = model.remove(&value).map(|_| value);
+                assert_eq!(tree.remove(&value), expected);
+                persistent = persistent.remove(&value);
+            }
+            Op::RemoveOne(value) => {
+                let removed = match model.get_mut(&value) {
+                    None => false,
+                    Some(1) => model.remove(&value).is_some(),
+                    Some(count) => {
+                        *count -= 1;
+                        true
+                    }
+                };
+                assert_eq!(tree.remove_one(&value), removed);
+                if !model.contains_key(&value) {
+                    persistent = persistent.remove(&value);
+                }
+            }
+            Op::Query(a, b) => {
+                let (low, high) = (a.min(b), a.max(b));
+                let copies = |range: (Bound<u8>, Bound<u8>)| -> usize {
+                    model.range(range).map(|(_, count)| count).sum()
+                };
+                assert_eq!(tree.count(&a), model.get(&a).copied().unwrap_or(0));
+                assert_eq!(
+                    tree.count_range(low..=high),
+                    copies((Included(low), Included(high)))
+                );
+                assert_eq!(tree.rank(&a), copies((Unbounded, Excluded(a))));
+                assert!(tree.range(low..high).copied().eq(model
+                    .range(low..high)
+                    .flat_map(|(&value, &count)| std::iter::repeat_n(value, count))));
+                assert_eq!(persistent.contains(&a), model.contains_key(&a));
+                assert_eq!(persistent.rank(&a), model.range(..a).count());
+            }
+            Op::Rebalance => tree.rebalance(),
+        }
+
+        if let Err(err) = tree.validate() {
+            panic!("Bst broke an invariant: {err}\n{tree:?}");
+        }
+        if let Err(err) = persistent.validate() {
+            panic!("PersistentBst broke an invariant: {err}");
+        }
+        assert_eq!(tree.len(), model.values().sum::<usize>());
+        assert_eq!(persistent.len(), model.len());
+    }
+});
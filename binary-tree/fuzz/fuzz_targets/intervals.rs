@@ -0,0 +1,58 @@
+#![no_main]
+
+// runs random inserts, removals and overlap queries on an
+// `IntervalTree` next to a plain list, validating the tree
+// after every step.
+
+use arbitrary::Arbitrary;
+use binary_tree::IntervalTree;
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Insert(u8, u8),
+    Remove(u8, u8),
+    Point(u8),
+    Overlap(u8, u8),
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    let mut tree = IntervalTree::new();
+    let mut model: Vec<std::ops::Range<u16>> = Vec::new();
+
+    for op in ops {
+        match op {
+            Op::Insert(start, len) => {
+                let interval = start as u16..start as u16 + len as u16 % 32 + 1;
+                tree.insert(interval.clone(), ());
+                model.push(interval);
+            }
+            Op::Remove(start, len) => {
+                let interval = start as u16..start as u16 + len as u16 % 32 + 1;
+                let found = model.iter().position(|i| *i == interval);
+                if let Some(i) = found {
+                    model.swap_remove(i);
+                }
+                assert_eq!(tree.remove(&interval).is_some(), found.is_some());
+            }
+            Op::Point(point) => {
+                let point = point as u16;
+                let expected = model.iter().filter(|i| i.contains(&point)).count();
+                assert_eq!(tree.query_point(&point).count(), expected);
+            }
+            Op::Overlap(a, b) => {
+                let query = a.min(b) as u16..a.max(b) as u16 + 1;
+                let expected = model
+                    .iter()
+                    .filter(|i| i.start < query.end && query.start < i.end)
+                    .count();
+                assert_eq!(tree.query_overlap(query).count(), expected);
+            }
+        }
+
+        if let Err(err) = tree.validate() {
+            panic!("IntervalTree broke an invariant: {err}");
+        }
+        assert_eq!(tree.len(), model.len());
+    }
+});
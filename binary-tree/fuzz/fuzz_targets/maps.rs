@@ -0,0 +1,106 @@
+#![no_main]
+
+// replays random operations against every `OrderedMap` in
+// the crate and `BTreeMap`, validating each map after
+// every step. the radix trie is keyed by bytes, so it is
+// driven alongside the others rather than through the
+// trait.
+
+use std::collections::BTreeMap;
+
+use arbitrary::Arbitrary;
+use binary_tree::{BTree, BstMap, OrderedMap, RadixMap, SplayTree, Treap};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Insert(u16, u32),
+    Remove(u16),
+    Get(u16),
+    Range(u16, u16),
+    SplitAndJoin(u16),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    seed: u64,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut model = BTreeMap::new();
+    let mut bst = BstMap::new();
+    let mut small: BTree<u16, u32, 3> = BTree::new();
+    let mut wide: BTree<u16, u32, 11> = BTree::new();
+    let mut splay = SplayTree::new();
+    let mut treap = Treap::with_seed(input.seed);
+    // big-endian bytes sort the same way as the numbers.
+    let mut radix = RadixMap::new();
+
+    for op in input.ops {
+        let maps: [&mut dyn OrderedMap<u16, u32>; 5] =
+            [&mut bst, &mut small, &mut wide, &mut splay, &mut treap];
+        match op {
+            Op::Insert(key, value) => {
+                let expected = model.insert(key, value);
+                for map in maps {
+                    assert_eq!(map.insert(key, value), expected);
+                }
+                assert_eq!(radix.insert(&key.to_be_bytes(), value), expected);
+            }
+            Op::Remove(key) => {
+                let expected = model.remove(&key);
+                for map in maps {
+                    assert_eq!(map.remove(&key), expected);
+                }
+                assert_eq!(radix.remove(&key.to_be_bytes()), expected);
+            }
+            Op::Get(key) => {
+                let expected = model.get(&key);
+                for map in maps {
+                    assert_eq!(map.get(&key), expected);
+                }
+                assert_eq!(radix.get(&key.to_be_bytes()), expected);
+            }
+            Op::Range(a, b) => {
+                let range = a.min(b)..a.max(b);
+                let expected = || model.range(range.clone());
+                assert!(bst.range(range.clone()).eq(expected()));
+                assert!(small.range(range.clone()).eq(expected()));
+                assert!(wide.range(range.clone()).eq(expected()));
+                assert!(splay.range(range.clone()).eq(expected()));
+                assert!(treap.range(range.clone()).eq(expected()));
+                // the keys sharing a high byte with `a`
+                let [high, _] = a.to_be_bytes();
+                let under = model.iter().filter(|(k, _)| k.to_be_bytes()[0] == high);
+                let prefixed = radix.iter_prefix(&[high]);
+                assert!(prefixed.eq(under.map(|(k, v)| (k.to_be_bytes().to_vec(), v))));
+            }
+            Op::SplitAndJoin(key) => {
+                let (less, rest) = std::mem::take(&mut treap).split(&key);
+                assert!(less.iter().all(|(k, _)| *k < key));
+                assert!(rest.iter().all(|(k, _)| *k >= key));
+                treap = Treap::join(less, rest);
+            }
+        }
+
+        let checks = [
+            ("BstMap", bst.validate()),
+            ("BTree<3>", small.validate()),
+            ("BTree<11>", wide.validate()),
+            ("SplayTree", splay.validate()),
+            ("Treap", treap.validate()),
+            ("RadixMap", radix.validate()),
+        ];
+        for (name, result) in checks {
+            if let Err(err) = result {
+                panic!("{name} broke an invariant: {err}");
+            }
+        }
+        let maps: [&dyn OrderedMap<u16, u32>; 5] = [&bst, &small, &wide, &splay, &treap];
+        for map in maps {
+            assert_eq!(map.len(), model.len());
+        }
+        assert_eq!(radix.len(), model.len());
+    }
+});
@@ -0,0 +1,121 @@
+/*
+ *  AVL balancing
+ *
+ *  Rotations and rebalancing shared by the balanced
+ *  trees in the crate. A node type only says where its
+ *  children are and how to refresh what it caches about
+ *  its subtree, its height included. Everything here
+ *  keeps the two subtrees of every node within one
+ *  level of each other.
+ */
+
+use crate::observe::Rotation;
+use crate::shape::ChildrenMut;
+
+pub(crate) trait AvlNode: ChildrenMut {
+    fn height(&self) -> usize;
+    // recomputes the height and anything else derived
+    // from the children. called after they change.
+    fn update(&mut self);
+}
+
+pub(crate) fn height<N: AvlNode>(node: Option<&N>) -> usize {
+    node.map_or(0, N::height)
+}
+
+// how much taller the right subtree is than the left.
+fn balance_factor<N: AvlNode>(node: &N) -> isize {
+    height(node.right()) as isize - height(node.left()) as isize
+}
+
+pub(crate) fn rotate_left<N: AvlNode>(mut node: Box<N>) -> Box<N> {
+    let mut right = node
+        .right_mut()
+        .take()
+        .expect("rotating left needs a right child");
+    *node.right_mut() = right.left_mut().take();
+    node.update();
+    *right.left_mut() = Some(node);
+    right.update();
+    right
+}
+
+pub(crate) fn rotate_right<N: AvlNode>(mut node: Box<N>) -> Box<N> {
+    let mut left = node
+        .left_mut()
+        .take()
+        .expect("rotating right needs a left child");
+    *node.left_mut() = left.right_mut().take();
+    node.update();
+    *left.right_mut() = Some(node);
+    left.update();
+    left
+}
+
+// restores the balance of a node after one insert or
+// removal below it.
+pub(crate) fn rebalance<N: AvlNode>(node: Box<N>) -> Box<N> {
+    rebalance_observed(node, &mut |_, _| {})
+}
+
+// `rebalance`, calling `on_rotate` with each node a
+// rotation moves down before it turns.
+pub(crate) fn rebalance_observed<N: AvlNode>(
+    mut node: Box<N>,
+    on_rotate: &mut impl FnMut(&N, Rotation),
+) -> Box<N> {
+    node.update();
+    match balance_factor(&*node) {
+        2.. => {
+            let right = node.right_mut().take().unwrap();
+            *node.right_mut() = Some(if balance_factor(&*right) < 0 {
+                on_rotate(&right, Rotation::Right);
+                rotate_right(right)
+            } else {
+                right
+            });
+            on_rotate(&node, Rotation::Left);
+            rotate_left(node)
+        }
+        ..=-2 => {
+            let left = node.left_mut().take().unwrap();
+            *node.left_mut() = Some(if balance_factor(&*left) > 0 {
+                on_rotate(&left, Rotation::Left);
+                rotate_left(left)
+            } else {
+                left
+            });
+            on_rotate(&node, Rotation::Right);
+            rotate_right(node)
+        }
+        _ => node,
+    }
+}
+
+// detaches the leftmost node of a subtree, rebalancing
+// the path back up. returns the new subtree and the node.
+pub(crate) fn pop_min<N: AvlNode>(mut node: Box<N>) -> (Option<Box<N>>, Box<N>) {
+    match node.left_mut().take() {
+        None => {
+            let rest = node.right_mut().take();
+            node.update();
+            (rest, node)
+        }
+        Some(left) => {
+            let (rest, min) = pop_min(left);
+            *node.left_mut() = rest;
+            (Some(rebalance(node)), min)
+        }
+    }
+}
+
+// joins the children of a removed node.
+pub(crate) fn merge<N: AvlNode>(left: Option<Box<N>>, right: Option<Box<N>>) -> Option<Box<N>> {
+    let Some(right) = right else {
+        return left;
+    };
+    let (rest, mut min) = pop_min(right);
+    *min.left_mut() = left;
+    *min.right_mut() = rest;
+    Some(rebalance(min))
+}
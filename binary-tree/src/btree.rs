@@ -0,0 +1,640 @@
+/*
+ *  B-tree
+ *
+ *  Each node holds up to `B` keys in fixed arrays, so a
+ *  lookup touches one allocation per level instead of
+ *  one per key, and there are only about log_B(n)
+ *  levels. Every leaf sits at the same depth.
+ *
+ *  Nodes other than the root never drop below
+ *  `(B - 1) / 2` keys. Inserts split full nodes on the
+ *  way down and removals top up thin ones on the way
+ *  down, so neither ever has to walk back up.
+ */
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::RangeBounds;
+
+use crate::range::{after_start, before_end};
+use crate::validate::{InvariantViolation, Rule};
+use crate::Natural;
+
+struct Node<K, V, const B: usize> {
+    len: usize,
+    // only the first `len` slots are filled.
+    keys: [Option<K>; B],
+    values: [Option<V>; B],
+    // empty in a leaf, `len + 1` children otherwise.
+    children: Vec<Box<Node<K, V, B>>>,
+}
+
+impl<K, V, const B: usize> Node<K, V, B> {
+    const MIN: usize = (B - 1) / 2;
+
+    fn new() -> Self {
+        Self {
+            len: 0,
+            keys: std::array::from_fn(|_| None),
+            values: std::array::from_fn(|_| None),
+            children: Vec::new(),
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    fn key(&self, i: usize) -> &K {
+        self.keys[i].as_ref().unwrap()
+    }
+
+    fn value(&self, i: usize) -> &V {
+        self.values[i].as_ref().unwrap()
+    }
+
+    fn insert_at(&mut self, i: usize, key: K, value: V) {
+        self.keys[i..=self.len].rotate_right(1);
+        self.values[i..=self.len].rotate_right(1);
+        self.keys[i] = Some(key);
+        self.values[i] = Some(value);
+        self.len += 1;
+    }
+
+    fn remove_at(&mut self, i: usize) -> (K, V) {
+        let key = self.keys[i].take().unwrap();
+        let value = self.values[i].take().unwrap();
+        self.keys[i..self.len].rotate_left(1);
+        self.values[i..self.len].rotate_left(1);
+        self.len -= 1;
+        (key, value)
+    }
+
+    fn take(&mut self, i: usize) -> (K, V) {
+        (self.keys[i].take().unwrap(), self.values[i].take().unwrap())
+    }
+
+    fn put(&mut self, i: usize, (key, value): (K, V)) -> (K, V) {
+        (
+            self.keys[i].replace(key).unwrap(),
+            self.values[i].replace(value).unwrap(),
+        )
+    }
+
+    // where `key` is, or which child to look in.
+    fn search<Q>(&self, key: &Q) -> Result<usize, usize>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.keys[..self.len].binary_search_by(|k| k.as_ref().unwrap().borrow().cmp(key))
+    }
+
+    // splits the full child `i` around its middle key,
+    // which moves up into this node.
+    fn split_child(&mut self, i: usize) {
+        let child = &mut self.children[i];
+        let mid = B / 2;
+        let mut right = Box::new(Node::new());
+        for j in mid + 1..B {
+            let entry = child.take(j);
+            right.insert_at(j - mid - 1, entry.0, entry.1);
+        }
+        if !child.is_leaf() {
+            right.children = child.children.split_off(mid + 1);
+        }
+        let (key, value) = child.take(mid);
+        child.len = mid;
+        self.insert_at(i, key, value);
+        self.children.insert(i + 1, right);
+    }
+
+    // makes sure child `i` can lose a key, borrowing one
+    // from a sibling or merging with it. returns the index
+    // of the child that now covers the same keys.
+    fn fix_child(&mut self, i: usize) -> usize {
+        if self.children[i].len > Self::MIN {
+            return i;
+        }
+        // rotate a key through the separator, along with
+        // the child that changes sides.
+        if i > 0 && self.children[i - 1].len > Self::MIN {
+            let left = &mut self.children[i - 1];
+            let entry = left.remove_at(left.len - 1);
+            let grandchild = left.children.pop();
+            let (key, value) = self.put(i - 1, entry);
+            let child = &mut self.children[i];
+            child.insert_at(0, key, value);
+            child.children.splice(0..0, grandchild);
+            return i;
+        }
+        if i < self.len && self.children[i + 1].len > Self::MIN {
+            let right = &mut self.children[i + 1];
+            let entry = right.remove_at(0);
+            let grandchild = (!right.is_leaf()).then(|| right.children.remove(0));
+            let (key, value) = self.put(i, entry);
+            let child = &mut self.children[i];
+            child.insert_at(child.len, key, value);
+            child.children.extend(grandchild);
+            return i;
+        }
+        let i = if i < self.len { i } else { i - 1 };
+        self.merge_children(i);
+        i
+    }
+
+    // folds child `i + 1` and the key between them into
+    // child `i`.
+    fn merge_children(&mut self, i: usize) {
+        let mut right = self.children.remove(i + 1);
+        let (key, value) = self.remove_at(i);
+        let left = &mut self.children[i];
+        left.insert_at(left.len, key, value);
+        for j in 0..right.len {
+            let (key, value) = right.take(j);
+            left.insert_at(left.len, key, value);
+        }
+        left.children.append(&mut right.children);
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match self.search(key) {
+            Ok(i) if self.is_leaf() => Some(self.remove_at(i)),
+            Ok(i) => {
+                // swap in the predecessor or successor, or
+                // merge and look again in the merged child.
+                if self.children[i].len > Self::MIN {
+                    let pred = self.children[i].remove_max();
+                    Some(self.put(i, pred))
+                } else if self.children[i + 1].len > Self::MIN {
+                    let succ = self.children[i + 1].remove_min();
+                    Some(self.put(i, succ))
+                } else {
+                    self.merge_children(i);
+                    self.children[i].remove(key)
+                }
+            }
+            Err(_) if self.is_leaf() => None,
+            Err(i) => {
+                let i = self.fix_child(i);
+                self.children[i].remove(key)
+            }
+        }
+    }
+
+    fn remove_min(&mut self) -> (K, V) {
+        if self.is_leaf() {
+            return self.remove_at(0);
+        }
+        let i = self.fix_child(0);
+        self.children[i].remove_min()
+    }
+
+    fn remove_max(&mut self) -> (K, V) {
+        if self.is_leaf() {
+            return self.remove_at(self.len - 1);
+        }
+        let i = self.fix_child(self.len);
+        self.children[i].remove_max()
+    }
+
+    // checks this subtree, whose keys must fall strictly
+    // between `lower` and `upper`. returns how many entries
+    // it holds and how deep it is. on failure `path` leads
+    // to the node that broke the rule.
+    fn validate(
+        &self,
+        path: &mut Vec<usize>,
+        lower: Option<&K>,
+        upper: Option<&K>,
+    ) -> Result<(usize, usize), Rule>
+    where
+        K: Ord,
+    {
+        let min = if path.is_empty() { 1 } else { Self::MIN };
+        if self.len < min || self.len > B {
+            return Err(Rule::KeyCount(self.len));
+        }
+        let filled = |i: usize| self.keys[i].is_some() && self.values[i].is_some();
+        if (0..B).any(|i| filled(i) != (i < self.len)) {
+            return Err(Rule::KeyCount(self.len));
+        }
+        let keys: Vec<&K> = (lower.into_iter())
+            .chain((0..self.len).map(|i| self.key(i)))
+            .chain(upper)
+            .collect();
+        if keys.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(Rule::Order);
+        }
+        if self.is_leaf() {
+            return Ok((self.len, 1));
+        }
+        if self.children.len() != self.len + 1 {
+            return Err(Rule::Shape);
+        }
+
+        let (mut entries, mut depth) = (self.len, None);
+        for (i, child) in self.children.iter().enumerate() {
+            let lower = if i == 0 { lower } else { Some(self.key(i - 1)) };
+            let upper = if i == self.len {
+                upper
+            } else {
+                Some(self.key(i))
+            };
+            path.push(i);
+            let (len, child_depth) = child.validate(path, lower, upper)?;
+            path.pop();
+            if depth.is_some_and(|depth| depth != child_depth) {
+                return Err(Rule::Shape);
+            }
+            depth = Some(child_depth);
+            entries += len;
+        }
+        Ok((entries, depth.unwrap() + 1))
+    }
+}
+
+/// An ordered map whose nodes hold up to `B` keys each.
+pub struct BTree<K, V, const B: usize = 11> {
+    root: Option<Box<Node<K, V, B>>>,
+    len: usize,
+}
+
+impl<K, V, const B: usize> Default for BTree<K, V, B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, const B: usize> BTree<K, V, B> {
+    pub fn new() -> Self {
+        const { assert!(B >= 3, "a B-tree node must hold at least 3 keys") };
+        Self { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn first(&self) -> Option<(&K, &V)> {
+        let mut node = self.root.as_deref()?;
+        while let Some(child) = node.children.first() {
+            node = child;
+        }
+        Some((node.key(0), node.value(0)))
+    }
+
+    pub fn last(&self) -> Option<(&K, &V)> {
+        let mut node = self.root.as_deref()?;
+        while let Some(child) = node.children.last() {
+            node = child;
+        }
+        Some((node.key(node.len - 1), node.value(node.len - 1)))
+    }
+
+    pub fn iter(&self) -> Range<'_, K, V, B> {
+        let mut range = Range {
+            stack: Vec::new(),
+            before_end: Box::new(|_| true),
+        };
+        range.push_left(self.root.as_deref());
+        range
+    }
+}
+
+impl<K, V, const B: usize> BTree<K, V, B>
+where
+    K: Ord,
+{
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut node = self.root.as_deref()?;
+        loop {
+            match node.search(key) {
+                Ok(i) => return Some(node.value(i)),
+                Err(i) => node = node.children.get(i)?,
+            }
+        }
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut node = self.root.as_deref_mut()?;
+        loop {
+            match node.search(key) {
+                Ok(i) => return node.values[i].as_mut(),
+                Err(i) => node = node.children.get_mut(i)?,
+            }
+        }
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Inserts a key-value pair, returning the old value
+    /// if the key was already present.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let root = self.root.get_or_insert_with(|| Box::new(Node::new()));
+        if root.len == B {
+            let old = std::mem::replace(root, Box::new(Node::new()));
+            root.children.push(old);
+            root.split_child(0);
+        }
+
+        let mut node = &mut **root;
+        loop {
+            let i = match node.search(&key) {
+                Ok(i) => return node.values[i].replace(value),
+                Err(i) => i,
+            };
+            if node.is_leaf() {
+                node.insert_at(i, key, value);
+                self.len += 1;
+                return None;
+            }
+            let i = if node.children[i].len == B {
+                node.split_child(i);
+                // the key that moved up may be the one we want
+                match key.cmp(node.key(i)) {
+                    std::cmp::Ordering::Equal => return node.values[i].replace(value),
+                    std::cmp::Ordering::Less => i,
+                    std::cmp::Ordering::Greater => i + 1,
+                }
+            } else {
+                i
+            };
+            node = &mut node.children[i];
+        }
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let root = self.root.as_mut()?;
+        let removed = root.remove(key);
+        // a root left without keys hands over to its only child
+        if root.len == 0 {
+            self.root = root.children.pop();
+        }
+        let (_, value) = removed?;
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// Checks that the keys are in order, that every node
+    /// but the root is at least half full, that all the
+    /// leaves are at the same depth and that the length
+    /// matches the entries.
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        let mut path = Vec::new();
+        let actual = match self.root.as_deref() {
+            None => 0,
+            Some(root) => match root.validate(&mut path, None, None) {
+                Ok((entries, _)) => entries,
+                Err(rule) => return Err(InvariantViolation { path, rule }),
+            },
+        };
+        if actual != self.len {
+            return Err(InvariantViolation::at_root(Rule::Len {
+                cached: self.len,
+                actual,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Returns the entries whose keys fall inside `range`, in order.
+    pub fn range<'a, Q, R>(&'a self, range: R) -> Range<'a, K, V, B>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized + 'a,
+        R: RangeBounds<Q> + 'a,
+    {
+        let mut stack = Vec::new();
+        let mut next = self.root.as_deref();
+        while let Some(node) = next {
+            let i = node.keys[..node.len].partition_point(|key| {
+                !after_start(
+                    &Natural,
+                    range.start_bound(),
+                    key.as_ref().unwrap().borrow(),
+                )
+            });
+            stack.push((node, i));
+            next = node.children.get(i).map(|child| &**child);
+        }
+        Range {
+            stack,
+            before_end: Box::new(move |key| before_end(&Natural, range.end_bound(), key.borrow())),
+        }
+    }
+}
+
+impl<K: Ord, V, const B: usize> FromIterator<(K, V)> for BTree<K, V, B> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = Self::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<K: Ord, V, const B: usize> Extend<(K, V)> for BTree<K, V, B> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, const B: usize> fmt::Debug for BTree<K, V, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K, V, const B: usize> IntoIterator for &'a BTree<K, V, B> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Range<'a, K, V, B>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Range<'a, K, V, const B: usize> {
+    // each node on the path down, with the index of the
+    // next key it will yield once the child before that
+    // key is done.
+    stack: Vec<(&'a Node<K, V, B>, usize)>,
+    before_end: Box<dyn Fn(&K) -> bool + 'a>,
+}
+
+impl<'a, K, V, const B: usize> Range<'a, K, V, B> {
+    fn push_left(&mut self, mut next: Option<&'a Node<K, V, B>>) {
+        while let Some(node) = next {
+            self.stack.push((node, 0));
+            next = node.children.first().map(|child| &**child);
+        }
+    }
+}
+
+impl<'a, K, V, const B: usize> Iterator for Range<'a, K, V, B> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let top = self.stack.last_mut()?;
+            let (node, i) = *top;
+            if i == node.len {
+                self.stack.pop();
+                continue;
+            }
+            top.1 += 1;
+            if !(self.before_end)(node.key(i)) {
+                self.stack.clear();
+                return None;
+            }
+            self.push_left(node.children.get(i + 1).map(|child| &**child));
+            return Some((node.key(i), node.value(i)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
+
+    // checks key order, node fill and that every leaf is
+    // at the same depth. returns the depth.
+    fn check<K: Ord, V, const B: usize>(node: &Node<K, V, B>, is_root: bool) -> usize {
+        assert!(node.len <= B);
+        assert!(is_root || node.len >= Node::<K, V, B>::MIN);
+        assert!(node.keys[..node.len].windows(2).all(|w| w[0] < w[1]));
+        assert!(node.keys[node.len..].iter().all(Option::is_none));
+        if node.is_leaf() {
+            return 1;
+        }
+        assert_eq!(node.children.len(), node.len + 1);
+        let depths: Vec<usize> = node.children.iter().map(|c| check(c, false)).collect();
+        assert!(depths.windows(2).all(|w| w[0] == w[1]));
+        depths[0] + 1
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut tree: BTree<u32, &str, 3> = BTree::new();
+        assert_eq!(tree.insert(2, "b"), None);
+        assert_eq!(tree.insert(1, "a"), None);
+        assert_eq!(tree.insert(2, "B"), Some("b"));
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.get(&2), Some(&"B"));
+        assert_eq!(tree.first(), Some((&1, &"a")));
+        assert_eq!(tree.last(), Some((&2, &"B")));
+        *tree.get_mut(&1).unwrap() = "A";
+        assert_eq!(tree.remove(&1), Some("A"));
+        assert_eq!(tree.remove(&1), None);
+        assert_eq!(tree.remove(&2), Some("B"));
+        assert!(tree.is_empty() && tree.root.is_none());
+        assert_eq!(tree.first(), None);
+    }
+
+    fn matches_btreemap<const B: usize>() {
+        let mut seed = 12345u64;
+        let mut random = move |below: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % below
+        };
+        let mut tree: BTree<u64, u64, B> = BTree::new();
+        let mut model = BTreeMap::new();
+        for step in 0..20_000 {
+            let key = random(2_000);
+            if random(3) == 0 {
+                assert_eq!(tree.remove(&key), model.remove(&key));
+            } else {
+                assert_eq!(tree.insert(key, step), model.insert(key, step));
+            }
+            if step % 1000 == 0 {
+                if let Some(root) = &tree.root {
+                    check(root, true);
+                }
+                assert_eq!(tree.validate(), Ok(()));
+            }
+        }
+        assert_eq!(tree.len(), model.len());
+        assert!(tree.iter().eq(model.iter()));
+        assert!(tree.range(500..700).eq(model.range(500..700)));
+        assert!(tree.range(..=10).eq(model.range(..=10)));
+        assert_eq!(tree.first(), model.first_key_value());
+        assert_eq!(tree.last(), model.last_key_value());
+    }
+
+    #[test]
+    fn matches_btreemap_with_small_nodes() {
+        matches_btreemap::<3>();
+        matches_btreemap::<4>();
+    }
+
+    #[test]
+    fn matches_btreemap_with_wide_nodes() {
+        matches_btreemap::<11>();
+        matches_btreemap::<64>();
+    }
+
+    #[test]
+    fn validate_points_at_the_broken_node() {
+        let mut tree: BTree<u32, (), 3> = (0..10).map(|k| (k, ())).collect();
+        assert_eq!(tree.validate(), Ok(()));
+        let root = tree.root.as_mut().unwrap();
+        root.children[1].keys[0] = Some(100);
+        let err = tree.validate().unwrap_err();
+        assert_eq!((err.path, err.rule), (vec![1], Rule::Order));
+
+        let mut tree: BTree<u32, (), 3> = (0..10).map(|k| (k, ())).collect();
+        tree.len = 9;
+        assert_eq!(
+            tree.validate().unwrap_err().rule,
+            Rule::Len {
+                cached: 9,
+                actual: 10
+            }
+        );
+    }
+
+    #[test]
+    fn stays_shallow() {
+        let tree: BTree<u32, (), 11> = (0..100_000).map(|k| (k, ())).collect();
+        // at least 6 keys per node below the root
+        assert!(check(tree.root.as_ref().unwrap(), true) <= 7);
+        let strings: BTree<String, u32> = [("b".to_string(), 2), ("a".to_string(), 1)]
+            .into_iter()
+            .collect();
+        assert_eq!(strings.get("a"), Some(&1));
+        let from_b = (Bound::Included("b"), Bound::Unbounded);
+        assert_eq!(strings.range::<str, _>(from_b).count(), 1);
+    }
+}
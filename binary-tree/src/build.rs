@@ -0,0 +1,154 @@
+/*
+ *  Building balanced trees from sorted input
+ */
+
+use crate::{Bst, Compare, DuplicatePolicy, Link, Natural, Node, Nodes};
+
+impl<T, C> Bst<T, C> {
+    // builds a perfectly balanced tree out of the next `len`
+    // nodes, which must come in order. the nodes are
+    // consumed in-order, so the left half is built first,
+    // then the root, then the right half.
+    pub(crate) fn build_balanced(
+        nodes: &mut impl Iterator<Item = Box<Node<T>>>,
+        len: usize,
+    ) -> Link<T> {
+        if len == 0 {
+            return None;
+        }
+        let left = Self::build_balanced(nodes, len / 2);
+        let mut node = nodes.next()?;
+        node.left = left;
+        node.right = Self::build_balanced(nodes, len - len / 2 - 1);
+        node.fix_size();
+        Some(node)
+    }
+
+    pub(crate) fn from_sorted_runs(runs: Vec<(T, usize)>, policy: DuplicatePolicy, cmp: C) -> Self {
+        let mut tree = Self::with_policy_and_comparator(policy, cmp);
+        tree.replace_with_runs(runs);
+        tree
+    }
+
+    // swaps the contents for `runs`, clamping the counts
+    // to one unless the policy keeps them.
+    pub(crate) fn replace_with_runs(&mut self, runs: Vec<(T, usize)>) {
+        let keep_counts = self.policy == DuplicatePolicy::Count;
+        let len = runs.len();
+        let mut nodes = runs.into_iter().map(|(value, count)| {
+            let count = if keep_counts { count } else { 1 };
+            Box::new(Node::with_count(value, count))
+        });
+        self.root = Self::build_balanced(&mut nodes, len);
+    }
+}
+
+impl<T, C> Bst<T, C>
+where
+    C: Compare<T>,
+{
+    // folds runs of equal values into single nodes the way
+    // `policy` would have handled inserting them one by one.
+    pub(crate) fn from_sorted_vec(values: Vec<T>, policy: DuplicatePolicy, cmp: C) -> Self {
+        let mut runs: Vec<(T, usize)> = Vec::with_capacity(values.len());
+        for value in values {
+            match runs.last_mut() {
+                Some((last, count)) if cmp.compare(last, &value).is_eq() => match policy {
+                    DuplicatePolicy::Reject => {}
+                    DuplicatePolicy::Count => *count += 1,
+                    DuplicatePolicy::Replace => *last = value,
+                },
+                _ => runs.push((value, 1)),
+            }
+        }
+        Self::from_sorted_runs(runs, policy, cmp)
+    }
+}
+
+impl<T: Ord> Bst<T> {
+    /// Builds a perfectly balanced tree in O(n) out of
+    /// values that are already in ascending order.
+    pub fn from_sorted_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let values: Vec<T> = iter.into_iter().collect();
+        debug_assert!(
+            values.windows(2).all(|pair| pair[0] <= pair[1]),
+            "from_sorted_iter expects its input in ascending order"
+        );
+        Self::from_sorted_vec(values, DuplicatePolicy::default(), Natural)
+    }
+}
+
+impl<T, C> Bst<T, C> {
+    /// Rebuilds the tree so it is perfectly balanced. The
+    /// existing nodes are unhooked in order and relinked,
+    /// so nothing is allocated or cloned.
+    pub fn rebalance(&mut self) {
+        let len = self.runs().count();
+        let mut nodes = Nodes::new(self.root.take());
+        self.root = Self::build_balanced(&mut nodes, len);
+    }
+}
+
+impl<T, C> FromIterator<T> for Bst<T, C>
+where
+    C: Compare<T> + Default,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let cmp = C::default();
+        let mut values: Vec<T> = iter.into_iter().collect();
+        values.sort_by(|a, b| cmp.compare(a, b));
+        Self::from_sorted_vec(values, DuplicatePolicy::default(), cmp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Bst, Link};
+
+    fn height<T>(link: &Link<T>) -> usize {
+        link.as_ref()
+            .map_or(0, |node| 1 + height(&node.left).max(height(&node.right)))
+    }
+
+    #[test]
+    fn from_sorted_iter_is_balanced() {
+        let tree = Bst::from_sorted_iter(0..100_000);
+        assert_eq!(tree.len(), 100_000);
+        // a perfectly balanced tree of 100k nodes is 17 levels deep
+        assert_eq!(height(&tree.root), 17);
+        assert_eq!(tree.select(12_345), Some(&12_345));
+        assert!(tree.iter().copied().eq(0..100_000));
+
+        assert!(Bst::<i32>::from_sorted_iter([]).is_empty());
+    }
+
+    #[test]
+    fn rebalance_a_degenerate_tree() {
+        let mut tree = Bst::from_value(0);
+        for value in 1..1_000 {
+            tree.insert(value);
+        }
+        assert_eq!(height(&tree.root), 1_000);
+
+        tree.rebalance();
+        assert_eq!(height(&tree.root), 10);
+        assert_eq!(tree.len(), 1_000);
+        assert_eq!(tree.rank(&500), 500);
+        assert!(tree.iter().copied().eq(0..1_000));
+    }
+
+    #[test]
+    fn duplicates_fold_into_counts() {
+        let tree = Bst::from_sorted_iter([1, 1, 2, 3, 3, 3]);
+        assert_eq!(tree.len(), 6);
+        assert_eq!(tree.count(&3), 3);
+        assert_eq!(height(&tree.root), 2);
+    }
+
+    #[test]
+    fn collect_sorts_first() {
+        let tree: Bst<i32> = [5, 3, 9, 1, 7].into_iter().collect();
+        assert_eq!(height(&tree.root), 3);
+        assert_eq!(tree.iter().copied().collect::<Vec<_>>(), [1, 3, 5, 7, 9]);
+    }
+}
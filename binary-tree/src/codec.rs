@@ -0,0 +1,474 @@
+/*
+ *  A compact binary format for `Bst`
+ *
+ *  The layout is:
+ *
+ *      magic    4 bytes  b"BST\x01"
+ *      version  1 byte
+ *      policy   1 byte, the `DuplicatePolicy`
+ *      length   u64, little endian, number of values
+ *      nodes    in pre-order
+ *
+ *  Each node is one flag byte saying which children
+ *  follow and whether a u64 copy count comes next, then
+ *  the value. Nodes without the count hold one copy.
+ *  Replaying the nodes in pre-order rebuilds exactly the
+ *  same shape.
+ */
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::{Bst, Compare, DuplicatePolicy, Node};
+
+const MAGIC: [u8; 4] = *b"BST\x01";
+const VERSION: u8 = 1;
+
+const HAS_LEFT: u8 = 0b001;
+const HAS_RIGHT: u8 = 0b010;
+const HAS_COUNT: u8 = 0b100;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(io::Error),
+    Truncated,
+    BadMagic([u8; 4]),
+    UnsupportedVersion(u8),
+    BadPolicy(u8),
+    BadFlags(u8),
+    LengthMismatch { expected: u64, found: u64 },
+    InvalidValue(&'static str),
+    Unordered,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Io(err) => write!(f, "i/o error: {err}"),
+            DecodeError::Truncated => write!(f, "input ended in the middle of a tree"),
+            DecodeError::BadMagic(magic) => write!(f, "not a tree, magic bytes were {magic:?}"),
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported format version {v}"),
+            DecodeError::BadPolicy(p) => write!(f, "unknown duplicate policy {p}"),
+            DecodeError::BadFlags(flags) => write!(f, "invalid node flags {flags:#04x}"),
+            DecodeError::LengthMismatch { expected, found } => {
+                write!(f, "header says {expected} values but found {found}")
+            }
+            DecodeError::InvalidValue(what) => write!(f, "invalid value: {what}"),
+            DecodeError::Unordered => write!(f, "decoded values are not in search tree order"),
+        }
+    }
+}
+
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DecodeError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            DecodeError::Truncated
+        } else {
+            DecodeError::Io(err)
+        }
+    }
+}
+
+/// A value that can be stored in the compact format.
+pub trait Compact: Sized {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, DecodeError>;
+}
+
+macro_rules! compact_int {
+    ($($int:ty),*) => {
+        $(
+            impl Compact for $int {
+                fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+                    writer.write_all(&self.to_le_bytes())
+                }
+
+                fn decode<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+                    let mut bytes = [0; std::mem::size_of::<$int>()];
+                    reader.read_exact(&mut bytes)?;
+                    Ok(<$int>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+
+compact_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Compact for bool {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u8).encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        match u8::decode(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::InvalidValue("bool is not 0 or 1")),
+        }
+    }
+}
+
+impl Compact for char {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u32).encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        char::from_u32(u32::decode(reader)?).ok_or(DecodeError::InvalidValue(
+            "char is not a unicode scalar value",
+        ))
+    }
+}
+
+impl Compact for String {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (self.len() as u64).encode(writer)?;
+        writer.write_all(self.as_bytes())
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let len = u64::decode(reader)?;
+        // read through `take` so a corrupt length can't
+        // make us allocate more than the input holds.
+        let mut bytes = Vec::new();
+        reader.take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(DecodeError::Truncated);
+        }
+        String::from_utf8(bytes).map_err(|_| DecodeError::InvalidValue("string is not utf-8"))
+    }
+}
+
+fn policy_byte(policy: DuplicatePolicy) -> u8 {
+    match policy {
+        DuplicatePolicy::Reject => 0,
+        DuplicatePolicy::Count => 1,
+        DuplicatePolicy::Replace => 2,
+    }
+}
+
+fn policy_from_byte(byte: u8) -> Result<DuplicatePolicy, DecodeError> {
+    match byte {
+        0 => Ok(DuplicatePolicy::Reject),
+        1 => Ok(DuplicatePolicy::Count),
+        2 => Ok(DuplicatePolicy::Replace),
+        _ => Err(DecodeError::BadPolicy(byte)),
+    }
+}
+
+impl<T, C> Bst<T, C>
+where
+    T: Compact,
+{
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION, policy_byte(self.policy)])?;
+        (self.len() as u64).encode(writer)?;
+
+        let mut stack: Vec<&Node<T>> = self.root.as_deref().into_iter().collect();
+        while let Some(node) = stack.pop() {
+            let mut flags = 0;
+            if node.left.is_some() {
+                flags |= HAS_LEFT;
+            }
+            if node.right.is_some() {
+                flags |= HAS_RIGHT;
+            }
+            if node.count > 1 {
+                flags |= HAS_COUNT;
+            }
+            writer.write_all(&[flags])?;
+            if node.count > 1 {
+                (node.count as u64).encode(writer)?;
+            }
+            node.value.encode(writer)?;
+
+            stack.extend(node.right.as_deref());
+            stack.extend(node.left.as_deref());
+        }
+        Ok(())
+    }
+}
+
+// a node whose children are still being read. `pending`
+// holds the flags of the children not started yet.
+struct Frame<T> {
+    node: Box<Node<T>>,
+    flags: u8,
+    pending: u8,
+}
+
+impl<T, C> Bst<T, C>
+where
+    T: Compact,
+    C: Compare<T>,
+{
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, DecodeError>
+    where
+        C: Default,
+    {
+        Self::read_from_with(reader, C::default())
+    }
+
+    /// Reads a tree written with the same order as `cmp`.
+    /// The comparator itself isn't stored in the format.
+    pub fn read_from_with<R: Read>(reader: &mut R, cmp: C) -> Result<Self, DecodeError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(DecodeError::BadMagic(magic));
+        }
+        let version = u8::decode(reader)?;
+        if version != VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let policy = policy_from_byte(u8::decode(reader)?)?;
+        let expected = u64::decode(reader)?;
+        if expected == 0 {
+            return Ok(Self::with_policy_and_comparator(policy, cmp));
+        }
+
+        let mut found = 0;
+        // every copy read so far. checking it against the
+        // header as we go keeps the subtree sizes from
+        // overflowing when `fix_size` adds them up.
+        let mut copies: u64 = 0;
+        let mut read_node = |reader: &mut R| -> Result<Frame<T>, DecodeError> {
+            found += 1;
+            if found > expected {
+                return Err(DecodeError::LengthMismatch { expected, found });
+            }
+            let flags = u8::decode(reader)?;
+            if flags & !(HAS_LEFT | HAS_RIGHT | HAS_COUNT) != 0 {
+                return Err(DecodeError::BadFlags(flags));
+            }
+            let count = if flags & HAS_COUNT != 0 {
+                match u64::decode(reader)? {
+                    0 => return Err(DecodeError::InvalidValue("node holds no copies")),
+                    _ if policy != DuplicatePolicy::Count => {
+                        return Err(DecodeError::InvalidValue("copies in a tree that keeps one"))
+                    }
+                    count => count,
+                }
+            } else {
+                1
+            };
+            copies = match copies.checked_add(count) {
+                Some(total) if total <= expected => total,
+                total => {
+                    return Err(DecodeError::LengthMismatch {
+                        expected,
+                        found: total.unwrap_or(u64::MAX),
+                    })
+                }
+            };
+            let Ok(count) = usize::try_from(count) else {
+                return Err(DecodeError::InvalidValue("more copies than fit in memory"));
+            };
+            if usize::try_from(copies).is_err() {
+                return Err(DecodeError::InvalidValue("more copies than fit in memory"));
+            }
+            let value = T::decode(reader)?;
+            Ok(Frame {
+                node: Box::new(Node::with_count(value, count)),
+                flags,
+                pending: flags,
+            })
+        };
+
+        let mut stack = vec![read_node(reader)?];
+        let root = loop {
+            let frame = stack.last_mut().unwrap();
+            if frame.pending & HAS_LEFT != 0 {
+                frame.pending &= !HAS_LEFT;
+                let child = read_node(reader)?;
+                stack.push(child);
+                continue;
+            }
+            if frame.pending & HAS_RIGHT != 0 {
+                frame.pending &= !HAS_RIGHT;
+                let child = read_node(reader)?;
+                stack.push(child);
+                continue;
+            }
+
+            let mut done = stack.pop().unwrap().node;
+            done.fix_size();
+            match stack.last_mut() {
+                None => break done,
+                // the right child is only started once the
+                // left one is finished, so a parent that
+                // already started its right child is
+                // waiting for that one.
+                Some(parent)
+                    if parent.flags & HAS_RIGHT != 0 && parent.pending & HAS_RIGHT == 0 =>
+                {
+                    parent.node.right = Some(done)
+                }
+                Some(parent) => parent.node.left = Some(done),
+            }
+        };
+
+        let found = root.size as u64;
+        if found != expected {
+            return Err(DecodeError::LengthMismatch { expected, found });
+        }
+        let mut tree = Self::with_policy_and_comparator(policy, cmp);
+        tree.root = Some(root);
+        // equal values must share one node, so the order
+        // has to be strict.
+        let mut values = tree.runs().map(|(value, _)| value);
+        let mut prev = values.next();
+        for value in values {
+            if prev.is_some_and(|prev| tree.cmp.compare(prev, value).is_ge()) {
+                return Err(DecodeError::Unordered);
+            }
+            prev = Some(value);
+        }
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape<T: std::fmt::Display>(tree: &Bst<T>) -> String {
+        tree.pretty().top_down().to_string()
+    }
+
+    #[test]
+    fn round_trip_keeps_the_shape() {
+        let mut tree = Bst::from_value(50u32);
+        for value in [30, 70, 20, 40, 80, 75, 35] {
+            tree.insert(value);
+        }
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes[..6], *b"BST\x01\x01\x01");
+        assert_eq!(bytes.len(), 4 + 1 + 1 + 8 + 8 * (1 + 4));
+
+        let back = Bst::<u32>::read_from(&mut bytes.as_slice()).unwrap();
+        assert!(back.structurally_eq(&tree));
+        assert_eq!(shape(&back), shape(&tree));
+        assert_eq!(back.len(), 8);
+        assert_eq!(back.select(3), Some(&40));
+    }
+
+    #[test]
+    fn strings_and_empty_trees() {
+        let tree: Bst<String> = ["pear", "apple", "fig"]
+            .map(String::from)
+            .into_iter()
+            .collect();
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        let back = Bst::<String>::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(shape(&back), shape(&tree));
+
+        let mut bytes = Vec::new();
+        Bst::<i64>::default().write_to(&mut bytes).unwrap();
+        assert!(Bst::<i64>::read_from(&mut &bytes[..]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn corrupt_input_is_an_error() {
+        let tree: Bst<i32> = (0..10).collect();
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        let read = |bytes: &[u8]| Bst::<i32>::read_from(&mut &bytes[..]).unwrap_err();
+
+        for len in 0..bytes.len() {
+            assert!(matches!(read(&bytes[..len]), DecodeError::Truncated));
+        }
+
+        let mut bad = bytes.clone();
+        bad[0] = b'X';
+        assert!(matches!(read(&bad), DecodeError::BadMagic(_)));
+
+        let mut bad = bytes.clone();
+        bad[4] = 9;
+        assert!(matches!(read(&bad), DecodeError::UnsupportedVersion(9)));
+
+        let mut bad = bytes.clone();
+        bad[5] = 7;
+        assert!(matches!(read(&bad), DecodeError::BadPolicy(7)));
+
+        let mut bad = bytes.clone();
+        bad[14] = 0xff;
+        assert!(matches!(read(&bad), DecodeError::BadFlags(0xff)));
+
+        let mut bad = bytes.clone();
+        bad[6] = 3;
+        assert!(matches!(read(&bad), DecodeError::LengthMismatch { .. }));
+
+        // swap the root value for one that breaks the ordering
+        let mut bad = bytes.clone();
+        bad[15..19].copy_from_slice(&100i32.to_le_bytes());
+        assert!(matches!(read(&bad), DecodeError::Unordered));
+    }
+
+    #[test]
+    fn copies_past_the_header_are_an_error() {
+        // a multiset claiming u64::MAX values, in two nodes
+        // of u64::MAX - 1 copies each. either count fits
+        // the header, but together they overflow.
+        let mut bytes = b"BST\x01\x01\x01".to_vec();
+        bytes.extend(u64::MAX.to_le_bytes());
+        bytes.push(HAS_LEFT | HAS_COUNT);
+        bytes.extend((u64::MAX - 1).to_le_bytes());
+        bytes.push(5);
+        bytes.push(HAS_COUNT);
+        bytes.extend((u64::MAX - 1).to_le_bytes());
+        bytes.push(3);
+        assert!(matches!(
+            Bst::<u8>::read_from(&mut &bytes[..]),
+            Err(DecodeError::LengthMismatch {
+                expected: u64::MAX,
+                found: u64::MAX,
+            })
+        ));
+    }
+
+    #[test]
+    fn counts_and_policy_round_trip() {
+        let mut tree = Bst::from_value(5u8);
+        tree.insert_n(5, 2);
+        tree.insert(9);
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        // root flags, then its count of three
+        assert_eq!(bytes[14], HAS_RIGHT | HAS_COUNT);
+        assert_eq!(bytes[15..23], 3u64.to_le_bytes());
+
+        let back = Bst::<u8>::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(back.count(&5), 3);
+        assert_eq!(back.len(), 4);
+
+        // a set can't hold copies
+        let mut bad = bytes.clone();
+        bad[5] = 0;
+        assert!(matches!(
+            Bst::<u8>::read_from(&mut &bad[..]),
+            Err(DecodeError::InvalidValue(_))
+        ));
+
+        let mut set = Bst::with_policy(DuplicatePolicy::Reject);
+        set.insert('x');
+        let mut bytes = Vec::new();
+        set.write_to(&mut bytes).unwrap();
+        let back = Bst::<char>::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(back.policy(), DuplicatePolicy::Reject);
+    }
+}
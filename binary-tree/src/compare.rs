@@ -0,0 +1,136 @@
+/*
+ *  Comparators
+ *
+ *  A tree asks its comparator for the order of two
+ *  values instead of requiring `T: Ord`, so records can
+ *  be ordered by a field, or by a collation picked at
+ *  runtime. `Natural` falls back to `Ord` and is what a
+ *  plain `Bst<T>` uses.
+ *
+ *  Lookups compare a borrowed `Q` against the stored
+ *  values, so a comparator that implements `Compare<Q>`
+ *  lets a tree of `String` be searched with a `&str`.
+ */
+
+use std::cmp::Ordering;
+
+pub trait Compare<T: ?Sized> {
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+}
+
+/// Orders values by their `Ord` implementation.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Natural;
+
+impl<T: Ord + ?Sized> Compare<T> for Natural {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+impl<T: ?Sized, F> Compare<T> for F
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self(a, b)
+    }
+}
+
+// lets the comparator be picked at runtime.
+impl<T: ?Sized> Compare<T> for Box<dyn Compare<T> + '_> {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        (**self).compare(a, b)
+    }
+}
+
+/// Orders values by the key that `F` extracts from them.
+#[derive(Clone, Copy)]
+pub struct ByKey<F>(pub F);
+
+impl<T, K, F> Compare<T> for ByKey<F>
+where
+    F: Fn(&T) -> K,
+    K: Ord,
+{
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        (self.0)(a).cmp(&(self.0)(b))
+    }
+}
+
+/// Orders values the other way round from `C`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Reversed<C>(pub C);
+
+impl<T: ?Sized, C: Compare<T>> Compare<T> for Reversed<C> {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self.0.compare(b, a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bst, DuplicatePolicy};
+    use std::ops::Bound::{Included, Unbounded};
+
+    // neither `Clone`, `Debug` nor `Ord`.
+    struct Record {
+        tenant: &'static str,
+        id: u32,
+    }
+
+    fn ids<C>(tree: &Bst<Record, C>) -> Vec<u32> {
+        tree.iter().map(|record| record.id).collect()
+    }
+
+    #[test]
+    fn by_key() {
+        let mut tree = Bst::by_key(|record: &Record| record.tenant);
+        tree.insert(Record { tenant: "b", id: 1 });
+        tree.insert(Record { tenant: "a", id: 2 });
+        tree.insert(Record { tenant: "c", id: 3 });
+        assert_eq!(ids(&tree), [2, 1, 3]);
+
+        let probe = Record { tenant: "b", id: 0 };
+        assert_eq!(tree.get(&probe).map(|record| record.id), Some(1));
+        assert_eq!(tree.rank(&probe), 1);
+        assert_eq!(tree.remove(&probe).map(|record| record.id), Some(1));
+        assert_eq!(ids(&tree), [2, 3]);
+    }
+
+    #[test]
+    fn closures_and_runtime_choice() {
+        let mut tree = Bst::with_comparator(|a: &i32, b: &i32| b.cmp(a));
+        for value in [3, 1, 4, 1, 5] {
+            tree.insert(value);
+        }
+        assert_eq!(tree.iter().copied().collect::<Vec<_>>(), [5, 4, 3, 1, 1]);
+        // ranges run in the tree's own order
+        assert_eq!(tree.range((Included(4), Included(1))).count(), 4);
+
+        let by_id = |descending: bool| -> Box<dyn Compare<Record>> {
+            if descending {
+                Box::new(|a: &Record, b: &Record| b.id.cmp(&a.id))
+            } else {
+                Box::new(ByKey(|record: &Record| record.id))
+            }
+        };
+        for descending in [false, true] {
+            let mut tree =
+                Bst::with_policy_and_comparator(DuplicatePolicy::Reject, by_id(descending));
+            for id in [2, 1, 3, 1] {
+                tree.insert(Record { tenant: "t", id });
+            }
+            let expected = if descending { [3, 2, 1] } else { [1, 2, 3] };
+            assert_eq!(ids(&tree), expected);
+        }
+    }
+
+    #[test]
+    fn natural_lookups_borrow() {
+        let tree: Bst<String> = ["b", "a"].map(String::from).into_iter().collect();
+        assert!(tree.contains("a"));
+        assert_eq!(tree.range::<str, _>((Included("b"), Unbounded)).count(), 1);
+    }
+}
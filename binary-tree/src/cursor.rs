@@ -0,0 +1,570 @@
+/*
+ *  Cursors
+ *
+ *  A node only owns its children, so a cursor remembers
+ *  how it got where it is: the path from the root down
+ *  to the current node. Moving to a parent pops the
+ *  path, and stepping to the next or previous value in
+ *  order climbs it or extends it, O(1) amortized.
+ *
+ *  `CursorMut` can't hold references into the tree it
+ *  changes, so it keeps the turns it took instead and
+ *  walks them from the root, O(depth), for each step.
+ *
+ *  Both have a ghost position with no current value,
+ *  past the last value and before the first, like the
+ *  cursors of `LinkedList`.
+ */
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+
+use crate::{Bst, Compare, Natural, Node};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Side {
+    Left,
+    Right,
+}
+
+fn child<T>(node: &Node<T>, side: Side) -> Option<&Node<T>> {
+    match side {
+        Side::Left => node.left.as_deref(),
+        Side::Right => node.right.as_deref(),
+    }
+}
+
+/// A read-only position in a `Bst`.
+pub struct Cursor<'a, T, C = Natural> {
+    tree: &'a Bst<T, C>,
+    // the nodes from the root down to the current one,
+    // empty on the ghost position.
+    path: Vec<&'a Node<T>>,
+}
+
+impl<T, C> Clone for Cursor<'_, T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree,
+            path: self.path.clone(),
+        }
+    }
+}
+
+impl<'a, T, C> Cursor<'a, T, C> {
+    fn at_sides(tree: &'a Bst<T, C>, sides: Option<&[Side]>) -> Self {
+        let mut cursor = Self {
+            tree,
+            path: Vec::new(),
+        };
+        if let (Some(sides), Some(root)) = (sides, tree.root.as_deref()) {
+            cursor.path.push(root);
+            for &side in sides {
+                let next = child(cursor.path.last().unwrap(), side);
+                cursor.path.push(next.expect("the path leads to a node"));
+            }
+        }
+        cursor
+    }
+
+    // the turns from the root to the current node, or
+    // `None` on the ghost position.
+    fn sides(&self) -> Option<Vec<Side>> {
+        if self.path.is_empty() {
+            return None;
+        }
+        let turn = |pair: &[&Node<T>]| match child(pair[0], Side::Left) {
+            Some(left) if std::ptr::eq(left, pair[1]) => Side::Left,
+            _ => Side::Right,
+        };
+        Some(self.path.windows(2).map(turn).collect())
+    }
+
+    /// The value under the cursor, `None` on the ghost.
+    pub fn current(&self) -> Option<&'a T> {
+        self.path.last().map(|node| &node.value)
+    }
+
+    /// How many copies of the current value the tree holds.
+    pub fn count(&self) -> usize {
+        self.path.last().map_or(0, |node| node.count)
+    }
+
+    /// Steps down to the left child, if there is one.
+    pub fn move_left(&mut self) -> bool {
+        self.move_down(Side::Left)
+    }
+
+    /// Steps down to the right child, if there is one.
+    pub fn move_right(&mut self) -> bool {
+        self.move_down(Side::Right)
+    }
+
+    fn move_down(&mut self, side: Side) -> bool {
+        match self.path.last().and_then(|node| child(node, side)) {
+            Some(next) => {
+                self.path.push(next);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Steps up to the parent, unless this is the root.
+    pub fn move_parent(&mut self) -> bool {
+        if self.path.len() < 2 {
+            return false;
+        }
+        self.path.pop();
+        true
+    }
+
+    /// Moves to the next value in order and returns it.
+    /// From the last value this moves to the ghost, and
+    /// from the ghost to the first value.
+    // not an iterator, since the ghost wraps around.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&'a T> {
+        self.step(Side::Right)
+    }
+
+    /// Moves to the previous value in order and returns it.
+    pub fn prev(&mut self) -> Option<&'a T> {
+        self.step(Side::Left)
+    }
+
+    // the next value towards `side` is the far end of the
+    // subtree on that side, or else the first ancestor
+    // reached from the other side.
+    fn step(&mut self, side: Side) -> Option<&'a T> {
+        let other = match side {
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+        };
+        match self.path.last() {
+            None => self.descend(self.tree.root.as_deref(), other),
+            Some(node) => match child(node, side) {
+                Some(next) => self.descend(Some(next), other),
+                None => self.climb(other),
+            },
+        }
+        self.current()
+    }
+
+    fn descend(&mut self, mut next: Option<&'a Node<T>>, side: Side) {
+        while let Some(node) = next {
+            self.path.push(node);
+            next = child(node, side);
+        }
+    }
+
+    // pops up to the first ancestor entered through its
+    // `side` child, or to the ghost if there is none.
+    fn climb(&mut self, side: Side) {
+        while let Some(node) = self.path.pop() {
+            let from_side = self
+                .path
+                .last()
+                .and_then(|parent| child(parent, side))
+                .is_some_and(|c| std::ptr::eq(c, node));
+            if from_side {
+                return;
+            }
+        }
+    }
+}
+
+// follows `sides` down from `root`.
+fn node_at<'n, T>(root: Option<&'n Node<T>>, sides: &[Side]) -> Option<&'n Node<T>> {
+    let mut node = root?;
+    for &side in sides {
+        node = child(node, side).expect("the path leads to a node");
+    }
+    Some(node)
+}
+
+/// A position in a `Bst` that can also insert and remove.
+pub struct CursorMut<'a, T, C = Natural> {
+    tree: &'a mut Bst<T, C>,
+    // the turns from the root to the current node. kept
+    // between moves, so stepping never allocates.
+    sides: Vec<Side>,
+    // on the ghost position `sides` is empty.
+    ghost: bool,
+}
+
+impl<'a, T, C> CursorMut<'a, T, C> {
+    fn new(tree: &'a mut Bst<T, C>, path: Option<Vec<Side>>) -> Self {
+        Self {
+            tree,
+            ghost: path.is_none(),
+            sides: path.unwrap_or_default(),
+        }
+    }
+
+    /// A read-only cursor at the same position.
+    pub fn as_cursor(&self) -> Cursor<'_, T, C> {
+        Cursor::at_sides(self.tree, (!self.ghost).then_some(&self.sides[..]))
+    }
+
+    fn node(&self) -> Option<&Node<T>> {
+        if self.ghost {
+            return None;
+        }
+        node_at(self.tree.root.as_deref(), &self.sides)
+    }
+
+    pub fn current(&self) -> Option<&T> {
+        self.node().map(|node| &node.value)
+    }
+
+    pub fn count(&self) -> usize {
+        self.node().map_or(0, |node| node.count)
+    }
+
+    pub fn move_left(&mut self) -> bool {
+        self.move_down(Side::Left)
+    }
+
+    pub fn move_right(&mut self) -> bool {
+        self.move_down(Side::Right)
+    }
+
+    fn move_down(&mut self, side: Side) -> bool {
+        let found = self.node().and_then(|node| child(node, side)).is_some();
+        if found {
+            self.sides.push(side);
+        }
+        found
+    }
+
+    pub fn move_parent(&mut self) -> bool {
+        self.sides.pop().is_some()
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&T> {
+        self.step(Side::Right);
+        self.current()
+    }
+
+    pub fn prev(&mut self) -> Option<&T> {
+        self.step(Side::Left);
+        self.current()
+    }
+
+    // the same walk as `Cursor::step`, done on the turns.
+    fn step(&mut self, side: Side) {
+        let other = match side {
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+        };
+        let Self { tree, sides, ghost } = self;
+        let root = tree.root.as_deref();
+        let mut next = if *ghost {
+            *ghost = root.is_none();
+            root
+        } else {
+            match child(node_at(root, sides).unwrap(), side) {
+                Some(next) => {
+                    sides.push(side);
+                    Some(next)
+                }
+                None => {
+                    // up to the first ancestor entered
+                    // from the other side
+                    *ghost = true;
+                    while let Some(turn) = sides.pop() {
+                        if turn == other {
+                            *ghost = false;
+                            break;
+                        }
+                    }
+                    return;
+                }
+            }
+        };
+        while let Some(node) = next.and_then(|node| child(node, other)) {
+            sides.push(other);
+            next = Some(node);
+        }
+    }
+
+    /// Removes the current node, every copy of its value
+    /// included, and moves to the next value in order.
+    /// Returns `None` on the ghost.
+    pub fn remove_current(&mut self) -> Option<T> {
+        let (count, has_left, has_right) = {
+            let node = self.node()?;
+            (node.count, node.left.is_some(), node.right.is_some())
+        };
+
+        let mut link = &mut self.tree.root;
+        for &side in &self.sides {
+            let node = link.as_mut().unwrap();
+            node.size -= count;
+            link = match side {
+                Side::Left => &mut node.left,
+                Side::Right => &mut node.right,
+            };
+        }
+        let removed = Bst::<T, C>::unlink(link);
+
+        match (has_left, has_right) {
+            // the successor took the node's place
+            (true, true) => {}
+            // the right child did, and the successor is
+            // the leftmost node under it
+            (false, true) => {
+                let mut node = node_at(self.tree.root.as_deref(), &self.sides);
+                while let Some(left) = node.and_then(|node| child(node, Side::Left)) {
+                    self.sides.push(Side::Left);
+                    node = Some(left);
+                }
+            }
+            // the successor is the first ancestor whose
+            // left subtree this was
+            (_, false) => {
+                self.ghost = true;
+                while let Some(side) = self.sides.pop() {
+                    if side == Side::Left {
+                        self.ghost = false;
+                        break;
+                    }
+                }
+            }
+        }
+        Some(removed.value)
+    }
+}
+
+impl<T, C> CursorMut<'_, T, C>
+where
+    C: Compare<T>,
+{
+    /// Inserts `value` as `Bst::insert` would and moves to
+    /// the node that holds it.
+    pub fn insert_here(&mut self, value: T) -> bool {
+        self.sides.clear();
+        let mut next = self.tree.root.as_deref();
+        while let Some(node) = next {
+            let side = match self.tree.cmp.compare(&value, &node.value) {
+                Ordering::Less => Side::Left,
+                Ordering::Equal => break,
+                Ordering::Greater => Side::Right,
+            };
+            self.sides.push(side);
+            next = child(node, side);
+        }
+        // the value lands on the node this search ended at,
+        // or on a new leaf in the empty spot it ended at,
+        // so the path stays right.
+        self.ghost = false;
+        self.tree.insert(value)
+    }
+}
+
+impl<T, C> Bst<T, C> {
+    /// A cursor on the root.
+    pub fn cursor(&self) -> Cursor<'_, T, C> {
+        Cursor::at_sides(self, Some(&[]))
+    }
+
+    /// A cursor on the smallest value.
+    pub fn cursor_front(&self) -> Cursor<'_, T, C> {
+        let mut cursor = Cursor::at_sides(self, None);
+        cursor.next();
+        cursor
+    }
+
+    /// A cursor on the largest value.
+    pub fn cursor_back(&self) -> Cursor<'_, T, C> {
+        let mut cursor = Cursor::at_sides(self, None);
+        cursor.prev();
+        cursor
+    }
+
+    /// A cursor on the first value not less than `value`,
+    /// or on the ghost if there is none.
+    pub fn cursor_at<Q>(&self, value: &Q) -> Cursor<'_, T, C>
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        let mut cursor = Cursor::at_sides(self, None);
+        // every node on the way down is pushed, then the
+        // path is cut back to the last one not less.
+        let mut keep = 0;
+        let mut next = self.root.as_deref();
+        while let Some(node) = next {
+            cursor.path.push(node);
+            next = match self.cmp.compare(value, node.value.borrow()) {
+                Ordering::Less => {
+                    keep = cursor.path.len();
+                    node.left.as_deref()
+                }
+                Ordering::Equal => return cursor,
+                Ordering::Greater => node.right.as_deref(),
+            };
+        }
+        cursor.path.truncate(keep);
+        cursor
+    }
+
+    pub fn cursor_mut(&mut self) -> CursorMut<'_, T, C> {
+        let path = self.root.as_ref().map(|_| Vec::new());
+        CursorMut::new(self, path)
+    }
+
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, T, C> {
+        let path = self.cursor_front().sides();
+        CursorMut::new(self, path)
+    }
+
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, T, C> {
+        let path = self.cursor_back().sides();
+        CursorMut::new(self, path)
+    }
+
+    pub fn cursor_at_mut<Q>(&mut self, value: &Q) -> CursorMut<'_, T, C>
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        let path = self.cursor_at(value).sides();
+        CursorMut::new(self, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Bst, DuplicatePolicy};
+
+    fn tree() -> Bst<i32> {
+        let mut tree = Bst::from_value(50);
+        for value in [30, 70, 20, 40, 60, 80, 35, 45, 65] {
+            tree.insert(value);
+        }
+        tree
+    }
+
+    #[test]
+    fn walks_in_order_both_ways() {
+        let tree = tree();
+        let mut cursor = tree.cursor_front();
+        let mut forward = vec![*cursor.current().unwrap()];
+        while let Some(&value) = cursor.next() {
+            forward.push(value);
+        }
+        assert!(forward.iter().eq(tree.iter()));
+        // from the ghost, `prev` wraps to the back
+        assert_eq!(cursor.current(), None);
+        assert_eq!(cursor.prev(), Some(&80));
+
+        let mut backward = Vec::new();
+        let mut cursor = tree.cursor_back();
+        while let Some(&value) = cursor.current() {
+            backward.push(value);
+            cursor.prev();
+        }
+        forward.reverse();
+        assert_eq!(backward, forward);
+    }
+
+    #[test]
+    fn moves_around_the_shape() {
+        let tree = tree();
+        let mut cursor = tree.cursor();
+        assert_eq!(cursor.current(), Some(&50));
+        assert!(!cursor.move_parent());
+        assert!(cursor.move_left() && cursor.move_right() && cursor.move_left());
+        assert_eq!(cursor.current(), Some(&35));
+        assert!(!cursor.move_left());
+        // resuming an in-order walk from inside the tree
+        assert_eq!(cursor.next(), Some(&40));
+        assert_eq!(cursor.next(), Some(&45));
+        assert_eq!(cursor.next(), Some(&50));
+        assert!(cursor.move_right() && cursor.move_parent());
+        assert_eq!(cursor.current(), Some(&50));
+
+        assert_eq!(tree.cursor_at(&41).current(), Some(&45));
+        assert_eq!(tree.cursor_at(&45).current(), Some(&45));
+        assert_eq!(tree.cursor_at(&81).current(), None);
+        assert_eq!(Bst::<i32>::default().cursor_front().current(), None);
+    }
+
+    #[test]
+    fn removing_moves_to_the_successor() {
+        let mut tree = tree();
+        let mut cursor = tree.cursor_front_mut();
+        let mut removed = Vec::new();
+        // every other value, covering leaves and nodes with
+        // one and two children
+        while cursor.current().is_some() {
+            removed.push(cursor.remove_current().unwrap());
+            cursor.next();
+        }
+        assert_eq!(removed, [20, 35, 45, 60, 70]);
+        assert_eq!(cursor.remove_current(), None);
+        assert_eq!(
+            tree.iter().copied().collect::<Vec<_>>(),
+            [30, 40, 50, 65, 80]
+        );
+        assert_eq!(tree.validate(), Ok(()));
+
+        let mut cursor = tree.cursor_mut();
+        assert_eq!(cursor.remove_current(), Some(50));
+        assert_eq!(cursor.current(), Some(&65));
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn mut_cursor_walks_like_the_read_only_one() {
+        let mut tree = tree();
+        let values: Vec<i32> = tree.iter().copied().collect();
+        let mut cursor = tree.cursor_mut();
+        assert!(cursor.move_left() && cursor.move_right());
+        assert_eq!(cursor.current(), Some(&40));
+        assert!(cursor.move_parent() && cursor.move_parent() && !cursor.move_parent());
+
+        // off the end from the root, then round the ghost
+        // once each way
+        while cursor.next().is_some() {}
+        let mut forward = Vec::new();
+        while let Some(&value) = cursor.next() {
+            forward.push(value);
+        }
+        assert_eq!(forward, values);
+        let mut backward = Vec::new();
+        while let Some(&value) = cursor.prev() {
+            backward.push(value);
+        }
+        backward.reverse();
+        assert_eq!(backward, values);
+        assert_eq!(cursor.as_cursor().current(), None);
+    }
+
+    #[test]
+    fn insert_here_lands_on_the_value() {
+        let mut tree = Bst::with_policy(DuplicatePolicy::Count);
+        let mut cursor = tree.cursor_mut();
+        assert_eq!(cursor.current(), None);
+        for value in [5, 2, 8, 2] {
+            cursor.insert_here(value);
+            assert_eq!(cursor.current(), Some(&value));
+        }
+        assert_eq!(cursor.count(), 2);
+        assert_eq!(cursor.prev(), None);
+        assert_eq!(cursor.next(), Some(&2));
+        assert_eq!(cursor.next(), Some(&5));
+
+        // a merge that resumes where the last value went
+        assert!(!cursor.insert_here(5));
+        assert!(cursor.insert_here(6));
+        assert_eq!(cursor.next(), Some(&8));
+        assert_eq!(tree.len(), 6);
+        assert_eq!(tree.validate(), Ok(()));
+    }
+}
@@ -0,0 +1,300 @@
+/*
+ *  Eytzinger layout
+ *
+ *  A perfectly balanced search tree with no pointers at
+ *  all: the values sit in one array in breadth-first
+ *  order, the way a binary heap keeps them. Counting
+ *  from 1, the children of slot `k` are `2k` and
+ *  `2k + 1`, so a search is a loop that only ever does
+ *  `k = 2k + (value < key)`, which compiles to a
+ *  conditional move rather than a branch the CPU could
+ *  mispredict.
+ *
+ *  The top levels of the tree share the first few cache
+ *  lines, and the 16 great-great-grandchildren of a slot
+ *  sit next to each other, so a search can ask for them
+ *  four levels ahead and have them loaded by the time it
+ *  gets there.
+ *
+ *  The set can't change once built. It is for lookups
+ *  that vastly outnumber the updates, and a rebuild is
+ *  cheap: O(n) from sorted input.
+ */
+
+use std::borrow::Borrow;
+use std::fmt;
+
+use crate::{Bst, Compare, Natural};
+
+pub struct EytzingerSet<T, C = Natural> {
+    // slot `k` of the tree is at `values[k - 1]`.
+    values: Vec<T>,
+    cmp: C,
+    prefetch: bool,
+}
+
+// the in-order successor of slot `k` in a tree of `len`
+// slots, or 0 past the last one.
+fn successor(mut k: usize, len: usize) -> usize {
+    // a right child, then as far left as it goes.
+    if 2 * k < len {
+        k = 2 * k + 1;
+        while 2 * k <= len {
+            k *= 2;
+        }
+        return k;
+    }
+    // up past every parent we are the right child of, then
+    // one more step to the parent we are the left child of.
+    k >> (k.trailing_ones() + 1)
+}
+
+// the slot with the smallest value.
+fn first(len: usize) -> usize {
+    if len == 0 {
+        return 0;
+    }
+    1 << len.ilog2()
+}
+
+impl<T: Clone + Ord> EytzingerSet<T> {
+    /// Builds the set out of values in ascending order.
+    /// Repeats are kept once.
+    pub fn from_sorted(values: &[T]) -> Self {
+        Self::from_sorted_with(values, Natural)
+    }
+}
+
+impl<T: Clone, C: Compare<T>> EytzingerSet<T, C> {
+    /// Builds the set out of values in ascending order
+    /// under `cmp`. Repeats are kept once.
+    pub fn from_sorted_with(values: &[T], cmp: C) -> Self {
+        debug_assert!(
+            values
+                .windows(2)
+                .all(|pair| cmp.compare(&pair[0], &pair[1]).is_le()),
+            "from_sorted expects its input in ascending order"
+        );
+        let mut distinct: Vec<&T> = Vec::with_capacity(values.len());
+        for value in values {
+            if distinct
+                .last()
+                .is_none_or(|&last| cmp.compare(last, value).is_ne())
+            {
+                distinct.push(value);
+            }
+        }
+        Self::from_distinct(distinct, cmp)
+    }
+
+    // lays out values that are in order with no repeats.
+    fn from_distinct(sorted: Vec<&T>, cmp: C) -> Self {
+        let len = sorted.len();
+        // walking the slots in order hands each one the next
+        // smallest value.
+        let mut rank = vec![0; len];
+        let mut k = first(len);
+        for i in 0..len {
+            rank[k - 1] = i;
+            k = successor(k, len);
+        }
+        Self {
+            values: rank.into_iter().map(|i| sorted[i].clone()).collect(),
+            cmp,
+            prefetch: false,
+        }
+    }
+}
+
+impl<T: Clone, C: Compare<T> + Clone> EytzingerSet<T, C> {
+    /// Copies the distinct values of `tree` into a set
+    /// ordered the same way. O(n).
+    pub fn from_bst(tree: &Bst<T, C>) -> Self {
+        let distinct = tree.runs().map(|(value, _)| value).collect();
+        Self::from_distinct(distinct, tree.comparator().clone())
+    }
+}
+
+impl<T, C> EytzingerSet<T, C> {
+    /// Whether searches ask for the slots four levels down
+    /// ahead of time. Whether that pays off depends on the
+    /// machine and on how much of the set stays cached, so
+    /// measure it with `benches/search.rs`.
+    pub fn with_prefetch(mut self, prefetch: bool) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The values in ascending order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            values: &self.values,
+            next: first(self.values.len()),
+        }
+    }
+
+    #[inline]
+    fn prefetch(&self, k: usize) {
+        // the first of the 16 slots four levels below `k`.
+        // asking for memory past the end is harmless, it is
+        // only a hint.
+        #[cfg(target_arch = "x86_64")]
+        if self.prefetch {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+            let ahead = self.values.as_ptr().wrapping_add(16 * k - 1);
+            // SAFETY: prefetching never faults, whatever the address.
+            unsafe { _mm_prefetch::<_MM_HINT_T0>(ahead.cast()) };
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = k;
+    }
+
+    // descends to a leaf, going right whenever `right` says
+    // so, and returns the last slot it went left at, which
+    // holds the first value `right` didn't hold for.
+    #[inline]
+    fn descend(&self, mut right: impl FnMut(&T) -> bool) -> Option<&T> {
+        let mut k = 1;
+        while k <= self.values.len() {
+            self.prefetch(k);
+            k = 2 * k + right(&self.values[k - 1]) as usize;
+        }
+        // each trailing 1 was a step right; dropping them and
+        // the 0 before them climbs back to that left turn.
+        k >>= k.trailing_ones() + 1;
+        k.checked_sub(1).map(|i| &self.values[i])
+    }
+
+    /// The smallest value not less than `key`.
+    pub fn lower_bound<Q>(&self, key: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Compare<Q>,
+    {
+        self.descend(|value| self.cmp.compare(value.borrow(), key).is_lt())
+    }
+
+    /// The smallest value greater than `key`.
+    pub fn upper_bound<Q>(&self, key: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Compare<Q>,
+    {
+        self.descend(|value| self.cmp.compare(value.borrow(), key).is_le())
+    }
+
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Compare<Q>,
+    {
+        self.lower_bound(key)
+            .is_some_and(|value| self.cmp.compare(value.borrow(), key).is_eq())
+    }
+}
+
+pub struct Iter<'a, T> {
+    values: &'a [T],
+    next: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let k = self.next;
+        if k == 0 {
+            return None;
+        }
+        self.next = successor(k, self.values.len());
+        Some(&self.values[k - 1])
+    }
+}
+
+impl<'a, T, C> IntoIterator for &'a EytzingerSet<T, C> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: fmt::Debug, C> fmt::Debug for EytzingerSet<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lays_out_breadth_first() {
+        let set = EytzingerSet::from_sorted(&[1, 2, 3, 3, 4, 5, 6, 7]);
+        assert_eq!(set.values, [4, 2, 6, 1, 3, 5, 7]);
+        assert_eq!(format!("{set:?}"), "{1, 2, 3, 4, 5, 6, 7}");
+        assert!(EytzingerSet::<u8>::from_sorted(&[]).iter().next().is_none());
+    }
+
+    #[test]
+    fn bounds() {
+        let set = EytzingerSet::from_sorted(&[10, 20, 30, 40, 50]);
+        assert_eq!(set.lower_bound(&5), Some(&10));
+        assert_eq!(set.lower_bound(&20), Some(&20));
+        assert_eq!(set.lower_bound(&21), Some(&30));
+        assert_eq!(set.lower_bound(&51), None);
+        assert_eq!(set.upper_bound(&20), Some(&30));
+        assert_eq!(set.upper_bound(&50), None);
+        assert!(set.contains(&40));
+        assert!(!set.contains(&45));
+
+        let words = ["apple", "fig", "kiwi"].map(String::from);
+        let set = EytzingerSet::from_sorted(&words);
+        assert!(set.contains("fig"));
+        assert_eq!(set.lower_bound("g").map(String::as_str), Some("kiwi"));
+    }
+
+    #[test]
+    fn from_a_bst_keeps_its_order() {
+        let mut tree = Bst::with_comparator(|a: &i32, b: &i32| b.cmp(a));
+        for value in [3, 1, 4, 1, 5, 9, 2, 6] {
+            tree.insert(value);
+        }
+        let set = EytzingerSet::from_bst(&tree).with_prefetch(true);
+        assert!(set.iter().eq(tree.runs().map(|(value, _)| value)));
+        // descending, so the first value not before 7 is 6
+        assert_eq!(set.lower_bound(&7), Some(&6));
+        assert!(set.contains(&9));
+    }
+
+    #[test]
+    fn matches_binary_search() {
+        for len in 0..70 {
+            let values: Vec<u32> = (0..len).map(|v| v * 2).collect();
+            for prefetch in [false, true] {
+                let set = EytzingerSet::from_sorted(&values).with_prefetch(prefetch);
+                assert!(set.iter().eq(&values));
+                for key in 0..2 * len + 2 {
+                    let lower = values.partition_point(|&v| v < key);
+                    let upper = values.partition_point(|&v| v <= key);
+                    assert_eq!(set.lower_bound(&key), values.get(lower));
+                    assert_eq!(set.upper_bound(&key), values.get(upper));
+                    assert_eq!(set.contains(&key), values.binary_search(&key).is_ok());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,246 @@
+/*
+ *  Fenwick tree
+ *
+ *  Prefix sums in a single array the length of the
+ *  values. Counting from 1, slot `i` holds the sum of
+ *  the `i & -i` values that end at `i`, so a prefix is
+ *  the sum of the slots met by clearing the lowest set
+ *  bit until nothing is left, and a change to one value
+ *  reaches every slot met by adding it instead. Both
+ *  take O(log n) steps.
+ *
+ *  It needs subtraction to answer a range that doesn't
+ *  start at 0, which is what a segment tree avoids, but
+ *  it is half the size and has no recursion at all.
+ */
+
+use std::fmt;
+use std::ops::{Add, RangeBounds, Sub};
+
+use crate::range::positions;
+
+pub struct FenwickTree<T> {
+    // slot 0 is unused, so the bit tricks can count from 1.
+    tree: Vec<T>,
+}
+
+// the lowest set bit of `i`.
+fn lowbit(i: usize) -> usize {
+    i & i.wrapping_neg()
+}
+
+impl<T> FenwickTree<T>
+where
+    T: Clone + Default + Add<Output = T> + Sub<Output = T>,
+{
+    /// `len` values, all `T::default()`.
+    pub fn new(len: usize) -> Self {
+        Self {
+            tree: vec![T::default(); len + 1],
+        }
+    }
+
+    /// Builds the tree over `values` in O(n).
+    pub fn build_from(values: impl IntoIterator<Item = T>) -> Self {
+        let mut tree: Vec<T> = std::iter::once(T::default()).chain(values).collect();
+        // each slot, once it holds its own sum, adds it to
+        // the next slot that covers it.
+        for i in 1..tree.len() {
+            let parent = i + lowbit(i);
+            if parent < tree.len() {
+                tree[parent] = tree[parent].clone() + tree[i].clone();
+            }
+        }
+        Self { tree }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds `delta` to the value at `index`.
+    pub fn add(&mut self, index: usize, delta: T) {
+        assert!(
+            index < self.len(),
+            "index {index} out of bounds for length {}",
+            self.len()
+        );
+        let mut i = index + 1;
+        while i < self.tree.len() {
+            self.tree[i] = self.tree[i].clone() + delta.clone();
+            i += lowbit(i);
+        }
+    }
+
+    /// Sets the value at `index`.
+    pub fn update(&mut self, index: usize, value: T) {
+        let old = self.get(index);
+        // taking the old value out and putting the new one in
+        // as two passes never takes more from a slot than it
+        // holds, so unsigned values can go down too. the
+        // difference could underflow.
+        let mut i = index + 1;
+        while i < self.tree.len() {
+            self.tree[i] = self.tree[i].clone() - old.clone();
+            i += lowbit(i);
+        }
+        self.add(index, value);
+    }
+
+    pub fn get(&self, index: usize) -> T {
+        assert!(
+            index < self.len(),
+            "index {index} out of bounds for length {}",
+            self.len()
+        );
+        self.query(index..=index)
+    }
+
+    /// The sum of the first `end` values.
+    pub fn prefix(&self, end: usize) -> T {
+        assert!(
+            end <= self.len(),
+            "prefix end {end} out of bounds for length {}",
+            self.len()
+        );
+        let mut sum = T::default();
+        let mut i = end;
+        while i > 0 {
+            sum = sum + self.tree[i].clone();
+            i -= lowbit(i);
+        }
+        sum
+    }
+
+    /// The sum of the values in `range`.
+    pub fn query(&self, range: impl RangeBounds<usize>) -> T {
+        let (start, end) = positions(range, self.len());
+        self.prefix(end) - self.prefix(start)
+    }
+
+    /// The length of the longest prefix whose sum `pred`
+    /// holds for: the first `i` where `pred(prefix(i + 1))`
+    /// doesn't hold, or `len()` if it always does. O(log n).
+    ///
+    /// `pred` must hold for the empty sum, and once false
+    /// for a prefix, stay false for every longer one, as it
+    /// does for `sum <= target` over values that aren't
+    /// negative.
+    pub fn partition_point(&self, mut pred: impl FnMut(&T) -> bool) -> usize {
+        let len = self.len();
+        let mut end = 0;
+        let mut sum = T::default();
+        // widening the prefix by each power of two, largest
+        // first, lands on slots that hold exactly the values
+        // being added.
+        let mut step = if len == 0 { 0 } else { 1 << len.ilog2() };
+        while step > 0 {
+            if end + step <= len {
+                let next = sum.clone() + self.tree[end + step].clone();
+                if pred(&next) {
+                    end += step;
+                    sum = next;
+                }
+            }
+            step >>= 1;
+        }
+        end
+    }
+}
+
+impl<T> fmt::Debug for FenwickTree<T>
+where
+    T: Clone + Default + Add<Output = T> + Sub<Output = T> + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries((0..self.len()).map(|index| self.get(index)))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_and_updates() {
+        let mut tree = FenwickTree::build_from([5i64, 3, 8, 1, 4]);
+        assert_eq!(tree.query(..), 21);
+        assert_eq!(tree.query(1..4), 12);
+        assert_eq!(tree.prefix(0), 0);
+        tree.update(2, 10);
+        tree.add(0, -5);
+        assert_eq!(format!("{tree:?}"), "[0, 3, 10, 1, 4]");
+        assert_eq!(tree.query(..=2), 13);
+
+        // lowering an unsigned value
+        let mut counters = FenwickTree::build_from([5u32, 7, 9]);
+        counters.update(1, 3);
+        assert_eq!(counters.get(1), 3);
+        assert_eq!(counters.query(..), 17);
+        counters.update(2, 0);
+        assert_eq!(format!("{counters:?}"), "[5, 3, 0]");
+
+        let mut empty = FenwickTree::<u32>::new(3);
+        empty.add(1, 7);
+        assert_eq!(empty.query(1..), 7);
+        assert!(FenwickTree::<u32>::build_from([]).is_empty());
+    }
+
+    #[test]
+    fn partition_point_finds_the_prefix() {
+        let tree = FenwickTree::build_from([2u32, 0, 3, 1, 4]);
+        assert_eq!(tree.partition_point(|&sum| sum < 1), 0);
+        assert_eq!(tree.partition_point(|&sum| sum <= 2), 2);
+        assert_eq!(tree.partition_point(|&sum| sum < 6), 3);
+        assert_eq!(tree.partition_point(|&sum| sum <= 10), 5);
+        assert_eq!(FenwickTree::<u32>::new(0).partition_point(|_| true), 0);
+    }
+
+    #[test]
+    fn matches_a_vec() {
+        let mut seed = 7u64;
+        let mut random = move |below: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % below
+        };
+        for len in [1, 2, 3, 13, 64, 100] {
+            let mut model: Vec<i64> = (0..len).map(|_| random(50) as i64).collect();
+            let mut tree = FenwickTree::build_from(model.clone());
+            for _ in 0..1_000 {
+                let a = random(len as u64) as usize;
+                let b = random(len as u64 + 1) as usize;
+                let (start, end) = (a.min(b), a.max(b));
+                let value = random(20) as i64;
+                if random(2) == 0 {
+                    tree.update(a, value);
+                    model[a] = value;
+                } else {
+                    tree.add(a, value);
+                    model[a] += value;
+                }
+                assert_eq!(
+                    tree.query(start..end),
+                    model[start..end].iter().sum::<i64>()
+                );
+                let total = random(500) as i64;
+                let expected = model
+                    .iter()
+                    .scan(0, |sum, v| {
+                        *sum += v;
+                        Some(*sum)
+                    })
+                    .take_while(|&sum| sum <= total)
+                    .count();
+                assert_eq!(tree.partition_point(|&sum| sum <= total), expected);
+            }
+        }
+    }
+}
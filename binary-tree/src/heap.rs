@@ -0,0 +1,500 @@
+/*
+ *  Indexed heap
+ *
+ *  A binary heap of values, each with a priority, where
+ *  `push` hands back a handle to the value. The heap
+ *  keeps, for every value, where it currently sits in
+ *  the heap array, so a handle can reach it in O(1) and
+ *  changing its priority or taking it out is one sift in
+ *  O(log n), which `std::collections::BinaryHeap` can't
+ *  do.
+ *
+ *  The priority that comes first under the comparator
+ *  comes out first, so `Natural` gives a min-heap, and
+ *  `Reversed<Natural>` a max-heap.
+ *
+ *  A handle names a slot plus the generation of the slot.
+ *  Removing a value bumps the generation, so a handle to
+ *  it stays invalid even once the slot is reused.
+ */
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::validate::{InvariantViolation, Rule};
+use crate::{Compare, Natural, Reversed};
+
+/// Names a value pushed onto an `IndexedHeap`, until it
+/// is popped or removed.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct Handle {
+    slot: usize,
+    generation: u64,
+}
+
+struct Entry<V, P> {
+    value: V,
+    priority: P,
+    // where the entry sits in the heap array.
+    index: usize,
+}
+
+struct Slot<V, P> {
+    generation: u64,
+    entry: Option<Entry<V, P>>,
+}
+
+pub struct IndexedHeap<V, P, C = Natural> {
+    // slots in heap order: the children of `i` are at
+    // `2i + 1` and `2i + 2`.
+    heap: Vec<usize>,
+    slots: Vec<Slot<V, P>>,
+    // slots with no entry, for the next push to reuse.
+    free: Vec<usize>,
+    cmp: C,
+}
+
+impl<V, P> IndexedHeap<V, P> {
+    /// A min-heap.
+    pub fn new() -> Self {
+        Self::with_comparator(Natural)
+    }
+}
+
+impl<V, P> IndexedHeap<V, P, Reversed<Natural>> {
+    /// A max-heap.
+    pub fn new_max() -> Self {
+        Self::with_comparator(Reversed(Natural))
+    }
+}
+
+impl<V, P> Default for IndexedHeap<V, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V, P, C> IndexedHeap<V, P, C> {
+    pub fn with_comparator(cmp: C) -> Self {
+        Self {
+            heap: Vec::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+            cmp,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    fn entry(&self, handle: Handle) -> Option<&Entry<V, P>> {
+        let slot = self.slots.get(handle.slot)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.entry.as_ref()
+    }
+
+    // only for slots the heap array points at, which
+    // always hold an entry.
+    fn at(&self, index: usize) -> &Entry<V, P> {
+        self.slots[self.heap[index]].entry.as_ref().unwrap()
+    }
+
+    fn at_mut(&mut self, index: usize) -> &mut Entry<V, P> {
+        self.slots[self.heap[index]].entry.as_mut().unwrap()
+    }
+
+    /// The value and priority `handle` names, unless it was
+    /// popped or removed.
+    pub fn get(&self, handle: Handle) -> Option<(&V, &P)> {
+        self.entry(handle)
+            .map(|entry| (&entry.value, &entry.priority))
+    }
+
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut V> {
+        self.entry(handle)?;
+        let entry = self.slots[handle.slot].entry.as_mut()?;
+        Some(&mut entry.value)
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.entry(handle).is_some()
+    }
+
+    /// The value whose priority comes first.
+    pub fn peek(&self) -> Option<(&V, &P)> {
+        if self.heap.is_empty() {
+            return None;
+        }
+        let entry = self.at(0);
+        Some((&entry.value, &entry.priority))
+    }
+
+    /// The handle of the value `peek` returns.
+    pub fn peek_handle(&self) -> Option<Handle> {
+        let &slot = self.heap.first()?;
+        Some(Handle {
+            slot,
+            generation: self.slots[slot].generation,
+        })
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.heap.swap(a, b);
+        self.at_mut(a).index = a;
+        self.at_mut(b).index = b;
+    }
+}
+
+impl<V, P, C: Compare<P>> IndexedHeap<V, P, C> {
+    fn before(&self, a: usize, b: usize) -> bool {
+        let (a, b) = (&self.at(a).priority, &self.at(b).priority);
+        self.cmp.compare(a, b).is_lt()
+    }
+
+    fn sift_up(&mut self, mut index: usize) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if !self.before(index, parent) {
+                break;
+            }
+            self.swap(index, parent);
+            index = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut index: usize) {
+        loop {
+            let mut first = index;
+            for child in [2 * index + 1, 2 * index + 2] {
+                if child < self.heap.len() && self.before(child, first) {
+                    first = child;
+                }
+            }
+            if first == index {
+                break;
+            }
+            self.swap(index, first);
+            index = first;
+        }
+    }
+
+    // puts the entry at `index` where it belongs, whichever
+    // way its priority moved.
+    fn sift(&mut self, index: usize) {
+        self.sift_up(index);
+        self.sift_down(index);
+    }
+
+    /// Adds `value` with `priority`. O(log n).
+    pub fn push(&mut self, value: V, priority: P) -> Handle {
+        let index = self.heap.len();
+        let entry = Entry {
+            value,
+            priority,
+            index,
+        };
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot].entry = Some(entry);
+                slot
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    entry: Some(entry),
+                });
+                self.slots.len() - 1
+            }
+        };
+        let generation = self.slots[slot].generation;
+        self.heap.push(slot);
+        self.sift_up(index);
+        Handle { slot, generation }
+    }
+
+    /// Takes out the value whose priority comes first. O(log n).
+    pub fn pop(&mut self) -> Option<(V, P)> {
+        let handle = self.peek_handle()?;
+        self.remove(handle)
+    }
+
+    /// Gives the value `handle` names a new priority, and
+    /// returns the old one. O(log n).
+    pub fn change_priority(&mut self, handle: Handle, priority: P) -> Option<P> {
+        let index = self.entry(handle)?.index;
+        let old = std::mem::replace(&mut self.at_mut(index).priority, priority);
+        self.sift(index);
+        Some(old)
+    }
+
+    /// Takes out the value `handle` names. O(log n).
+    pub fn remove(&mut self, handle: Handle) -> Option<(V, P)> {
+        let index = self.entry(handle)?.index;
+        // the last entry fills the hole, then finds its place.
+        let last = self.heap.len() - 1;
+        self.swap(index, last);
+        self.heap.pop();
+        if index < last {
+            self.sift(index);
+        }
+        let slot = &mut self.slots[handle.slot];
+        slot.generation += 1;
+        self.free.push(handle.slot);
+        let entry = slot.entry.take().unwrap();
+        Some((entry.value, entry.priority))
+    }
+
+    /// Moves every value of `other` into this heap, and
+    /// returns the new handle of each under its old one.
+    /// O(m log n) for `m` values moved, so merging the
+    /// smaller heap into the larger one is cheaper.
+    pub fn merge(&mut self, other: Self) -> HashMap<Handle, Handle> {
+        let mut moved = HashMap::with_capacity(other.len());
+        for (slot, Slot { generation, entry }) in other.slots.into_iter().enumerate() {
+            if let Some(entry) = entry {
+                let new = self.push(entry.value, entry.priority);
+                moved.insert(Handle { slot, generation }, new);
+            }
+        }
+        moved
+    }
+
+    /// Checks that no value's priority comes before its
+    /// parent's, and that each value knows where it sits.
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        // the turns from the root to an index are the bits
+        // below the leading one of `index + 1`.
+        let path = |index: usize| {
+            let n = index + 1;
+            (0..n.ilog2()).rev().map(|bit| n >> bit & 1).collect()
+        };
+        for index in 0..self.heap.len() {
+            let entry = self.slots[self.heap[index]].entry.as_ref();
+            if entry.is_none_or(|entry| entry.index != index) {
+                return Err(InvariantViolation {
+                    path: path(index),
+                    rule: Rule::Position,
+                });
+            }
+            if index > 0 && self.before(index, (index - 1) / 2) {
+                return Err(InvariantViolation {
+                    path: path(index),
+                    rule: Rule::Priority,
+                });
+            }
+        }
+        let held = self
+            .slots
+            .iter()
+            .filter(|slot| slot.entry.is_some())
+            .count();
+        if held != self.heap.len() {
+            return Err(InvariantViolation::at_root(Rule::Len {
+                cached: self.heap.len(),
+                actual: held,
+            }));
+        }
+        Ok(())
+    }
+}
+
+impl<V: fmt::Debug, P: fmt::Debug, C> fmt::Debug for IndexedHeap<V, P, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // in heap order, the first one on top.
+        f.debug_list()
+            .entries((0..self.heap.len()).map(|index| {
+                let entry = self.at(index);
+                (&entry.value, &entry.priority)
+            }))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn min_and_max() {
+        let mut min = IndexedHeap::new();
+        let mut max = IndexedHeap::new_max();
+        for (value, priority) in [("c", 3), ("a", 1), ("d", 4), ("b", 2)] {
+            min.push(value, priority);
+            max.push(value, priority);
+        }
+        assert_eq!(min.peek(), Some((&"a", &1)));
+        assert_eq!(max.peek(), Some((&"d", &4)));
+        fn drain<C: Compare<i32>>(
+            heap: &mut IndexedHeap<&'static str, i32, C>,
+        ) -> Vec<&'static str> {
+            std::iter::from_fn(|| heap.pop().map(|(value, _)| value)).collect()
+        }
+        assert_eq!(drain(&mut min), ["a", "b", "c", "d"]);
+        assert_eq!(drain(&mut max), ["d", "c", "b", "a"]);
+        assert_eq!(min.pop(), None);
+
+        // `Reversed` flips any comparator, not just `Natural`
+        let by_distance = |a: &i32, b: &i32| a.abs().cmp(&b.abs());
+        let mut near = IndexedHeap::with_comparator(by_distance);
+        let mut far = IndexedHeap::with_comparator(Reversed(by_distance));
+        for (value, priority) in [("b", -2), ("d", 4), ("a", 1), ("c", -3)] {
+            near.push(value, priority);
+            far.push(value, priority);
+        }
+        assert_eq!(drain(&mut near), ["a", "b", "c", "d"]);
+        assert_eq!(drain(&mut far), ["d", "c", "b", "a"]);
+    }
+
+    #[test]
+    fn handles_follow_their_value() {
+        let mut heap = IndexedHeap::new();
+        let a = heap.push('a', 10);
+        let b = heap.push('b', 20);
+        let c = heap.push('c', 30);
+
+        assert_eq!(heap.change_priority(c, 5), Some(30));
+        assert_eq!(heap.peek(), Some((&'c', &5)));
+        assert_eq!(heap.change_priority(c, 40), Some(5));
+        assert_eq!(heap.peek_handle(), Some(a));
+
+        assert_eq!(heap.remove(a), Some(('a', 10)));
+        assert_eq!(heap.remove(a), None);
+        assert!(!heap.contains(a));
+        *heap.get_mut(b).unwrap() = 'B';
+        assert_eq!(heap.get(b), Some((&'B', &20)));
+
+        // `d` reuses the slot `a` had, but not its handle
+        let d = heap.push('d', 1);
+        assert_eq!(heap.get(a), None);
+        assert_eq!(heap.change_priority(a, 0), None);
+        assert_eq!(format!("{heap:?}"), "[('d', 1), ('c', 40), ('B', 20)]");
+        assert_eq!(heap.pop(), Some(('d', 1)));
+        assert!(!heap.contains(d));
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+    #[test]
+    fn merge_hands_out_new_handles() {
+        let mut left = IndexedHeap::new();
+        left.push("x", 2);
+        let mut right = IndexedHeap::new();
+        let gone = right.push("gone", 0);
+        let y = right.push("y", 1);
+        let z = right.push("z", 3);
+        right.remove(gone);
+
+        let moved = left.merge(right);
+        assert_eq!(moved.len(), 2);
+        assert_eq!(left.get(moved[&y]), Some((&"y", &1)));
+        left.change_priority(moved[&z], 0);
+        assert_eq!(left.pop(), Some(("z", 0)));
+        assert_eq!(left.len(), 2);
+        assert_eq!(left.validate(), Ok(()));
+    }
+
+    #[test]
+    fn dijkstra() {
+        // from 0: 0-1 is 4, but 0-2-1 is 3
+        let edges: [&[(usize, u32)]; 4] = [&[(1, 4), (2, 1)], &[(3, 1)], &[(1, 2), (3, 5)], &[]];
+        let mut dist = [u32::MAX; 4];
+        let mut handles = [None; 4];
+        let mut queue = IndexedHeap::new();
+        dist[0] = 0;
+        handles[0] = Some(queue.push(0, 0));
+        while let Some((node, d)) = queue.pop() {
+            for &(next, weight) in edges[node] {
+                let through = d + weight;
+                if through >= dist[next] {
+                    continue;
+                }
+                dist[next] = through;
+                match handles[next].filter(|&handle| queue.contains(handle)) {
+                    Some(handle) => {
+                        queue.change_priority(handle, through);
+                    }
+                    None => handles[next] = Some(queue.push(next, through)),
+                }
+            }
+        }
+        assert_eq!(dist, [0, 3, 1, 4]);
+    }
+
+    #[test]
+    fn matches_a_sorted_set() {
+        let mut seed = 3u64;
+        let mut random = move |below: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % below
+        };
+        let mut heap = IndexedHeap::new();
+        // (priority, value) of everything in the heap
+        let mut model = BTreeSet::new();
+        let mut handles = Vec::new();
+        for step in 0..20_000u64 {
+            match random(4) {
+                0 | 1 => {
+                    let priority = random(1_000);
+                    handles.push((heap.push(step, priority), step));
+                    model.insert((priority, step));
+                }
+                2 => {
+                    let popped = heap.pop().map(|(value, priority)| (priority, value));
+                    // ties may come out in any order
+                    let first = model.first().map(|&(priority, _)| priority);
+                    assert_eq!(popped.map(|(priority, _)| priority), first);
+                    if let Some(popped) = popped {
+                        assert!(model.remove(&popped));
+                    }
+                }
+                _ if !handles.is_empty() => {
+                    let (handle, value) = handles[random(handles.len() as u64) as usize];
+                    let Some((_, &old)) = heap.get(handle) else {
+                        continue;
+                    };
+                    model.remove(&(old, value));
+                    if random(2) == 0 {
+                        let priority = random(1_000);
+                        assert_eq!(heap.change_priority(handle, priority), Some(old));
+                        model.insert((priority, value));
+                    } else {
+                        assert_eq!(heap.remove(handle), Some((value, old)));
+                    }
+                }
+                _ => {}
+            }
+            assert_eq!(heap.len(), model.len());
+            if step % 1000 == 0 {
+                assert_eq!(heap.validate(), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn validate_points_at_the_broken_node() {
+        let mut heap = IndexedHeap::new();
+        for priority in 0..7 {
+            heap.push(priority, priority);
+        }
+        assert_eq!(heap.validate(), Ok(()));
+        // index 4 is the right child of the root's left child
+        let slot = heap.heap[4];
+        heap.slots[slot].entry.as_mut().unwrap().priority = 0;
+        assert_eq!(
+            heap.validate(),
+            Err(InvariantViolation {
+                path: vec![0, 1],
+                rule: Rule::Priority,
+            })
+        );
+    }
+}
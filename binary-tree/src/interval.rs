@@ -0,0 +1,505 @@
+/*
+ *  Interval tree
+ *
+ *  An AVL tree of half-open `[start, end)` intervals
+ *  ordered by start, then end. Every node also keeps the
+ *  largest end point in its subtree, so a query can skip
+ *  any subtree that ends before the query starts, and
+ *  stops as soon as the starts pass the query's end.
+ *  That makes a query O(log n + k) for k results.
+ *
+ *  Equal intervals may be stored more than once, each
+ *  with its own value.
+ */
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Range;
+
+use crate::avl::{self, AvlNode};
+use crate::observe::TreeObserver;
+use crate::range::Children;
+use crate::shape::ChildrenMut;
+use crate::validate::{self, check_subtrees, InvariantViolation, Rule};
+
+type Link<K, V> = Option<Box<Node<K, V>>>;
+
+struct Node<K, V> {
+    interval: Range<K>,
+    value: V,
+    // the largest `end` in this subtree.
+    max_end: K,
+    height: usize,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+impl<K, V> Children for Node<K, V> {
+    fn left(&self) -> Option<&Self> {
+        self.left.as_deref()
+    }
+
+    fn right(&self) -> Option<&Self> {
+        self.right.as_deref()
+    }
+}
+
+impl<K, V> ChildrenMut for Node<K, V> {
+    fn left_mut(&mut self) -> &mut Option<Box<Self>> {
+        &mut self.left
+    }
+
+    fn right_mut(&mut self) -> &mut Option<Box<Self>> {
+        &mut self.right
+    }
+}
+
+impl<K: Ord + Clone, V> AvlNode for Node<K, V> {
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn update(&mut self) {
+        self.height = 1 + avl::height(self.left()).max(avl::height(self.right()));
+        let mut max_end = &self.interval.end;
+        for child in [&self.left, &self.right].into_iter().flatten() {
+            max_end = max_end.max(&child.max_end);
+        }
+        self.max_end = max_end.clone();
+    }
+}
+
+fn order<K: Ord>(a: &Range<K>, b: &Range<K>) -> Ordering {
+    a.start.cmp(&b.start).then_with(|| a.end.cmp(&b.end))
+}
+
+pub struct IntervalTree<K, V> {
+    root: Link<K, V>,
+    len: usize,
+}
+
+impl<K, V> Default for IntervalTree<K, V> {
+    fn default() -> Self {
+        Self { root: None, len: 0 }
+    }
+}
+
+impl<K, V> IntervalTree<K, V> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Visits every interval in order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_left(self.root.as_deref());
+        iter
+    }
+}
+
+impl<K, V> IntervalTree<K, V>
+where
+    K: Ord + Clone,
+{
+    /// Stores `value` for the interval `[start, end)`.
+    ///
+    /// Panics if the interval is empty.
+    pub fn insert(&mut self, interval: Range<K>, value: V) {
+        self.insert_observed(interval, value, &mut ());
+    }
+
+    /// Inserts like `insert`, telling `observer` about the
+    /// intervals on the way down, the new node and the
+    /// rotations that rebalance the path back up.
+    pub fn insert_observed(
+        &mut self,
+        interval: Range<K>,
+        value: V,
+        observer: &mut impl TreeObserver<Range<K>>,
+    ) {
+        assert!(
+            interval.start < interval.end,
+            "an interval must start before it ends"
+        );
+        let node = Box::new(Node {
+            max_end: interval.end.clone(),
+            interval,
+            value,
+            height: 1,
+            left: None,
+            right: None,
+        });
+        self.root = Some(Self::insert_node(self.root.take(), node, observer));
+        self.len += 1;
+    }
+
+    fn insert_node(
+        link: Link<K, V>,
+        new: Box<Node<K, V>>,
+        observer: &mut impl TreeObserver<Range<K>>,
+    ) -> Box<Node<K, V>> {
+        let Some(mut node) = link else {
+            observer.on_insert(&new.interval);
+            return new;
+        };
+        let ordering = order(&new.interval, &node.interval);
+        observer.on_visit(&node.interval, ordering);
+        // equal intervals go right, after the older ones
+        if ordering.is_lt() {
+            node.left = Some(Self::insert_node(node.left.take(), new, observer));
+        } else {
+            node.right = Some(Self::insert_node(node.right.take(), new, observer));
+        }
+        avl::rebalance_observed(node, &mut |pivot, rotation| {
+            observer.on_rotate(&pivot.interval, rotation)
+        })
+    }
+
+    /// Removes one interval equal to `interval` and returns
+    /// its value.
+    pub fn remove(&mut self, interval: &Range<K>) -> Option<V> {
+        let (root, removed) = Self::remove_node(self.root.take(), interval);
+        self.root = root;
+        let removed = removed?;
+        self.len -= 1;
+        Some(removed.value)
+    }
+
+    fn remove_node(link: Link<K, V>, interval: &Range<K>) -> (Link<K, V>, Link<K, V>) {
+        let Some(mut node) = link else {
+            return (None, None);
+        };
+        let removed = match order(interval, &node.interval) {
+            Ordering::Less => {
+                let (left, removed) = Self::remove_node(node.left.take(), interval);
+                node.left = left;
+                removed
+            }
+            Ordering::Greater => {
+                let (right, removed) = Self::remove_node(node.right.take(), interval);
+                node.right = right;
+                removed
+            }
+            Ordering::Equal => {
+                let rest = avl::merge(node.left.take(), node.right.take());
+                return (rest, Some(node));
+            }
+        };
+        (Some(avl::rebalance(node)), removed)
+    }
+
+    /// Checks that the intervals are in order and that
+    /// every node's height, balance and largest end are
+    /// right.
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        let (_, actual) = check_subtrees(
+            self.root.as_deref(),
+            || (None, 0),
+            |node, (left, left_len), (right, right_len)| {
+                // equal intervals can end up on either side
+                // once rotations have moved them around.
+                let before = |a: &Range<K>, b: &Range<K>| order(a, b).is_le();
+                let bounds = validate::order(before, left, &node.interval, right)?;
+                let (lh, rh) = (avl::height(node.left()), avl::height(node.right()));
+                if lh.abs_diff(rh) > 1 {
+                    return Err(Rule::Balance {
+                        left: lh,
+                        right: rh,
+                    });
+                }
+                if node.height != 1 + lh.max(rh) {
+                    return Err(Rule::Height {
+                        cached: node.height,
+                        actual: 1 + lh.max(rh),
+                    });
+                }
+                let mut max_end = &node.interval.end;
+                for child in [&node.left, &node.right].into_iter().flatten() {
+                    max_end = max_end.max(&child.max_end);
+                }
+                if node.max_end != *max_end {
+                    return Err(Rule::MaxEnd);
+                }
+                Ok((Some(bounds), left_len + 1 + right_len))
+            },
+        )?;
+        if actual != self.len {
+            return Err(InvariantViolation::at_root(Rule::Len {
+                cached: self.len,
+                actual,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Returns every interval that contains `point`.
+    pub fn query_point(&self, point: &K) -> Overlaps<'_, K, V> {
+        Overlaps::new(self.root.as_deref(), point.clone(), point.clone(), true)
+    }
+
+    /// Returns every interval that overlaps `range`, in order.
+    /// An empty or inverted `range` overlaps nothing.
+    pub fn query_overlap(&self, range: Range<K>) -> Overlaps<'_, K, V> {
+        let root = if range.start < range.end {
+            self.root.as_deref()
+        } else {
+            None
+        };
+        Overlaps::new(root, range.start, range.end, false)
+    }
+
+    pub fn any_overlap(&self, range: Range<K>) -> bool {
+        self.query_overlap(range).next().is_some()
+    }
+}
+
+impl<K: Ord + Clone, V> FromIterator<(Range<K>, V)> for IntervalTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (Range<K>, V)>>(iter: I) -> Self {
+        let mut tree = Self::new();
+        for (interval, value) in iter {
+            tree.insert(interval, value);
+        }
+        tree
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for IntervalTree<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a IntervalTree<K, V> {
+    type Item = (&'a Range<K>, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left(&mut self, mut next: Option<&'a Node<K, V>>) {
+        while let Some(node) = next {
+            self.stack.push(node);
+            next = node.left.as_deref();
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a Range<K>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left(node.right.as_deref());
+        Some((&node.interval, &node.value))
+    }
+}
+
+/// The intervals overlapping a query, in order.
+pub struct Overlaps<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+    start: K,
+    end: K,
+    // a point query also matches intervals starting at
+    // the point, a range query only those before its end.
+    end_inclusive: bool,
+}
+
+impl<'a, K: Ord, V> Overlaps<'a, K, V> {
+    fn new(root: Option<&'a Node<K, V>>, start: K, end: K, end_inclusive: bool) -> Self {
+        let mut overlaps = Self {
+            stack: Vec::new(),
+            start,
+            end,
+            end_inclusive,
+        };
+        overlaps.push_left(root);
+        overlaps
+    }
+
+    // like an in-order walk, but never enters a subtree
+    // where everything ends at or before the start.
+    fn push_left(&mut self, mut next: Option<&'a Node<K, V>>) {
+        while let Some(node) = next {
+            if node.max_end <= self.start {
+                break;
+            }
+            self.stack.push(node);
+            next = node.left.as_deref();
+        }
+    }
+
+    fn starts_in_time(&self, node: &Node<K, V>) -> bool {
+        if self.end_inclusive {
+            node.interval.start <= self.end
+        } else {
+            node.interval.start < self.end
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for Overlaps<'a, K, V> {
+    type Item = (&'a Range<K>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            // the rest of the walk only meets later starts
+            if !self.starts_in_time(node) {
+                self.stack.clear();
+                return None;
+            }
+            self.push_left(node.right.as_deref());
+            if node.interval.end > self.start {
+                return Some((&node.interval, &node.value));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // checks heights, balance and `max_end`, returning the height.
+    fn check<K: Ord + Clone + fmt::Debug, V>(link: &Link<K, V>) -> usize {
+        let Some(node) = link else {
+            return 0;
+        };
+        let (left, right) = (check(&node.left), check(&node.right));
+        assert!(left.abs_diff(right) <= 1);
+        assert_eq!(node.height, 1 + left.max(right));
+        let mut max_end = node.interval.end.clone();
+        for child in [&node.left, &node.right].into_iter().flatten() {
+            max_end = max_end.max(child.max_end.clone());
+        }
+        assert_eq!(node.max_end, max_end);
+        node.height
+    }
+
+    fn values<'a>(overlaps: impl Iterator<Item = (&'a Range<u32>, &'a char)>) -> String {
+        overlaps.map(|(_, value)| *value).collect()
+    }
+
+    #[test]
+    fn point_and_overlap_queries() {
+        let tree: IntervalTree<u32, char> = [
+            (5..10, 'a'),
+            (0..3, 'b'),
+            (8..12, 'c'),
+            (3..5, 'd'),
+            (9..10, 'e'),
+        ]
+        .into_iter()
+        .collect();
+        check(&tree.root);
+
+        assert_eq!(values(tree.query_point(&9)), "ace");
+        assert_eq!(values(tree.query_point(&3)), "d");
+        assert_eq!(values(tree.query_point(&12)), "");
+        assert_eq!(values(tree.query_overlap(4..6)), "da");
+        // half-open, so touching intervals don't overlap
+        assert_eq!(values(tree.query_overlap(10..12)), "c");
+        assert!(tree.any_overlap(2..4));
+        assert!(!tree.any_overlap(12..20));
+    }
+
+    #[test]
+    fn remove_keeps_the_tree_balanced() {
+        let mut tree = IntervalTree::new();
+        for start in 0..1000u32 {
+            tree.insert(start..start + 10, start);
+        }
+        tree.insert(5..15, 1000);
+        // an AVL tree of n nodes is under 1.45 log2(n) deep
+        assert!(check(&tree.root) <= 14);
+
+        for start in (0..1000).step_by(2) {
+            assert_eq!(tree.remove(&(start..start + 10)), Some(start));
+            check(&tree.root);
+            assert_eq!(tree.validate(), Ok(()));
+        }
+        assert_eq!(tree.remove(&(0..10)), None);
+        // either copy of 5..15 may go first
+        let mut copies = [tree.remove(&(5..15)), tree.remove(&(5..15))];
+        copies.sort();
+        assert_eq!(copies, [Some(5), Some(1000)]);
+        assert_eq!(tree.len(), 499);
+        assert_eq!(
+            tree.query_point(&20).map(|(_, v)| *v).collect::<Vec<_>>(),
+            [11, 13, 15, 17, 19]
+        );
+    }
+
+    #[test]
+    fn matches_a_linear_scan() {
+        let mut seed = 7u32;
+        let mut random = move |below: u32| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed % below
+        };
+        let mut intervals = Vec::new();
+        let mut tree = IntervalTree::new();
+        for i in 0..500 {
+            let start = random(1000);
+            let interval = start..start + 1 + random(50);
+            intervals.push(interval.clone());
+            tree.insert(interval, i);
+        }
+        for _ in 0..200 {
+            let start = random(1000);
+            // some of these come out empty or inverted
+            let end = start + random(31);
+            let query = if random(5) == 0 {
+                end..start
+            } else {
+                start..end
+            };
+            let mut expected: Vec<_> = intervals
+                .iter()
+                .filter(|i| {
+                    // `[a, b)` is empty unless `a < b`
+                    query.start < query.end && i.start < query.end && query.start < i.end
+                })
+                .cloned()
+                .collect();
+            expected.sort_by(order);
+            let found: Vec<_> = tree.query_overlap(query).map(|(i, _)| i.clone()).collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn empty_queries_overlap_nothing() {
+        let tree = IntervalTree::from_iter([(0..10, ())]);
+        assert_eq!(tree.query_overlap(5..5).count(), 0);
+        // spelled out, since clippy flags a literal `7..3`
+        let inverted = Range { start: 7, end: 3 };
+        assert_eq!(tree.query_overlap(inverted.clone()).count(), 0);
+        assert!(!tree.any_overlap(5..5));
+        assert!(!tree.any_overlap(inverted));
+        assert!(tree.any_overlap(5..6));
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_intervals_are_rejected() {
+        IntervalTree::new().insert(3..3, ());
+    }
+}
@@ -1,101 +1,529 @@
+mod avl;
+pub mod btree;
+mod build;
+pub mod codec;
+pub mod compare;
+pub mod cursor;
+pub mod eytzinger;
+pub mod fenwick;
+pub mod heap;
+pub mod interval;
+pub mod map;
+pub mod monoid;
+pub mod observe;
+mod order;
+pub mod ordered;
+pub mod persistent;
+pub mod radix;
+pub mod range;
+pub mod render;
+pub mod segment;
+#[cfg(feature = "serde")]
+mod serde_impl;
+pub mod set;
+mod shape;
+pub mod splay;
+pub mod treap;
+pub mod validate;
+
+pub use btree::BTree;
+pub use codec::{Compact, DecodeError};
+pub use compare::{ByKey, Compare, Natural, Reversed};
+pub use cursor::{Cursor, CursorMut};
+pub use eytzinger::EytzingerSet;
+pub use fenwick::FenwickTree;
+pub use heap::IndexedHeap;
+pub use interval::IntervalTree;
+pub use map::BstMap;
+pub use monoid::{Max, Min, Monoid, Sum};
+pub use observe::{Rotation, Trace, TreeObserver};
+pub use ordered::OrderedMap;
+pub use persistent::PersistentBst;
+pub use radix::RadixMap;
+pub use range::Range;
+pub use segment::SegmentTree;
+pub use splay::SplayTree;
+pub use treap::Treap;
+pub use validate::{InvariantViolation, Rule};
+
+use std::borrow::Borrow;
 use std::cmp::Ordering;
 
-#[derive(Clone, Hash, Default, Debug, Eq, Ord, PartialEq, PartialOrd)]
+use observe::SkipVisits;
+
+type Link<T> = Option<Box<Node<T>>>;
+
+#[derive(Debug)]
 struct Node<T> {
     value: T,
-    left: Option<Box<Node<T>>>,
-    right: Option<Box<Node<T>>>,
+    // how many equal values this node stands for. only
+    // a `DuplicatePolicy::Count` tree goes above one.
+    count: usize,
+    // number of values in the subtree rooted here,
+    // counting every copy.
+    size: usize,
+    left: Link<T>,
+    right: Link<T>,
+}
+
+/// What a tree does when it is given a value equal to
+/// one it already holds. Every policy keeps a single
+/// node per distinct value.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, Ord, PartialEq, PartialOrd)]
+pub enum DuplicatePolicy {
+    /// Keep the stored value and drop the new one, like a set.
+    Reject,
+    /// Count the copies on the stored node, like a multiset.
+    #[default]
+    Count,
+    /// Swap the stored value for the new one.
+    Replace,
 }
 
-#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
-pub struct Bst<T> {
-    root: Option<Box<Node<T>>>,
+/// A binary search tree ordered by the comparator `C`,
+/// which is `T`'s own `Ord` unless given.
+#[derive(Debug)]
+pub struct Bst<T, C = Natural> {
+    root: Link<T>,
+    policy: DuplicatePolicy,
+    cmp: C,
 }
 
 impl<T> Default for Bst<T> {
     fn default() -> Self {
-        Self { root: None }
+        Self::with_policy(DuplicatePolicy::default())
     }
 }
 
-impl<T> Node<T>
-where
-    T: Ord + Clone + std::fmt::Debug,
-{
+impl<T> Node<T> {
     fn leaf(value: T) -> Self {
+        Self::with_count(value, 1)
+    }
+
+    fn with_count(value: T, count: usize) -> Self {
         Self {
             value,
+            count,
+            size: count,
             left: None,
             right: None,
         }
     }
 
-    pub fn find(&self, predicate: T) -> Option<Box<Node<T>>> {
-        Self::find_node(Some(Box::new(self.to_owned())), predicate)
+    fn size(link: &Link<T>) -> usize {
+        link.as_ref().map_or(0, |node| node.size)
     }
 
-    fn find_node(node: Option<Box<Node<T>>>, predicate: T) -> Option<Box<Node<T>>> {
-        if let Some(node) = node {
-            return match predicate.cmp(&node.value) {
-                Ordering::Less => {
-                    println!("is less? {:?}", node.left);
-                    Self::find_node(node.left, predicate)
-                }
-                Ordering::Equal => {
-                    println!("is EQUAL? {:?}", node);
-                    Some(node)
-                }
-                Ordering::Greater => {
-                    println!("is greater? {:?}", node.right);
-                    Self::find_node(node.right, predicate)
-                }
-            };
+    fn fix_size(&mut self) {
+        self.size = self.count + Self::size(&self.left) + Self::size(&self.right);
+    }
+}
+
+impl<T> Bst<T> {
+    pub fn from_value(value: T) -> Self {
+        Self {
+            root: Some(Box::new(Node::leaf(value))),
+            ..Self::default()
         }
-        None
+    }
+
+    pub fn with_policy(policy: DuplicatePolicy) -> Self {
+        Self::with_policy_and_comparator(policy, Natural)
+    }
+}
+
+impl<T, F> Bst<T, ByKey<F>> {
+    /// Creates a tree ordered by the key `f` extracts
+    /// from each value.
+    pub fn by_key<K>(f: F) -> Self
+    where
+        F: Fn(&T) -> K,
+        K: Ord,
+    {
+        Self::with_comparator(ByKey(f))
     }
 }
 
-impl<T> Bst<T>
+impl<T, C> Bst<T, C>
 where
-    T: Ord + std::fmt::Debug + Clone,
+    C: Compare<T>,
 {
-    pub fn from_value(value: T) -> Self {
-        let root = Box::new(Node::leaf(value));
-        Self { root: Some(root) }
-    }
-
-    pub fn insert(&mut self, value: T) {
-        let new_node = Box::new(Node::leaf(value));
-        Self::push_node(new_node, &mut self.root);
-    }
-
-    fn push_node(mut new_node: Box<Node<T>>, curr_node: &mut Option<Box<Node<T>>>) {
-        if let Some(node) = curr_node {
-            match &new_node.value.cmp(&node.value) {
-                Ordering::Less | Ordering::Equal => {
-                    // swap curr_node.left = new_node.value
-                    if let Some(left) = node.left.clone() {
-                        if left.value < new_node.value {
-                            node.left = Some(new_node);
-                            new_node = left;
-                        }
-                    }
-                    Self::push_node(new_node, &mut node.left);
-                }
-                Ordering::Greater => {
-                    // swap curr_node.right = new_node.value
-                    if let Some(right) = node.right.clone() {
-                        if right.value > new_node.value {
-                            node.right = Some(new_node);
-                            new_node = right;
-                        }
-                    }
-                    Self::push_node(new_node, &mut node.right);
+    /// Inserts `value` as the tree's duplicate policy says.
+    /// Returns `false` if an equal value was already there.
+    pub fn insert(&mut self, value: T) -> bool {
+        self.insert_n(value, 1)
+    }
+
+    /// Inserts `n` copies of `value`. Only a `Count` tree
+    /// keeps them all, the other policies treat this like
+    /// a single `insert`.
+    pub fn insert_n(&mut self, value: T, n: usize) -> bool {
+        self.insert_n_observed(value, n, &mut ())
+    }
+
+    /// Inserts `value` like `insert`, telling `observer`
+    /// about every node on the way down and the new node,
+    /// if there is one.
+    pub fn insert_observed(&mut self, value: T, observer: &mut impl TreeObserver<T>) -> bool {
+        self.insert_n_observed(value, 1, observer)
+    }
+
+    fn insert_n_observed(
+        &mut self,
+        value: T,
+        n: usize,
+        observer: &mut impl TreeObserver<T>,
+    ) -> bool {
+        if n == 0 {
+            return false;
+        }
+        let count = match self.policy {
+            DuplicatePolicy::Count => n,
+            DuplicatePolicy::Reject => {
+                if self.lookup(&value, observer).is_some() {
+                    return false;
                 }
+                1
             }
+            DuplicatePolicy::Replace => match self.lookup_mut(&value, observer) {
+                Some(stored) => {
+                    *stored = value;
+                    return false;
+                }
+                None => 1,
+            },
+        };
+        let new_node = Box::new(Node::with_count(value, count));
+        if matches!(self.policy, DuplicatePolicy::Count) {
+            self.push_node(new_node, observer)
         } else {
-            *curr_node = Some(new_node);
+            // the lookup already showed the observer this path
+            self.push_node(new_node, &mut SkipVisits(observer))
+        }
+    }
+
+    fn push_node(&mut self, new_node: Box<Node<T>>, observer: &mut impl TreeObserver<T>) -> bool {
+        let mut link = &mut self.root;
+        while let Some(node) = link {
+            node.size += new_node.count;
+            let ordering = self.cmp.compare(&new_node.value, &node.value);
+            observer.on_visit(&node.value, ordering);
+            link = match ordering {
+                Ordering::Less => &mut node.left,
+                Ordering::Equal => {
+                    node.count += new_node.count;
+                    return false;
+                }
+                Ordering::Greater => &mut node.right,
+            };
+        }
+        observer.on_insert(&new_node.value);
+        *link = Some(new_node);
+        true
+    }
+}
+
+impl<T, C> Bst<T, C> {
+    pub fn with_comparator(cmp: C) -> Self {
+        Self::with_policy_and_comparator(DuplicatePolicy::default(), cmp)
+    }
+
+    pub fn with_policy_and_comparator(policy: DuplicatePolicy, cmp: C) -> Self {
+        Self {
+            root: None,
+            policy,
+            cmp,
+        }
+    }
+
+    pub fn comparator(&self) -> &C {
+        &self.cmp
+    }
+
+    pub fn policy(&self) -> DuplicatePolicy {
+        self.policy
+    }
+
+    /// Number of values in the tree, every copy included.
+    pub fn len(&self) -> usize {
+        Node::size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    fn pop_min(mut link: &mut Link<T>) -> Option<Box<Node<T>>> {
+        // every ancestor loses all the copies of the minimum,
+        // so look its count up before walking down.
+        let mut min = link.as_deref()?;
+        while let Some(left) = min.left.as_deref() {
+            min = left;
+        }
+        let count = min.count;
+
+        while link.as_ref()?.left.is_some() {
+            let node = link.as_mut().unwrap();
+            node.size -= count;
+            link = &mut node.left;
+        }
+        let mut min = link.take()?;
+        *link = min.right.take();
+        min.size = min.count;
+        Some(min)
+    }
+
+    fn pop_max(mut link: &mut Link<T>) -> Option<Box<Node<T>>> {
+        let mut max = link.as_deref()?;
+        while let Some(right) = max.right.as_deref() {
+            max = right;
+        }
+        let count = max.count;
+
+        while link.as_ref()?.right.is_some() {
+            let node = link.as_mut().unwrap();
+            node.size -= count;
+            link = &mut node.right;
         }
+        let mut max = link.take()?;
+        *link = max.left.take();
+        max.size = max.count;
+        Some(max)
+    }
+}
+
+impl<T, C> Bst<T, C> {
+    fn lookup<Q>(&self, value: &Q, observer: &mut impl TreeObserver<T>) -> Option<&Node<T>>
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        let mut link = &self.root;
+        while let Some(node) = link {
+            let ordering = self.cmp.compare(value, node.value.borrow());
+            observer.on_visit(&node.value, ordering);
+            link = match ordering {
+                Ordering::Less => &node.left,
+                Ordering::Equal => return Some(node),
+                Ordering::Greater => &node.right,
+            };
+        }
+        None
+    }
+
+    fn lookup_mut<Q>(&mut self, value: &Q, observer: &mut impl TreeObserver<T>) -> Option<&mut T>
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        let mut link = &mut self.root;
+        while let Some(node) = link {
+            let ordering = self.cmp.compare(value, node.value.borrow());
+            observer.on_visit(&node.value, ordering);
+            link = match ordering {
+                Ordering::Less => &mut node.left,
+                Ordering::Equal => return Some(&mut node.value),
+                Ordering::Greater => &mut node.right,
+            };
+        }
+        None
+    }
+
+    /// Looks `value` up like `get`, telling `observer`
+    /// about every node it is compared against.
+    pub fn find_observed<Q>(&self, value: &Q, observer: &mut impl TreeObserver<T>) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        self.lookup(value, observer).map(|node| &node.value)
+    }
+
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        self.lookup(value, &mut ()).is_some()
+    }
+
+    pub fn get<Q>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        self.lookup(value, &mut ()).map(|node| &node.value)
+    }
+
+    /// Returns how many copies of `value` the tree holds.
+    pub fn count<Q>(&self, value: &Q) -> usize
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        self.lookup(value, &mut ()).map_or(0, |node| node.count)
+    }
+
+    /// Removes every copy of `value` and returns the stored value.
+    pub fn remove<Q>(&mut self, value: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        let count = self.lookup(value, &mut ())?.count;
+        let link = self.descend(value, count);
+        Some(Self::unlink(link).value)
+    }
+
+    /// Removes a single copy of `value`, dropping its node
+    /// once no copies are left. Returns `false` if there
+    /// was nothing to remove.
+    pub fn remove_one<Q>(&mut self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        match self.lookup(value, &mut ()).map(|node| node.count) {
+            None => false,
+            Some(1) => self.remove(value).is_some(),
+            Some(_) => {
+                let node = self.descend(value, 1).as_mut().unwrap();
+                node.count -= 1;
+                node.size -= 1;
+                true
+            }
+        }
+    }
+
+    // walks down to the link holding `value`, taking `by`
+    // off the size of every ancestor on the way. the value
+    // must be in the tree.
+    fn descend<Q>(&mut self, value: &Q, by: usize) -> &mut Link<T>
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        let mut link = &mut self.root;
+        loop {
+            let ord = self
+                .cmp
+                .compare(value, link.as_ref().unwrap().value.borrow());
+            if ord == Ordering::Equal {
+                return link;
+            }
+            let node = link.as_mut().unwrap();
+            node.size -= by;
+            link = if ord == Ordering::Less {
+                &mut node.left
+            } else {
+                &mut node.right
+            };
+        }
+    }
+
+    // detaches the node held by `link` and splices its
+    // children back in, using the in-order successor
+    // when both of them are present.
+    fn unlink(link: &mut Link<T>) -> Box<Node<T>> {
+        let mut node = link.take().unwrap();
+        *link = match (node.left.take(), node.right.take()) {
+            (None, None) => None,
+            (Some(child), None) | (None, Some(child)) => Some(child),
+            (Some(left), Some(right)) => {
+                let mut right = Some(right);
+                let mut successor = Self::pop_min(&mut right).unwrap();
+                successor.size = node.size - node.count;
+                successor.left = Some(left);
+                successor.right = right;
+                Some(successor)
+            }
+        };
+        node.size = node.count;
+        node
+    }
+}
+
+// copies beyond the first are cloned out of the node,
+// since each node only stores one value.
+impl<T: Clone, C> IntoIterator for Bst<T, C> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        IntoIter {
+            nodes: Nodes::new(self.root.take()),
+            current: None,
+        }
+    }
+}
+
+impl<'a, T, C> IntoIterator for &'a Bst<T, C> {
+    type Item = &'a T;
+    type IntoIter = Range<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// takes a tree apart, handing out its nodes in order
+// with both children detached.
+struct Nodes<T> {
+    stack: Vec<Box<Node<T>>>,
+}
+
+impl<T> Nodes<T> {
+    fn new(root: Link<T>) -> Self {
+        let mut nodes = Self { stack: Vec::new() };
+        nodes.push_left(root);
+        nodes
+    }
+
+    fn push_left(&mut self, mut next: Link<T>) {
+        while let Some(mut node) = next {
+            next = node.left.take();
+            self.stack.push(node);
+        }
+    }
+}
+
+impl<T> Iterator for Nodes<T> {
+    type Item = Box<Node<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut node = self.stack.pop()?;
+        self.push_left(node.right.take());
+        Some(node)
+    }
+}
+
+pub struct IntoIter<T> {
+    nodes: Nodes<T>,
+    current: Option<Box<Node<T>>>,
+}
+
+impl<T: Clone> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current.is_none() {
+            self.current = self.nodes.next();
+        }
+        let node = self.current.as_mut()?;
+        if node.count > 1 {
+            node.count -= 1;
+            return Some(node.value.clone());
+        }
+        self.current.take().map(|node| node.value)
     }
 }
 
@@ -112,7 +540,8 @@ mod tests {
         tree.insert(6);
         tree.insert(1);
         tree.insert(2);
-        println!("{:#?}", tree);
+        assert!(tree.iter().eq(&[1, 2, 3, 4, 5, 6, 7]));
+        assert!(tree.validate().is_ok());
     }
 
     #[test]
@@ -120,10 +549,98 @@ mod tests {
         let mut tree = Bst::from_value(3);
         tree.insert(5);
         tree.insert(4);
-        let found = tree.root.unwrap().find(5);
-        // let found2 = tree.find(5);
+        assert_eq!(tree.find_observed(&5, &mut ()), Some(&5));
+        assert_eq!(tree.find_observed(&6, &mut ()), None);
+    }
+
+    #[test]
+    fn remove_keeps_order_and_len() {
+        let mut tree = Bst::from_value(50);
+        for value in [30, 70, 20, 40, 60, 80, 35] {
+            tree.insert(value);
+        }
+        assert_eq!(tree.len(), 8);
 
-        println!("-- found {:#?}", found);
+        assert_eq!(tree.remove(&30), Some(30));
+        assert_eq!(tree.remove(&50), Some(50));
+        assert_eq!(tree.remove(&55), None);
+        assert_eq!(tree.len(), 6);
+        assert!(!tree.contains(&30));
+        assert_eq!(
+            tree.iter().copied().collect::<Vec<_>>(),
+            [20, 35, 40, 60, 70, 80]
+        );
+
+        for value in [20, 35, 40, 60, 70, 80] {
+            tree.remove(&value);
+        }
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn duplicate_policies() {
+        let mut counted = Bst::from_value(5);
+        assert!(counted.insert(3));
+        assert!(!counted.insert(5));
+        assert!(counted.insert_n(7, 3));
+        assert_eq!(counted.count(&5), 2);
+        assert_eq!(counted.count(&7), 3);
+        assert_eq!(counted.count(&4), 0);
+        assert_eq!(counted.len(), 6);
+        assert_eq!(
+            counted.iter().copied().collect::<Vec<_>>(),
+            [3, 5, 5, 7, 7, 7]
+        );
+        assert_eq!(counted.select(4), Some(&7));
+        assert_eq!(counted.rank(&7), 3);
+
+        assert!(counted.remove_one(&7));
+        assert_eq!(counted.count(&7), 2);
+        assert_eq!(counted.remove(&7), Some(7));
+        assert!(!counted.remove_one(&7));
+        assert_eq!(counted.len(), 3);
+
+        let mut set = Bst::with_policy(DuplicatePolicy::Reject);
+        assert!(set.insert(1));
+        assert!(!set.insert(1));
+        assert!(!set.insert_n(1, 10));
+        assert_eq!(set.count(&1), 1);
+        assert_eq!(set.len(), 1);
+
+        // tags order by name only, so a newer tag with the
+        // same name replaces the stored one.
+        #[derive(Clone, Debug)]
+        struct Tag(&'static str, u32);
+        impl PartialEq for Tag {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+        impl Eq for Tag {}
+        impl PartialOrd for Tag {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+        impl Ord for Tag {
+            fn cmp(&self, other: &Self) -> Ordering {
+                self.0.cmp(other.0)
+            }
+        }
+
+        let mut latest = Bst::with_policy(DuplicatePolicy::Replace);
+        assert!(latest.insert(Tag("a", 1)));
+        assert!(!latest.insert(Tag("a", 2)));
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest.get(&Tag("a", 0)).map(|tag| tag.1), Some(2));
+    }
+
+    #[test]
+    fn into_iter_repeats_counted_values() {
+        let mut tree = Bst::from_value(String::from("b"));
+        tree.insert_n(String::from("a"), 2);
+        let values: Vec<String> = tree.into_iter().collect();
+        assert_eq!(values, ["a", "a", "b"]);
     }
 
     #[test]
@@ -0,0 +1,668 @@
+/*
+ *  A key-value map ordered by `K` only
+ */
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Index, RangeBounds};
+
+use crate::range::{after_start, before_end, Children, Span};
+use crate::shape::{clone_link, drop_link, ChildrenMut};
+use crate::validate::{check_subtrees, order, InvariantViolation, Rule};
+use crate::Natural;
+
+type Link<K, V> = Option<Box<Node<K, V>>>;
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+impl<K, V> Children for Node<K, V> {
+    fn left(&self) -> Option<&Self> {
+        self.left.as_deref()
+    }
+
+    fn right(&self) -> Option<&Self> {
+        self.right.as_deref()
+    }
+}
+
+impl<K, V> ChildrenMut for Node<K, V> {
+    fn left_mut(&mut self) -> &mut Option<Box<Self>> {
+        &mut self.left
+    }
+
+    fn right_mut(&mut self) -> &mut Option<Box<Self>> {
+        &mut self.right
+    }
+}
+
+impl<K, V> Node<K, V> {
+    fn leaf(key: K, value: V) -> Self {
+        Self {
+            key,
+            value,
+            left: None,
+            right: None,
+        }
+    }
+}
+
+pub struct BstMap<K, V> {
+    root: Link<K, V>,
+    len: usize,
+}
+
+impl<K, V> Default for BstMap<K, V> {
+    fn default() -> Self {
+        Self { root: None, len: 0 }
+    }
+}
+
+impl<K, V> BstMap<K, V> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            remaining: self.len,
+        };
+        iter.push_left(self.root.as_deref());
+        iter
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys(self.iter())
+    }
+
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values(self.iter())
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        let mut iter = ValuesMut {
+            stack: Vec::new(),
+            remaining: self.len,
+        };
+        iter.push_left(&mut self.root);
+        iter
+    }
+}
+
+impl<K, V> BstMap<K, V>
+where
+    K: Ord,
+{
+    /// Inserts `value` under `key`, returning the value it replaced.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.entry(key) {
+            Entry::Occupied(mut entry) => Some(entry.insert(value)),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                None
+            }
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Self::find_node(&self.root, key).map(|node| &node.value)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Self::find_link(&mut self.root, key)
+            .as_mut()
+            .map(|node| &mut node.value)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Self::find_node(&self.root, key).is_some()
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let link = Self::find_link(&mut self.root, key);
+        link.as_ref()?;
+        self.len -= 1;
+        Some(unlink(link).value)
+    }
+
+    /// Returns the entries whose keys fall inside `range`, in order.
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        Range {
+            span: Span::new(
+                self.root.as_deref(),
+                |node| after_start(&Natural, range.start_bound(), node.key.borrow()),
+                |node| before_end(&Natural, range.end_bound(), node.key.borrow()),
+            ),
+        }
+    }
+
+    /// Checks that the keys are in order and that the
+    /// length matches the entries.
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        let (_, actual) = check_subtrees(
+            self.root.as_deref(),
+            || (None, 0),
+            |node, (left, left_len), (right, right_len)| {
+                let bounds = order(|a: &K, b: &K| a < b, left, &node.key, right)?;
+                Ok((Some(bounds), left_len + 1 + right_len))
+            },
+        )?;
+        if actual != self.len {
+            return Err(InvariantViolation::at_root(Rule::Len {
+                cached: self.len,
+                actual,
+            }));
+        }
+        Ok(())
+    }
+
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        let Self { root, len } = self;
+        let link = Self::find_link(root, &key);
+        if link.is_some() {
+            Entry::Occupied(OccupiedEntry { link, len })
+        } else {
+            Entry::Vacant(VacantEntry { key, link, len })
+        }
+    }
+
+    fn find_node<'a, Q>(mut link: &'a Link<K, V>, key: &Q) -> Option<&'a Node<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        while let Some(node) = link {
+            link = match key.cmp(node.key.borrow()) {
+                Ordering::Less => &node.left,
+                Ordering::Equal => return Some(node),
+                Ordering::Greater => &node.right,
+            };
+        }
+        None
+    }
+
+    // returns the link that holds `key`, or the empty
+    // link where `key` would be attached.
+    fn find_link<'a, Q>(mut link: &'a mut Link<K, V>, key: &Q) -> &'a mut Link<K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        loop {
+            let ord = match link {
+                Some(node) => key.cmp(node.key.borrow()),
+                None => return link,
+            };
+            if ord == Ordering::Equal {
+                return link;
+            }
+            // the match above only peeked, so the borrow
+            // can move down the tree here.
+            let node = link.as_mut().unwrap();
+            link = if ord == Ordering::Less {
+                &mut node.left
+            } else {
+                &mut node.right
+            };
+        }
+    }
+}
+
+// detaches the node held by `link` and splices its
+// children back in, using the in-order successor
+// when both of them are present.
+fn unlink<K, V>(link: &mut Link<K, V>) -> Box<Node<K, V>> {
+    let mut node = link.take().unwrap();
+    *link = match (node.left.take(), node.right.take()) {
+        (None, None) => None,
+        (Some(child), None) | (None, Some(child)) => Some(child),
+        (Some(left), Some(right)) => {
+            let mut right = Some(right);
+            let mut successor = pop_min(&mut right).unwrap();
+            successor.left = Some(left);
+            successor.right = right;
+            Some(successor)
+        }
+    };
+    node
+}
+
+fn pop_min<K, V>(mut link: &mut Link<K, V>) -> Option<Box<Node<K, V>>> {
+    while link.as_ref()?.left.is_some() {
+        link = &mut link.as_mut().unwrap().left;
+    }
+    let mut min = link.take()?;
+    *link = min.right.take();
+    Some(min)
+}
+
+impl<K, V> Drop for BstMap<K, V> {
+    fn drop(&mut self) {
+        drop_link(self.root.take());
+    }
+}
+
+impl<K: Clone, V: Clone> Clone for BstMap<K, V> {
+    fn clone(&self) -> Self {
+        let root = clone_link(self.root.as_deref(), |node| {
+            Node::leaf(node.key.clone(), node.value.clone())
+        });
+        Self {
+            root,
+            len: self.len,
+        }
+    }
+}
+
+impl<K, Q, V> Index<&Q> for BstMap<K, V>
+where
+    K: Borrow<Q> + Ord,
+    Q: Ord + ?Sized,
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for BstMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for BstMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for BstMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a BstMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/*
+ *  Entry API
+ */
+
+pub enum Entry<'a, K, V> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+// `link` is always `Some` for an occupied entry.
+pub struct OccupiedEntry<'a, K, V> {
+    link: &'a mut Link<K, V>,
+    len: &'a mut usize,
+}
+
+// `link` is the empty slot where `key` belongs.
+pub struct VacantEntry<'a, K, V> {
+    key: K,
+    link: &'a mut Link<K, V>,
+    len: &'a mut usize,
+}
+
+impl<'a, K, V> Entry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.link.as_ref().unwrap().key
+    }
+
+    pub fn get(&self) -> &V {
+        &self.link.as_ref().unwrap().value
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.link.as_mut().unwrap().value
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.link.as_mut().unwrap().value
+    }
+
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        *self.len -= 1;
+        let node = unlink(self.link);
+        (node.key, node.value)
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    pub fn insert(self, value: V) -> &'a mut V {
+        *self.len += 1;
+        let node = self.link.insert(Box::new(Node::leaf(self.key, value)));
+        &mut node.value
+    }
+}
+
+/*
+ *  Iterators
+ */
+
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left(&mut self, mut next: Option<&'a Node<K, V>>) {
+        while let Some(node) = next {
+            self.stack.push(node);
+            next = node.left.as_deref();
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left(node.right.as_deref());
+        self.remaining -= 1;
+        Some((&node.key, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+pub struct Range<'a, K, V> {
+    span: Span<'a, Node<K, V>>,
+}
+
+impl<'a, K, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.span.next().map(|node| (&node.key, &node.value))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Range<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.span.next_back().map(|node| (&node.key, &node.value))
+    }
+}
+
+pub struct Keys<'a, K, V>(Iter<'a, K, V>);
+
+impl<'a, K, V> Iterator for Keys<'a, K, V> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, _)| key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+pub struct Values<'a, K, V>(Iter<'a, K, V>);
+
+impl<'a, K, V> Iterator for Values<'a, K, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(_, value)| value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+pub struct ValuesMut<'a, K, V> {
+    // each frame keeps the value that is yielded next
+    // and the right subtree that follows it.
+    stack: Vec<(&'a mut V, &'a mut Link<K, V>)>,
+    remaining: usize,
+}
+
+impl<'a, K, V> ValuesMut<'a, K, V> {
+    fn push_left(&mut self, mut next: &'a mut Link<K, V>) {
+        while let Some(node) = next {
+            let Node {
+                value, left, right, ..
+            } = &mut **node;
+            self.stack.push((value, right));
+            next = left;
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (value, right) = self.stack.pop()?;
+        self.push_left(right);
+        self.remaining -= 1;
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Bound;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = BstMap::new();
+        assert_eq!(map.insert(3, "c"), None);
+        assert_eq!(map.insert(1, "a"), None);
+        assert_eq!(map.insert(5, "e"), None);
+        assert_eq!(map.insert(4, "d"), None);
+        assert_eq!(map.insert(3, "C"), Some("c"));
+        assert_eq!(map.len(), 4);
+
+        assert_eq!(map.get(&3), Some(&"C"));
+        assert_eq!(map.get(&2), None);
+        assert_eq!(map[&4], "d");
+
+        *map.get_mut(&1).unwrap() = "A";
+        assert_eq!(map.get(&1), Some(&"A"));
+
+        // 3 is the root and has two children
+        assert_eq!(map.remove(&3), Some("C"));
+        assert_eq!(map.remove(&3), None);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.keys().collect::<Vec<_>>(), [&1, &4, &5]);
+        assert_eq!(map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn values_do_not_need_bounds() {
+        struct Opaque(u8);
+
+        let mut map = BstMap::new();
+        map.insert("b", Opaque(2));
+        map.insert("a", Opaque(1));
+
+        assert_eq!(map["a"].0, 1);
+        assert_eq!(map.values().map(|v| v.0).collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn entry() {
+        let mut counts: BstMap<&str, u32> = BstMap::new();
+        for word in ["b", "a", "b", "c", "b", "a"] {
+            counts.entry(word).and_modify(|n| *n += 1).or_insert(1);
+        }
+        assert_eq!(
+            counts.iter().collect::<Vec<_>>(),
+            [(&"a", &2), (&"b", &3), (&"c", &1)]
+        );
+
+        *counts.entry("d").or_insert_with(|| 10) += 1;
+        assert_eq!(counts["d"], 11);
+
+        match counts.entry("b") {
+            Entry::Occupied(entry) => assert_eq!(entry.remove(), 3),
+            Entry::Vacant(_) => panic!("b should be occupied"),
+        }
+        assert!(!counts.contains_key("b"));
+        assert_eq!(counts.len(), 3);
+    }
+
+    #[test]
+    fn values_mut() {
+        let mut map: BstMap<_, _> = [(2, 20), (1, 10), (3, 30)].into_iter().collect();
+        for value in map.values_mut() {
+            *value += 1;
+        }
+        assert_eq!(map.values().collect::<Vec<_>>(), [&11, &21, &31]);
+    }
+
+    #[test]
+    fn range() {
+        let map: BstMap<_, _> = (0..10).map(|k| (k, k * 10)).collect();
+        assert_eq!(
+            map.range(3..6).collect::<Vec<_>>(),
+            [(&3, &30), (&4, &40), (&5, &50)]
+        );
+        assert_eq!(
+            map.range(..=2).rev().map(|(k, _)| *k).collect::<Vec<_>>(),
+            [2, 1, 0]
+        );
+
+        let words: BstMap<String, ()> = ["apple", "banana", "cherry"]
+            .into_iter()
+            .map(|w| (w.to_string(), ()))
+            .collect();
+        let from_b: Vec<_> = words
+            .range::<str, _>((Bound::Included("b"), Bound::Unbounded))
+            .collect();
+        assert_eq!(from_b.len(), 2);
+    }
+
+    #[test]
+    fn clone_and_drop_a_long_chain() {
+        let mut map = BstMap::new();
+        let mut link = &mut map.root;
+        for key in 0..1_000_000 {
+            link = &mut link.insert(Box::new(Node::leaf(key, key))).right;
+        }
+        map.len = 1_000_000;
+        let copy = map.clone();
+        assert_eq!(copy.get(&999_999), Some(&999_999));
+        assert_eq!(copy.len(), map.len());
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_missing_key() {
+        let map: BstMap<i32, i32> = BstMap::new();
+        let _ = map[&1];
+    }
+}
@@ -0,0 +1,229 @@
+/*
+ *  Monoids
+ *
+ *  A segment tree only needs to know how to fold two
+ *  neighbouring aggregates into one, and what folding
+ *  nothing gives. Like the comparators, the operation is
+ *  a value of its own, so the same `i64`s can be summed
+ *  in one tree and searched for a minimum in another.
+ *
+ *  For updates to a whole range, the monoid also says
+ *  what an update does to the aggregate of `len` values,
+ *  and how two updates fold into one. A monoid without
+ *  range updates can use `Infallible` as its update and
+ *  `match *update {}` in both methods.
+ */
+
+use std::ops::Add;
+
+pub trait Monoid<T> {
+    /// What a range update carries.
+    type Update: Clone;
+
+    /// The aggregate of no values at all.
+    fn identity(&self) -> T;
+
+    /// The aggregate of `a`'s values followed by `b`'s.
+    fn combine(&self, a: &T, b: &T) -> T;
+
+    /// The aggregate of `len` values after `update` was
+    /// applied to each of them, given their aggregate before.
+    fn apply(&self, update: &Self::Update, aggregate: &T, len: usize) -> T;
+
+    /// One update with the effect of `first` and then `second`.
+    fn compose(&self, first: &Self::Update, second: &Self::Update) -> Self::Update;
+
+    /// The aggregate of `len` copies of `value`.
+    fn repeat(&self, value: &T, mut len: usize) -> T {
+        // by doubling, so a million copies take about
+        // forty combines.
+        let mut result = self.identity();
+        let mut power = self.combine(&result, value);
+        while len > 0 {
+            if len & 1 == 1 {
+                result = self.combine(&result, &power);
+            }
+            len >>= 1;
+            if len > 0 {
+                power = self.combine(&power, &power);
+            }
+        }
+        result
+    }
+}
+
+/// The update the built-in monoids take.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Update<T> {
+    /// Overwrite every value in the range.
+    Assign(T),
+    /// Add to every value in the range.
+    Add(T),
+}
+
+// an assignment wipes out whatever came before it, and
+// adding to one is the same as assigning the sum.
+fn compose<T: Clone + Add<Output = T>>(first: &Update<T>, second: &Update<T>) -> Update<T> {
+    match (first, second) {
+        (_, Update::Assign(value)) => Update::Assign(value.clone()),
+        (Update::Assign(value), Update::Add(delta)) => {
+            Update::Assign(value.clone() + delta.clone())
+        }
+        (Update::Add(a), Update::Add(b)) => Update::Add(a.clone() + b.clone()),
+    }
+}
+
+/// The values a type can't go below or above, which are
+/// what `Max` and `Min` fold nothing into.
+pub trait Bounded {
+    const MIN: Self;
+    const MAX: Self;
+}
+
+macro_rules! bounded {
+    ($($int:ty),*) => {
+        $(impl Bounded for $int {
+            const MIN: Self = <$int>::MIN;
+            const MAX: Self = <$int>::MAX;
+        })*
+    };
+}
+
+bounded!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+impl Bounded for f32 {
+    const MIN: Self = f32::NEG_INFINITY;
+    const MAX: Self = f32::INFINITY;
+}
+
+impl Bounded for f64 {
+    const MIN: Self = f64::NEG_INFINITY;
+    const MAX: Self = f64::INFINITY;
+}
+
+/// Adds values up. Nothing sums to `T::default()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sum;
+
+impl<T> Monoid<T> for Sum
+where
+    T: Clone + Default + Add<Output = T>,
+{
+    type Update = Update<T>;
+
+    fn identity(&self) -> T {
+        T::default()
+    }
+
+    fn combine(&self, a: &T, b: &T) -> T {
+        a.clone() + b.clone()
+    }
+
+    fn apply(&self, update: &Update<T>, aggregate: &T, len: usize) -> T {
+        match update {
+            Update::Assign(value) => self.repeat(value, len),
+            Update::Add(delta) => aggregate.clone() + self.repeat(delta, len),
+        }
+    }
+
+    fn compose(&self, first: &Update<T>, second: &Update<T>) -> Update<T> {
+        compose(first, second)
+    }
+}
+
+/// Keeps the smallest value. Nothing has `T::MAX` as its minimum.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Min;
+
+impl<T> Monoid<T> for Min
+where
+    T: Clone + PartialOrd + Bounded + Add<Output = T>,
+{
+    type Update = Update<T>;
+
+    fn identity(&self) -> T {
+        T::MAX
+    }
+
+    fn combine(&self, a: &T, b: &T) -> T {
+        if b < a {
+            b.clone()
+        } else {
+            a.clone()
+        }
+    }
+
+    fn apply(&self, update: &Update<T>, aggregate: &T, _len: usize) -> T {
+        // adding the same amount to everything keeps the
+        // smallest value the smallest.
+        match update {
+            Update::Assign(value) => value.clone(),
+            Update::Add(delta) => aggregate.clone() + delta.clone(),
+        }
+    }
+
+    fn compose(&self, first: &Update<T>, second: &Update<T>) -> Update<T> {
+        compose(first, second)
+    }
+}
+
+/// Keeps the largest value. Nothing has `T::MIN` as its maximum.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Max;
+
+impl<T> Monoid<T> for Max
+where
+    T: Clone + PartialOrd + Bounded + Add<Output = T>,
+{
+    type Update = Update<T>;
+
+    fn identity(&self) -> T {
+        T::MIN
+    }
+
+    fn combine(&self, a: &T, b: &T) -> T {
+        if b > a {
+            b.clone()
+        } else {
+            a.clone()
+        }
+    }
+
+    fn apply(&self, update: &Update<T>, aggregate: &T, _len: usize) -> T {
+        match update {
+            Update::Assign(value) => value.clone(),
+            Update::Add(delta) => aggregate.clone() + delta.clone(),
+        }
+    }
+
+    fn compose(&self, first: &Update<T>, second: &Update<T>) -> Update<T> {
+        compose(first, second)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeat_folds_copies() {
+        assert_eq!(Sum.repeat(&3i64, 0), 0);
+        assert_eq!(Sum.repeat(&3i64, 1_000_001), 3_000_003);
+        assert_eq!(Min.repeat(&3i64, 5), 3);
+        assert_eq!(Min.repeat(&3i64, 0), i64::MAX);
+    }
+
+    #[test]
+    fn updates_compose_in_order() {
+        let add = Update::Add(2);
+        let assign = Update::Assign(10);
+        assert_eq!(Monoid::<i32>::compose(&Sum, &add, &add), Update::Add(4));
+        assert_eq!(
+            Monoid::<i32>::compose(&Sum, &assign, &add),
+            Update::Assign(12)
+        );
+        assert_eq!(Monoid::<i32>::compose(&Sum, &add, &assign), assign);
+        assert_eq!(Sum.apply(&add, &5, 3), 11);
+        assert_eq!(Sum.apply(&assign, &5, 3), 30);
+    }
+}
@@ -0,0 +1,211 @@
+/*
+ *  Observers
+ *
+ *  Hooks into the steps a tree takes: every node a search
+ *  compares against, every rotation and every new node.
+ *  The plain operations pass `()`, which ignores them all
+ *  and compiles away, so nothing is printed or paid for
+ *  unless a caller asks for it through one of the
+ *  `_observed` methods.
+ *
+ *  `Trace` writes each step out as a line of text, which
+ *  is the view to reach for when a tree ends up in a shape
+ *  nobody expected.
+ */
+
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::io::{self, Write};
+
+/// Which way a subtree turned. A left rotation lifts the
+/// pivot's right child above it, a right one its left.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rotation {
+    Left,
+    Right,
+}
+
+/// Gets told about each step a tree takes. Every hook
+/// does nothing unless overridden.
+pub trait TreeObserver<T: ?Sized> {
+    /// A search compared its value against `value`, and
+    /// found it `ordering` to it.
+    fn on_visit(&mut self, value: &T, ordering: Ordering) {
+        let _ = (value, ordering);
+    }
+
+    /// The subtree under `pivot` turned, moving `pivot`
+    /// one level down.
+    fn on_rotate(&mut self, pivot: &T, rotation: Rotation) {
+        let _ = (pivot, rotation);
+    }
+
+    /// `value` was linked in as a new node.
+    fn on_insert(&mut self, value: &T) {
+        let _ = value;
+    }
+}
+
+impl<T: ?Sized> TreeObserver<T> for () {}
+
+impl<T: ?Sized, O: TreeObserver<T> + ?Sized> TreeObserver<T> for &mut O {
+    fn on_visit(&mut self, value: &T, ordering: Ordering) {
+        (**self).on_visit(value, ordering);
+    }
+
+    fn on_rotate(&mut self, pivot: &T, rotation: Rotation) {
+        (**self).on_rotate(pivot, rotation);
+    }
+
+    fn on_insert(&mut self, value: &T) {
+        (**self).on_insert(value);
+    }
+}
+
+// passes on everything but visits, for a second walk
+// down a path the observer has already been shown.
+pub(crate) struct SkipVisits<'a, O: ?Sized>(pub(crate) &'a mut O);
+
+impl<T: ?Sized, O: TreeObserver<T> + ?Sized> TreeObserver<T> for SkipVisits<'_, O> {
+    fn on_rotate(&mut self, pivot: &T, rotation: Rotation) {
+        self.0.on_rotate(pivot, rotation);
+    }
+
+    fn on_insert(&mut self, value: &T) {
+        self.0.on_insert(value);
+    }
+}
+
+/// Writes a line per step, to stderr unless given
+/// somewhere else. Failed writes are ignored, this is
+/// for debugging.
+pub struct Trace<W = io::Stderr> {
+    out: W,
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Self::to(io::stderr())
+    }
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: Write> Trace<W> {
+    pub fn to(out: W) -> Self {
+        Self { out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<T: Debug + ?Sized, W: Write> TreeObserver<T> for Trace<W> {
+    fn on_visit(&mut self, value: &T, ordering: Ordering) {
+        let side = match ordering {
+            Ordering::Less => "less than",
+            Ordering::Equal => "equal to",
+            Ordering::Greater => "greater than",
+        };
+        let _ = writeln!(self.out, "visit: {side} {value:?}");
+    }
+
+    fn on_rotate(&mut self, pivot: &T, rotation: Rotation) {
+        let _ = writeln!(self.out, "rotate {rotation:?} at {pivot:?}");
+    }
+
+    fn on_insert(&mut self, value: &T) {
+        let _ = writeln!(self.out, "insert: {value:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bst, DuplicatePolicy, IntervalTree};
+
+    fn traced(steps: impl FnOnce(&mut Trace<Vec<u8>>)) -> String {
+        let mut trace = Trace::to(Vec::new());
+        steps(&mut trace);
+        String::from_utf8(trace.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn traces_a_bst() {
+        let mut tree = Bst::with_policy(DuplicatePolicy::Reject);
+        for value in [3, 5, 4] {
+            tree.insert(value);
+        }
+        let lines = traced(|trace| {
+            assert_eq!(tree.find_observed(&4, trace), Some(&4));
+            assert!(tree.insert_observed(1, trace));
+            assert!(!tree.insert_observed(4, trace));
+        });
+        assert_eq!(
+            lines,
+            "visit: greater than 3\n\
+             visit: less than 5\n\
+             visit: equal to 4\n\
+             visit: less than 3\n\
+             insert: 1\n\
+             visit: greater than 3\n\
+             visit: less than 5\n\
+             visit: equal to 4\n"
+        );
+    }
+
+    #[test]
+    fn traces_rotations() {
+        let mut tree = IntervalTree::new();
+        let lines = traced(|trace| {
+            for start in 0..3 {
+                tree.insert_observed(start..start + 1, (), trace);
+            }
+        });
+        assert_eq!(
+            lines,
+            "insert: 0..1\n\
+             visit: greater than 0..1\n\
+             insert: 1..2\n\
+             visit: greater than 0..1\n\
+             visit: greater than 1..2\n\
+             insert: 2..3\n\
+             rotate Left at 0..1\n"
+        );
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn counts_steps() {
+        #[derive(Default)]
+        struct Steps {
+            visits: usize,
+            inserts: usize,
+        }
+        impl TreeObserver<u32> for Steps {
+            fn on_visit(&mut self, _: &u32, _: Ordering) {
+                self.visits += 1;
+            }
+            fn on_insert(&mut self, _: &u32) {
+                self.inserts += 1;
+            }
+        }
+
+        // a sorted run makes a path, so the k-th insert
+        // passes every node before it.
+        let mut tree = Bst::default();
+        let mut steps = Steps::default();
+        for value in 0..10 {
+            tree.insert_observed(value, &mut steps);
+        }
+        assert_eq!(steps.visits, 45);
+        assert_eq!(steps.inserts, 10);
+        assert_eq!(tree.find_observed(&20, &mut steps), None);
+        assert_eq!(steps.visits, 55);
+    }
+}
@@ -0,0 +1,116 @@
+/*
+ *  Order statistics
+ *
+ *  Every node knows the size of its subtree, so the
+ *  position of a value can be found on the way down
+ *  without visiting the nodes to its left.
+ */
+
+use std::borrow::Borrow;
+
+use crate::{Bst, Compare, Node};
+
+impl<T, C> Bst<T, C> {
+    /// Returns the `k`-th smallest value, counting from zero.
+    pub fn select(&self, mut k: usize) -> Option<&T> {
+        let mut link = &self.root;
+        while let Some(node) = link {
+            let left = Node::size(&node.left);
+            link = if k < left {
+                &node.left
+            } else if k < left + node.count {
+                return Some(&node.value);
+            } else {
+                k -= left + node.count;
+                &node.right
+            };
+        }
+        None
+    }
+
+    /// Returns the lower median, the value at `(len - 1) / 2`.
+    pub fn median(&self) -> Option<&T> {
+        self.select(self.len().checked_sub(1)? / 2)
+    }
+
+    // counts the values for which `pred` holds, given that
+    // it holds for every value up to some point and for
+    // none after it.
+    pub(crate) fn count_prefix(&self, pred: impl Fn(&T) -> bool) -> usize {
+        let mut count = 0;
+        let mut link = &self.root;
+        while let Some(node) = link {
+            link = if pred(&node.value) {
+                count += Node::size(&node.left) + node.count;
+                &node.right
+            } else {
+                &node.left
+            };
+        }
+        count
+    }
+}
+
+impl<T, C> Bst<T, C> {
+    /// Returns how many values are strictly less than `value`.
+    pub fn rank<Q>(&self, value: &Q) -> usize
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        self.count_prefix(|v| self.cmp.compare(v.borrow(), value).is_lt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Bst;
+
+    fn tree() -> Bst<i32> {
+        let mut tree = Bst::from_value(50);
+        for value in [30, 70, 20, 40, 60, 80, 35, 45, 65] {
+            tree.insert(value);
+        }
+        tree
+    }
+
+    #[test]
+    fn select_and_rank() {
+        let tree = tree();
+        let sorted = [20, 30, 35, 40, 45, 50, 60, 65, 70, 80];
+        assert_eq!(tree.len(), sorted.len());
+
+        for (k, value) in sorted.iter().enumerate() {
+            assert_eq!(tree.select(k), Some(value));
+            assert_eq!(tree.rank(value), k);
+        }
+        assert_eq!(tree.select(10), None);
+        assert_eq!(tree.rank(&0), 0);
+        assert_eq!(tree.rank(&42), 4);
+        assert_eq!(tree.rank(&100), 10);
+    }
+
+    #[test]
+    fn sizes_follow_removals() {
+        let mut tree = tree();
+        tree.remove(&30);
+        tree.remove(&70);
+        assert_eq!(tree.len(), 8);
+        assert_eq!(tree.select(1), Some(&35));
+        assert_eq!(tree.rank(&80), 7);
+        assert_eq!(tree.count_range(35..=65), 6);
+    }
+
+    #[test]
+    fn median() {
+        let mut tree = Bst::default();
+        assert_eq!(tree.median(), None);
+        tree.insert(3);
+        assert_eq!(tree.median(), Some(&3));
+        tree.insert(1);
+        assert_eq!(tree.median(), Some(&1));
+        tree.insert(2);
+        assert_eq!(tree.median(), Some(&2));
+    }
+}
@@ -0,0 +1,190 @@
+/*
+ *  Ordered maps
+ *
+ *  The operations every map in the crate shares, so the
+ *  same workload can be replayed against each of them
+ *  (and against `BTreeMap`) to see which suits it.
+ */
+
+use std::collections::BTreeMap;
+
+use crate::{BTree, BstMap, RadixMap, SplayTree, Treap};
+
+pub trait OrderedMap<K, V> {
+    /// Inserts `value` under `key`, returning the value it replaced.
+    fn insert(&mut self, key: K, value: V) -> Option<V>;
+
+    // takes `&mut self` so a splay tree can move the key
+    // it finds to the root.
+    fn get(&mut self, key: &K) -> Option<&V>;
+
+    fn remove(&mut self, key: &K) -> Option<V>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Ord, V> OrderedMap<K, V> for BTreeMap<K, V> {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        BTreeMap::insert(self, key, value)
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        BTreeMap::get(self, key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        BTreeMap::remove(self, key)
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+}
+
+impl<K: Ord, V> OrderedMap<K, V> for BstMap<K, V> {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        BstMap::insert(self, key, value)
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        BstMap::get(self, key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        BstMap::remove(self, key)
+    }
+
+    fn len(&self) -> usize {
+        BstMap::len(self)
+    }
+}
+
+impl<K: Ord, V, const B: usize> OrderedMap<K, V> for BTree<K, V, B> {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        BTree::insert(self, key, value)
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        BTree::get(self, key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        BTree::remove(self, key)
+    }
+
+    fn len(&self) -> usize {
+        BTree::len(self)
+    }
+}
+
+impl<K: Ord, V> OrderedMap<K, V> for SplayTree<K, V> {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        SplayTree::insert(self, key, value)
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        SplayTree::get(self, key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        SplayTree::remove(self, key)
+    }
+
+    fn len(&self) -> usize {
+        SplayTree::len(self)
+    }
+}
+
+impl<K: Ord, V> OrderedMap<K, V> for Treap<K, V> {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        Treap::insert(self, key, value)
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        Treap::get(self, key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        Treap::remove(self, key)
+    }
+
+    fn len(&self) -> usize {
+        Treap::len(self)
+    }
+}
+
+// keyed by owned byte strings, so it fits next to the
+// others. its own methods take any `AsRef<[u8]>`.
+impl<V> OrderedMap<Vec<u8>, V> for RadixMap<V> {
+    fn insert(&mut self, key: Vec<u8>, value: V) -> Option<V> {
+        RadixMap::insert(self, &key, value)
+    }
+
+    fn get(&mut self, key: &Vec<u8>) -> Option<&V> {
+        RadixMap::get(self, key)
+    }
+
+    fn remove(&mut self, key: &Vec<u8>) -> Option<V> {
+        RadixMap::remove(self, key)
+    }
+
+    fn len(&self) -> usize {
+        RadixMap::len(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a skewed workload: most lookups hit a few hot keys,
+    // each turned into the map's key type by `key`.
+    fn replay_with<K>(
+        map: &mut dyn OrderedMap<K, u32>,
+        key: impl Fn(u32) -> K,
+    ) -> Vec<Option<u32>> {
+        let mut seed = 11u32;
+        let mut random = move |below: u32| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed % below
+        };
+        let mut seen = Vec::new();
+        for step in 0..5000 {
+            let key = key(if random(10) < 8 {
+                random(8)
+            } else {
+                random(2000)
+            });
+            seen.push(match random(4) {
+                0 => map.insert(key, step),
+                1 => map.remove(&key),
+                _ => map.get(&key).copied(),
+            });
+        }
+        seen.push(Some(map.len() as u32));
+        seen
+    }
+
+    fn replay(map: &mut dyn OrderedMap<u32, u32>) -> Vec<Option<u32>> {
+        replay_with(map, |key| key)
+    }
+
+    #[test]
+    fn every_map_agrees_with_btreemap() {
+        let expected = replay(&mut BTreeMap::new());
+        assert_eq!(replay(&mut BstMap::new()), expected);
+        assert_eq!(replay(&mut BTree::<_, _, 11>::new()), expected);
+        assert_eq!(replay(&mut BTree::<_, _, 3>::new()), expected);
+        assert_eq!(replay(&mut SplayTree::new()), expected);
+        assert_eq!(replay(&mut Treap::with_seed(5)), expected);
+        // decimal keys, so "1" is a prefix of "12" and "123"
+        let decimal = |key: u32| key.to_string().into_bytes();
+        assert_eq!(replay_with(&mut RadixMap::new(), decimal), expected);
+    }
+}
@@ -0,0 +1,518 @@
+/*
+ *  A persistent search tree
+ *
+ *  `insert` and `remove` never touch the tree they are
+ *  called on. They copy the nodes on the path to the
+ *  change and point the copies at the untouched
+ *  subtrees of the old tree, so a new version costs
+ *  O(log n) nodes and every older version stays valid.
+ *  Cloning a tree only bumps a reference count, which
+ *  makes snapshots free.
+ *
+ *  The tree is weight-balanced: the subtree sizes used
+ *  for `select` and `rank` also decide when to rotate,
+ *  which keeps the depth logarithmic. Nodes are shared
+ *  through `Arc`, so a snapshot can be handed to
+ *  readers on other threads while the writer moves on.
+ */
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+use crate::range::{after_start, before_end, Children, Span};
+use crate::validate::{check_subtrees, order, InvariantViolation, Rule};
+use crate::Natural;
+
+type Link<T> = Option<Arc<Node<T>>>;
+
+struct Node<T> {
+    value: T,
+    size: usize,
+    left: Link<T>,
+    right: Link<T>,
+}
+
+impl<T> Children for Node<T> {
+    fn left(&self) -> Option<&Self> {
+        self.left.as_deref()
+    }
+
+    fn right(&self) -> Option<&Self> {
+        self.right.as_deref()
+    }
+}
+
+// a subtree may hold at most DELTA times the weight of
+// its sibling. on a rotation, an inner grandchild
+// lighter than GAMMA times the outer one moves up with
+// a single rotation, otherwise a double one.
+const DELTA: usize = 3;
+const GAMMA: usize = 2;
+
+fn size<T>(link: &Link<T>) -> usize {
+    link.as_ref().map_or(0, |node| node.size)
+}
+
+fn weight<T>(link: &Link<T>) -> usize {
+    size(link) + 1
+}
+
+fn node<T>(value: T, left: Link<T>, right: Link<T>) -> Arc<Node<T>> {
+    Arc::new(Node {
+        size: size(&left) + size(&right) + 1,
+        value,
+        left,
+        right,
+    })
+}
+
+// rebuilds a node whose children were balanced on
+// their own and whose weights are off by at most one
+// insert or removal.
+fn balance<T: Clone>(value: T, left: Link<T>, right: Link<T>) -> Arc<Node<T>> {
+    let (wl, wr) = (weight(&left), weight(&right));
+    if wl + wr <= 3 {
+        node(value, left, right)
+    } else if wr > DELTA * wl {
+        let r = right.expect("the heavy side is never empty");
+        if weight(&r.left) < GAMMA * weight(&r.right) {
+            let left = node(value, left, r.left.clone());
+            node(r.value.clone(), Some(left), r.right.clone())
+        } else {
+            let rl = r
+                .left
+                .as_ref()
+                .expect("a double rotation needs an inner child");
+            let left = node(value, left, rl.left.clone());
+            let right = node(r.value.clone(), rl.right.clone(), r.right.clone());
+            node(rl.value.clone(), Some(left), Some(right))
+        }
+    } else if wl > DELTA * wr {
+        let l = left.expect("the heavy side is never empty");
+        if weight(&l.right) < GAMMA * weight(&l.left) {
+            let right = node(value, l.right.clone(), right);
+            node(l.value.clone(), l.left.clone(), Some(right))
+        } else {
+            let lr = l
+                .right
+                .as_ref()
+                .expect("a double rotation needs an inner child");
+            let left = node(l.value.clone(), l.left.clone(), lr.left.clone());
+            let right = node(value, lr.right.clone(), right);
+            node(lr.value.clone(), Some(left), Some(right))
+        }
+    } else {
+        node(value, left, right)
+    }
+}
+
+// returns `None` when the value is already there, so the
+// caller can keep sharing the old tree.
+fn insert<T: Ord + Clone>(link: &Link<T>, value: T) -> Option<Arc<Node<T>>> {
+    let Some(n) = link else {
+        return Some(node(value, None, None));
+    };
+    Some(match value.cmp(&n.value) {
+        Ordering::Less => {
+            let left = insert(&n.left, value)?;
+            balance(n.value.clone(), Some(left), n.right.clone())
+        }
+        Ordering::Greater => {
+            let right = insert(&n.right, value)?;
+            balance(n.value.clone(), n.left.clone(), Some(right))
+        }
+        Ordering::Equal => return None,
+    })
+}
+
+// returns `None` when the value isn't there.
+fn remove<T, Q>(link: &Link<T>, value: &Q) -> Option<Link<T>>
+where
+    T: Ord + Clone + Borrow<Q>,
+    Q: Ord + ?Sized,
+{
+    let n = link.as_ref()?;
+    Some(Some(match value.cmp(n.value.borrow()) {
+        Ordering::Less => {
+            let left = remove(&n.left, value)?;
+            balance(n.value.clone(), left, n.right.clone())
+        }
+        Ordering::Greater => {
+            let right = remove(&n.right, value)?;
+            balance(n.value.clone(), n.left.clone(), right)
+        }
+        Ordering::Equal => return Some(glue(&n.left, &n.right)),
+    }))
+}
+
+// joins the two children of a removed node, taking the
+// new root from the heavier side.
+fn glue<T: Clone>(left: &Link<T>, right: &Link<T>) -> Link<T> {
+    match (left, right) {
+        (None, _) => right.clone(),
+        (_, None) => left.clone(),
+        (Some(l), Some(r)) if l.size > r.size => {
+            let (max, left) = pop_max(l);
+            Some(balance(max, left, right.clone()))
+        }
+        (Some(_), Some(r)) => {
+            let (min, right) = pop_min(r);
+            Some(balance(min, left.clone(), right))
+        }
+    }
+}
+
+fn pop_min<T: Clone>(n: &Node<T>) -> (T, Link<T>) {
+    match &n.left {
+        None => (n.value.clone(), n.right.clone()),
+        Some(left) => {
+            let (min, left) = pop_min(left);
+            (min, Some(balance(n.value.clone(), left, n.right.clone())))
+        }
+    }
+}
+
+fn pop_max<T: Clone>(n: &Node<T>) -> (T, Link<T>) {
+    match &n.right {
+        None => (n.value.clone(), n.left.clone()),
+        Some(right) => {
+            let (max, right) = pop_max(right);
+            (max, Some(balance(n.value.clone(), n.left.clone(), right)))
+        }
+    }
+}
+
+// builds a perfectly balanced tree out of the next `len`
+// values, which must be sorted and distinct.
+fn build<T>(values: &mut impl Iterator<Item = T>, len: usize) -> Link<T> {
+    if len == 0 {
+        return None;
+    }
+    let left = build(values, len / 2);
+    let value = values.next()?;
+    let right = build(values, len - len / 2 - 1);
+    Some(node(value, left, right))
+}
+
+pub struct PersistentBst<T> {
+    root: Link<T>,
+}
+
+impl<T> PersistentBst<T> {
+    pub fn new() -> Self {
+        Self { root: None }
+    }
+
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn iter(&self) -> Range<'_, T> {
+        Range {
+            span: Span::new(self.root.as_deref(), |_| true, |_| true),
+        }
+    }
+
+    /// Returns the `k`-th smallest value, counting from zero.
+    pub fn select(&self, mut k: usize) -> Option<&T> {
+        let mut link = &self.root;
+        while let Some(node) = link {
+            let left = size(&node.left);
+            link = match k.cmp(&left) {
+                Ordering::Less => &node.left,
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Greater => {
+                    k -= left + 1;
+                    &node.right
+                }
+            };
+        }
+        None
+    }
+
+    /// Whether both trees are the same version, or one is
+    /// an unchanged copy of the other.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+}
+
+impl<T> PersistentBst<T>
+where
+    T: Ord,
+{
+    pub fn get<Q>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut link = &self.root;
+        while let Some(node) = link {
+            link = match value.cmp(node.value.borrow()) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => &node.right,
+                Ordering::Equal => return Some(&node.value),
+            };
+        }
+        None
+    }
+
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.get(value).is_some()
+    }
+
+    /// Returns how many values are strictly less than `value`.
+    pub fn rank<Q>(&self, value: &Q) -> usize
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut rank = 0;
+        let mut link = &self.root;
+        while let Some(node) = link {
+            link = if node.value.borrow() < value {
+                rank += size(&node.left) + 1;
+                &node.right
+            } else {
+                &node.left
+            };
+        }
+        rank
+    }
+
+    /// Checks that the values are in order, that every
+    /// cached size is right, and that no subtree outweighs
+    /// its sibling by more than the balance allows.
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        check_subtrees(
+            self.root.as_deref(),
+            || (None, 0),
+            |node, (left, left_size), (right, right_size)| {
+                let bounds = order(|a: &T, b: &T| a < b, left, &node.value, right)?;
+                let actual = left_size + 1 + right_size;
+                if node.size != actual {
+                    return Err(Rule::Size {
+                        cached: node.size,
+                        actual,
+                    });
+                }
+                let (wl, wr) = (left_size + 1, right_size + 1);
+                if wl > DELTA * wr || wr > DELTA * wl {
+                    return Err(Rule::Balance {
+                        left: left_size,
+                        right: right_size,
+                    });
+                }
+                Ok((Some(bounds), actual))
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Returns the values that fall inside `range`, in order.
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        Range {
+            span: Span::new(
+                self.root.as_deref(),
+                |node| after_start(&Natural, range.start_bound(), node.value.borrow()),
+                |node| before_end(&Natural, range.end_bound(), node.value.borrow()),
+            ),
+        }
+    }
+}
+
+impl<T> PersistentBst<T>
+where
+    T: Ord + Clone,
+{
+    /// Returns a new version that also holds `value`. If an
+    /// equal value is already there, the new version is
+    /// this one, shared as is.
+    pub fn insert(&self, value: T) -> Self {
+        match insert(&self.root, value) {
+            Some(root) => Self { root: Some(root) },
+            None => self.clone(),
+        }
+    }
+
+    /// Returns a new version without `value`.
+    pub fn remove<Q>(&self, value: &Q) -> Self
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match remove(&self.root, value) {
+            Some(root) => Self { root },
+            None => self.clone(),
+        }
+    }
+}
+
+impl<T> Clone for PersistentBst<T> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+        }
+    }
+}
+
+impl<T> Default for PersistentBst<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PersistentBst<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<T: Ord> FromIterator<T> for PersistentBst<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut values: Vec<T> = iter.into_iter().collect();
+        values.sort();
+        values.dedup();
+        let len = values.len();
+        Self {
+            root: build(&mut values.into_iter(), len),
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a PersistentBst<T> {
+    type Item = &'a T;
+    type IntoIter = Range<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Range<'a, T> {
+    span: Span<'a, Node<T>>,
+}
+
+impl<'a, T> Iterator for Range<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.span.next().map(|node| &node.value)
+    }
+}
+
+impl<T> DoubleEndedIterator for Range<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.span.next_back().map(|node| &node.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // checks sizes and the weight bound, returning the size.
+    fn check<T>(link: &Link<T>) -> usize {
+        let Some(node) = link else {
+            return 0;
+        };
+        let (left, right) = (check(&node.left), check(&node.right));
+        assert_eq!(node.size, left + right + 1);
+        if left + right >= 2 {
+            assert!(right < DELTA * (left + 1) && left < DELTA * (right + 1));
+        }
+        node.size
+    }
+
+    fn values(tree: &PersistentBst<i32>) -> Vec<i32> {
+        tree.iter().copied().collect()
+    }
+
+    #[test]
+    fn old_versions_stay_intact() {
+        let empty = PersistentBst::new();
+        let one = empty.insert(5);
+        let two = one.insert(3);
+        let three = two.insert(8);
+        let back = three.remove(&5);
+
+        assert!(empty.is_empty());
+        assert_eq!(values(&one), [5]);
+        assert_eq!(values(&two), [3, 5]);
+        assert_eq!(values(&three), [3, 5, 8]);
+        assert_eq!(values(&back), [3, 8]);
+        assert!(three.contains(&5) && !back.contains(&5));
+
+        // nothing changed, so nothing is copied
+        assert!(three.insert(8).ptr_eq(&three));
+        assert!(three.remove(&4).ptr_eq(&three));
+    }
+
+    #[test]
+    fn updates_share_untouched_subtrees() {
+        let tree: PersistentBst<i32> = (0..1000).collect();
+        let root = tree.root.as_ref().unwrap();
+        let next = tree.insert(10_000);
+        // the insert went right, so the left half is shared
+        let new_root = next.root.as_ref().unwrap();
+        assert!(Arc::ptr_eq(
+            root.left.as_ref().unwrap(),
+            new_root.left.as_ref().unwrap()
+        ));
+        assert_eq!(tree.len(), 1000);
+        assert_eq!(next.len(), 1001);
+    }
+
+    #[test]
+    fn stays_balanced() {
+        let mut tree = PersistentBst::new();
+        for value in 0..2000 {
+            tree = tree.insert(value);
+            check(&tree.root);
+        }
+        for value in (0..2000).step_by(3) {
+            tree = tree.remove(&value);
+            check(&tree.root);
+        }
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.len(), 2000 - 667);
+        assert_eq!(tree.select(0), Some(&1));
+        assert_eq!(tree.rank(&5), 3);
+        assert_eq!(
+            tree.range(10..20).copied().collect::<Vec<_>>(),
+            [10, 11, 13, 14, 16, 17, 19]
+        );
+        assert_eq!(tree.iter().next_back(), Some(&1999));
+    }
+
+    #[test]
+    fn snapshots_cross_threads() {
+        let tree: PersistentBst<i32> = (0..100).collect();
+        let snapshot = tree.clone();
+        let reader = std::thread::spawn(move || snapshot.iter().sum::<i32>());
+        let tree = tree.remove(&99);
+        assert_eq!(reader.join().unwrap(), 4950);
+        assert_eq!(tree.len(), 99);
+        assert_eq!(tree.remove(&1).range(..3).count(), 2);
+        assert_eq!(format!("{:?}", PersistentBst::from_iter([2, 1])), "{1, 2}");
+    }
+}
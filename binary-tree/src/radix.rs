@@ -0,0 +1,582 @@
+/*
+ *  Radix trie
+ *
+ *  A map from byte strings where each edge is labelled
+ *  with a run of bytes rather than one, and a key is the
+ *  labels on the path down to its node. A node with no
+ *  value and a single child would only be a bend in one
+ *  long edge, so the trie never keeps one: inserting a
+ *  key that ends halfway along an edge splits it, and
+ *  removing a key merges the edges around it again.
+ *
+ *  Children are kept sorted by the first byte of their
+ *  labels, which all differ, so walking the trie depth
+ *  first, a node before its children, visits the keys in
+ *  sorted order, and every key with a given prefix sits
+ *  in one subtree.
+ *
+ *  Lookups cost O(key length) whatever the size of the
+ *  map, and a node can be as deep as its key is long, so
+ *  every walk keeps its own stack.
+ */
+
+use std::fmt;
+
+use crate::validate::{InvariantViolation, Rule};
+
+struct Node<V> {
+    // the bytes this node adds to its parent's key. only
+    // the root's is empty.
+    label: Vec<u8>,
+    value: Option<V>,
+    children: Vec<Node<V>>,
+}
+
+impl<V> Node<V> {
+    fn new(label: Vec<u8>, value: Option<V>) -> Self {
+        Self {
+            label,
+            value,
+            children: Vec::new(),
+        }
+    }
+
+    fn child(&self, first: u8) -> Result<usize, usize> {
+        self.children
+            .binary_search_by_key(&first, |child| child.label[0])
+    }
+
+    // cuts the label after `at` bytes, moving the rest of
+    // it and everything below into a new only child.
+    fn split(&mut self, at: usize) {
+        let rest = Node {
+            label: self.label.split_off(at),
+            value: self.value.take(),
+            children: std::mem::take(&mut self.children),
+        };
+        self.children.push(rest);
+    }
+
+    // the other way round: folds a lone child, and the
+    // edge to it, into this node.
+    fn merge_child(&mut self) {
+        let child = self.children.pop().unwrap();
+        self.label.extend(child.label);
+        self.value = child.value;
+        self.children = child.children;
+    }
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+pub struct RadixMap<V> {
+    root: Node<V>,
+    len: usize,
+}
+
+impl<V> Default for RadixMap<V> {
+    fn default() -> Self {
+        Self {
+            root: Node::new(Vec::new(), None),
+            len: 0,
+        }
+    }
+}
+
+// the derived drop would recurse once per level.
+impl<V> Drop for RadixMap<V> {
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(&mut self.root.children);
+        while let Some(mut node) = stack.pop() {
+            stack.append(&mut node.children);
+        }
+    }
+}
+
+impl<V> RadixMap<V> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Inserts `value` under `key`, returning the value it replaced.
+    pub fn insert<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K, value: V) -> Option<V> {
+        let mut key = key.as_ref();
+        let mut node = &mut self.root;
+        while let Some(&first) = key.first() {
+            let index = match node.child(first) {
+                Ok(index) => index,
+                Err(index) => {
+                    let leaf = Node::new(key.to_vec(), Some(value));
+                    node.children.insert(index, leaf);
+                    self.len += 1;
+                    return None;
+                }
+            };
+            let child = &mut node.children[index];
+            let common = common_prefix(&child.label, key);
+            if common < child.label.len() {
+                child.split(common);
+            }
+            key = &key[common..];
+            node = child;
+        }
+        let old = node.value.replace(value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    // the node `key` ends at, if it ends at one rather than
+    // halfway along an edge or off the trie.
+    fn find(&self, key: &[u8]) -> Option<&Node<V>> {
+        let mut key = key;
+        let mut node = &self.root;
+        while let Some(&first) = key.first() {
+            node = &node.children[node.child(first).ok()?];
+            key = key.strip_prefix(node.label.as_slice())?;
+        }
+        Some(node)
+    }
+
+    pub fn get<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> Option<&V> {
+        self.find(key.as_ref())?.value.as_ref()
+    }
+
+    pub fn get_mut<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K) -> Option<&mut V> {
+        let mut key = key.as_ref();
+        let mut node = &mut self.root;
+        while let Some(&first) = key.first() {
+            let index = node.child(first).ok()?;
+            node = &mut node.children[index];
+            key = key.strip_prefix(node.label.as_slice())?;
+        }
+        node.value.as_mut()
+    }
+
+    pub fn contains_key<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn remove<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K) -> Option<V> {
+        // the child taken at each step, so the parent can be
+        // found again once the value is out.
+        let mut path = Vec::new();
+        let mut key = key.as_ref();
+        let mut node = &mut self.root;
+        while let Some(&first) = key.first() {
+            let index = node.child(first).ok()?;
+            node = &mut node.children[index];
+            key = key.strip_prefix(node.label.as_slice())?;
+            path.push(index);
+        }
+        let value = node.value.take()?;
+        self.len -= 1;
+
+        let Some((&last, above)) = path.split_last() else {
+            return Some(value);
+        };
+        let mut parent = &mut self.root;
+        for &index in above {
+            parent = &mut parent.children[index];
+        }
+        // the emptied node goes if it has no children, and
+        // merges with its child if it has one.
+        let node = &mut parent.children[last];
+        match node.children.len() {
+            0 => {
+                parent.children.remove(last);
+            }
+            1 => node.merge_child(),
+            _ => {}
+        }
+        // losing a child can leave the parent a bend too.
+        let is_root = above.is_empty();
+        if !is_root && parent.value.is_none() && parent.children.len() == 1 {
+            parent.merge_child();
+        }
+        Some(value)
+    }
+
+    /// The value under the longest key that `key` starts
+    /// with, and that key.
+    pub fn longest_prefix_match<'k, K: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &'k K,
+    ) -> Option<(&'k [u8], &V)> {
+        let whole = key.as_ref();
+        let mut key = whole;
+        let mut node = &self.root;
+        let mut best = node.value.as_ref().map(|value| (0, value));
+        while let Some(&first) = key.first() {
+            let Ok(index) = node.child(first) else {
+                break;
+            };
+            node = &node.children[index];
+            let Some(rest) = key.strip_prefix(node.label.as_slice()) else {
+                break;
+            };
+            key = rest;
+            if let Some(value) = &node.value {
+                best = Some((whole.len() - key.len(), value));
+            }
+        }
+        best.map(|(len, value)| (&whole[..len], value))
+    }
+
+    /// Every entry, sorted by key.
+    pub fn iter(&self) -> Iter<'_, V> {
+        Iter {
+            stack: vec![(&self.root, 0)],
+            key: Vec::new(),
+        }
+    }
+
+    /// The entries whose keys start with `prefix`, sorted
+    /// by key.
+    pub fn iter_prefix<K: AsRef<[u8]> + ?Sized>(&self, prefix: &K) -> Iter<'_, V> {
+        let mut prefix = prefix.as_ref();
+        // the key down to where `node` hangs, not counting
+        // its own label.
+        let mut above = Vec::new();
+        let mut node = &self.root;
+        while let Some(&first) = prefix.first() {
+            let child = match node.child(first) {
+                Ok(index) => &node.children[index],
+                Err(_) => return Iter::empty(),
+            };
+            // the prefix may end halfway along the edge, in
+            // which case everything below the child matches.
+            let common = common_prefix(&child.label, prefix);
+            if common < prefix.len() && common < child.label.len() {
+                return Iter::empty();
+            }
+            above.extend_from_slice(&node.label);
+            node = child;
+            prefix = &prefix[common..];
+        }
+        Iter {
+            stack: vec![(node, above.len())],
+            key: above,
+        }
+    }
+
+    /// Checks that the children of every node are sorted by
+    /// distinct first bytes, that only the root has an empty
+    /// label, that every other node holds a value or
+    /// branches, and that the length is right.
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        let mut count = 0;
+        // the path to the node last popped. each entry on the
+        // stack says how deep its node is and which child it
+        // is, which is enough to fix the path up on the way.
+        let mut path = Vec::new();
+        let mut stack = vec![(&self.root, 0usize, 0)];
+        while let Some((node, depth, index)) = stack.pop() {
+            path.truncate(depth.saturating_sub(1));
+            if depth > 0 {
+                path.push(index);
+            }
+            let fail = |path: &Vec<usize>, rule| {
+                Err(InvariantViolation {
+                    path: path.clone(),
+                    rule,
+                })
+            };
+            let bare = node.value.is_none() && node.children.len() < 2;
+            if depth > 0 && (node.label.is_empty() || bare) {
+                return fail(&path, Rule::Shape);
+            }
+            // the order check needs every child's first byte,
+            // so an empty label is caught on the parent.
+            if node.children.iter().any(|child| child.label.is_empty()) {
+                return fail(&path, Rule::Shape);
+            }
+            let sorted = node
+                .children
+                .windows(2)
+                .all(|pair| pair[0].label[0] < pair[1].label[0]);
+            if !sorted {
+                return fail(&path, Rule::Order);
+            }
+            count += node.value.is_some() as usize;
+            for (index, child) in node.children.iter().enumerate() {
+                stack.push((child, depth + 1, index));
+            }
+        }
+        if count != self.len {
+            return Err(InvariantViolation::at_root(Rule::Len {
+                cached: self.len,
+                actual: count,
+            }));
+        }
+        Ok(())
+    }
+}
+
+pub struct Iter<'a, V> {
+    // nodes still to visit, each with the length of the key
+    // above it. the top is the next one in order.
+    stack: Vec<(&'a Node<V>, usize)>,
+    // the key of the node visited last.
+    key: Vec<u8>,
+}
+
+impl<V> Iter<'_, V> {
+    fn empty() -> Self {
+        Self {
+            stack: Vec::new(),
+            key: Vec::new(),
+        }
+    }
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (Vec<u8>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, above) = self.stack.pop()?;
+            self.key.truncate(above);
+            self.key.extend_from_slice(&node.label);
+            // reversed, so the smallest comes out first
+            let len = self.key.len();
+            self.stack
+                .extend(node.children.iter().rev().map(|child| (child, len)));
+            if let Some(value) = &node.value {
+                return Some((self.key.clone(), value));
+            }
+        }
+    }
+}
+
+impl<'a, V> IntoIterator for &'a RadixMap<V> {
+    type Item = (Vec<u8>, &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: AsRef<[u8]>, V> FromIterator<(K, V)> for RadixMap<V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = RadixMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: AsRef<[u8]>, V> Extend<(K, V)> for RadixMap<V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(&key, value);
+        }
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for RadixMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // keys as byte string literals, since they needn't
+        // be utf-8.
+        struct Key(Vec<u8>);
+
+        impl fmt::Debug for Key {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "b\"{}\"", self.0.escape_ascii())
+            }
+        }
+
+        f.debug_map()
+            .entries(self.iter().map(|(key, value)| (Key(key), value)))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn keys<V>(iter: Iter<'_, V>) -> Vec<String> {
+        iter.map(|(key, _)| String::from_utf8(key).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = RadixMap::new();
+        assert_eq!(map.insert("romane", 1), None);
+        assert_eq!(map.insert("romanus", 2), None);
+        assert_eq!(map.insert("romulus", 3), None);
+        assert_eq!(map.insert("rom", 4), None);
+        assert_eq!(map.insert("romane", 5), Some(1));
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get("romane"), Some(&5));
+        assert_eq!(map.get(b"rom".as_slice()), Some(&4));
+        assert_eq!(map.get("roma"), None);
+        assert_eq!(map.get("romanes"), None);
+        *map.get_mut("romulus").unwrap() += 10;
+        assert_eq!(map.get(&String::from("romulus")), Some(&13));
+        assert_eq!(
+            format!("{map:?}"),
+            r#"{b"rom": 4, b"romane": 5, b"romanus": 2, b"romulus": 13}"#
+        );
+        assert_eq!(map.validate(), Ok(()));
+
+        assert_eq!(map.remove("roma"), None);
+        assert_eq!(map.remove("rom"), Some(4));
+        assert_eq!(map.remove("rom"), None);
+        assert_eq!(map.remove("romanus"), Some(2));
+        assert_eq!(map.validate(), Ok(()));
+        assert_eq!(keys(map.iter()), ["romane", "romulus"]);
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn removal_merges_edges() {
+        let mut map: RadixMap<()> = [("test", ()), ("team", ()), ("toast", ())]
+            .into_iter()
+            .collect();
+        // t -> { e -> { st, am }, oast }
+        assert_eq!(map.root.children.len(), 1);
+        assert_eq!(map.root.children[0].children.len(), 2);
+
+        map.remove("toast");
+        // te -> { st, am }
+        assert_eq!(map.root.children[0].label, b"te");
+        map.remove("team");
+        assert_eq!(map.root.children[0].label, b"test");
+        assert!(map.root.children[0].children.is_empty());
+        map.remove("test");
+        assert!(map.root.children.is_empty());
+        assert_eq!(map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn longest_prefix_match() {
+        let routes: RadixMap<&str> = [
+            ("/", "root"),
+            ("/api", "api"),
+            ("/api/users", "users"),
+            ("/static/", "files"),
+        ]
+        .into_iter()
+        .collect();
+        let route = |path: &'static str| routes.longest_prefix_match(path);
+        assert_eq!(
+            route("/api/users/7"),
+            Some((b"/api/users".as_slice(), &"users"))
+        );
+        assert_eq!(route("/api/us"), Some((b"/api".as_slice(), &"api")));
+        assert_eq!(route("/static"), Some((b"/".as_slice(), &"root")));
+        assert_eq!(route("/static/app.js").map(|(_, v)| *v), Some("files"));
+        assert_eq!(route(""), None);
+
+        let mut empty_key = RadixMap::new();
+        empty_key.insert("", 0);
+        assert_eq!(
+            empty_key.longest_prefix_match("x"),
+            Some((b"".as_slice(), &0))
+        );
+    }
+
+    #[test]
+    fn iterates_under_a_prefix() {
+        let words = ["car", "card", "care", "cared", "cars", "cat", "dog", ""];
+        let map: RadixMap<usize> = words.iter().map(|word| (word, word.len())).collect();
+        assert_eq!(
+            keys(map.iter()),
+            ["", "car", "card", "care", "cared", "cars", "cat", "dog"]
+        );
+        assert_eq!(
+            keys(map.iter_prefix("car")),
+            ["car", "card", "care", "cared", "cars"]
+        );
+        // ends halfway along the edge to "card" and "care"
+        assert_eq!(keys(map.iter_prefix("ca")).len(), 6);
+        assert_eq!(keys(map.iter_prefix("care")), ["care", "cared"]);
+        assert_eq!(keys(map.iter_prefix("cart")), Vec::<String>::new());
+        assert_eq!(keys(map.iter_prefix("d")), ["dog"]);
+        assert_eq!(keys(map.iter_prefix("")).len(), words.len());
+    }
+
+    #[test]
+    fn matches_btreemap() {
+        let mut seed = 17u32;
+        let mut random = move |below: u32| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed % below
+        };
+        let mut map = RadixMap::new();
+        let mut model = BTreeMap::new();
+        for step in 0..20_000 {
+            // short keys over a small alphabet share a lot of
+            // prefixes.
+            let len = random(6);
+            let key: Vec<u8> = (0..len).map(|_| b'a' + random(3) as u8).collect();
+            if random(3) == 0 {
+                assert_eq!(map.remove(&key), model.remove(&key));
+            } else {
+                assert_eq!(map.insert(&key, step), model.insert(key, step));
+            }
+            if step % 1000 == 0 {
+                assert_eq!(map.validate(), Ok(()));
+            }
+        }
+        assert_eq!(map.len(), model.len());
+        assert!(map.iter().eq(model.iter().map(|(k, v)| (k.clone(), v))));
+        let under = model.keys().filter(|key| key.starts_with(b"ab"));
+        assert!(keys(map.iter_prefix("ab"))
+            .iter()
+            .map(String::as_bytes)
+            .eq(under.map(Vec::as_slice)));
+    }
+
+    #[test]
+    fn walks_and_drops_a_deep_trie() {
+        // "a", "aa", "aaa", ... built bottom up, since each
+        // insert would walk the whole chain.
+        let mut node = Node::new(b"a".to_vec(), Some(()));
+        for _ in 1..1_000_000 {
+            let mut parent = Node::new(b"a".to_vec(), Some(()));
+            parent.children.push(node);
+            node = parent;
+        }
+        let mut map = RadixMap::new();
+        map.root.children.push(node);
+        map.len = 1_000_000;
+        assert_eq!(map.validate(), Ok(()));
+        assert_eq!(map.remove("a"), Some(()));
+        assert_eq!(map.root.children[0].label, b"aa");
+    }
+
+    #[test]
+    fn validate_points_at_the_broken_node() {
+        let mut map: RadixMap<()> = [("ab", ()), ("ac", ()), ("b", ())].into_iter().collect();
+        assert_eq!(map.validate(), Ok(()));
+        map.root.children[0].children.swap(0, 1);
+        assert_eq!(
+            map.validate(),
+            Err(InvariantViolation {
+                path: vec![0],
+                rule: Rule::Order,
+            })
+        );
+        map.root.children[0].children.swap(0, 1);
+        map.root.children[1].value = None;
+        assert_eq!(map.validate().unwrap_err().path, [1]);
+    }
+}
@@ -0,0 +1,355 @@
+/*
+ *  Range queries
+ *
+ *  A range keeps two stacks: the path to the next
+ *  node from the front and the path to the next node
+ *  from the back. Only the nodes between the two
+ *  bounds are ever visited, so a range of `k` items
+ *  costs O(log n + k) on a balanced tree.
+ */
+
+use std::borrow::Borrow;
+use std::ops::{Bound, RangeBounds};
+
+use crate::{Bst, Compare, Node};
+
+pub(crate) trait Children {
+    fn left(&self) -> Option<&Self>;
+    fn right(&self) -> Option<&Self>;
+}
+
+impl<T> Children for Node<T> {
+    fn left(&self) -> Option<&Self> {
+        self.left.as_deref()
+    }
+
+    fn right(&self) -> Option<&Self> {
+        self.right.as_deref()
+    }
+}
+
+pub(crate) fn after_start<Q: ?Sized>(cmp: &impl Compare<Q>, start: Bound<&Q>, value: &Q) -> bool {
+    match start {
+        Bound::Included(start) => cmp.compare(value, start).is_ge(),
+        Bound::Excluded(start) => cmp.compare(value, start).is_gt(),
+        Bound::Unbounded => true,
+    }
+}
+
+pub(crate) fn before_end<Q: ?Sized>(cmp: &impl Compare<Q>, end: Bound<&Q>, value: &Q) -> bool {
+    match end {
+        Bound::Included(end) => cmp.compare(value, end).is_le(),
+        Bound::Excluded(end) => cmp.compare(value, end).is_lt(),
+        Bound::Unbounded => true,
+    }
+}
+
+// turns a range of positions into a start and an end
+// within `0..len`, panicking on a bad one like slice
+// indexing does.
+pub(crate) fn positions(range: impl RangeBounds<usize>, len: usize) -> (usize, usize) {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start.checked_add(1).expect("range start overflows"),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end.checked_add(1).expect("range end overflows"),
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => len,
+    };
+    assert!(start <= end, "range starts at {start} but ends at {end}");
+    assert!(end <= len, "range end {end} out of bounds for length {len}");
+    (start, end)
+}
+
+// the in-order walk shared by every range iterator in
+// the crate. the top of each stack is the next node
+// that end will yield.
+pub(crate) struct Span<'a, N> {
+    front: Vec<&'a N>,
+    back: Vec<&'a N>,
+}
+
+impl<'a, N: Children> Span<'a, N> {
+    pub(crate) fn new(
+        root: Option<&'a N>,
+        after_start: impl Fn(&N) -> bool,
+        before_end: impl Fn(&N) -> bool,
+    ) -> Self {
+        let mut front = Vec::new();
+        let mut next = root;
+        while let Some(node) = next {
+            if after_start(node) {
+                front.push(node);
+                next = node.left();
+            } else {
+                next = node.right();
+            }
+        }
+
+        let mut back = Vec::new();
+        let mut next = root;
+        while let Some(node) = next {
+            if before_end(node) {
+                back.push(node);
+                next = node.right();
+            } else {
+                next = node.left();
+            }
+        }
+
+        // the first node after the start is already
+        // past the end, so the range is empty.
+        match front.last() {
+            Some(first) if before_end(first) => Self { front, back },
+            _ => Self {
+                front: Vec::new(),
+                back: Vec::new(),
+            },
+        }
+    }
+
+    pub(crate) fn next(&mut self) -> Option<&'a N> {
+        let node = self.front.pop()?;
+        let mut next = node.right();
+        while let Some(child) = next {
+            self.front.push(child);
+            next = child.left();
+        }
+        self.finish_if_met(node);
+        Some(node)
+    }
+
+    pub(crate) fn next_back(&mut self) -> Option<&'a N> {
+        let node = self.back.pop()?;
+        let mut next = node.left();
+        while let Some(child) = next {
+            self.back.push(child);
+            next = child.right();
+        }
+        self.finish_if_met(node);
+        Some(node)
+    }
+
+    // both ends walk the same in-order sequence, so
+    // once one of them yields the node the other end
+    // was about to yield, every node has been seen.
+    fn finish_if_met(&mut self, yielded: &N) {
+        let met_front = self.front.last().is_some_and(|n| std::ptr::eq(*n, yielded));
+        let met_back = self.back.last().is_some_and(|n| std::ptr::eq(*n, yielded));
+        if met_front || met_back {
+            self.front.clear();
+            self.back.clear();
+        }
+    }
+}
+
+// the distinct values of a tree in order, each with
+// the number of copies its node holds.
+pub(crate) struct Runs<'a, T> {
+    span: Span<'a, Node<T>>,
+}
+
+impl<'a, T> Iterator for Runs<'a, T> {
+    type Item = (&'a T, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.span.next().map(|node| (&node.value, node.count))
+    }
+}
+
+pub struct Range<'a, T> {
+    span: Span<'a, Node<T>>,
+    // the value each end is repeating and how many of
+    // its copies are left to yield.
+    front: Option<(&'a T, usize)>,
+    back: Option<(&'a T, usize)>,
+}
+
+impl<'a, T> Range<'a, T> {
+    fn new(span: Span<'a, Node<T>>) -> Self {
+        Self {
+            span,
+            front: None,
+            back: None,
+        }
+    }
+}
+
+fn take_copy<'a, T>(run: &mut Option<(&'a T, usize)>) -> Option<&'a T> {
+    let (value, left) = run.as_mut()?;
+    if *left == 0 {
+        return None;
+    }
+    *left -= 1;
+    Some(*value)
+}
+
+impl<'a, T> Iterator for Range<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(value) = take_copy(&mut self.front) {
+                return Some(value);
+            }
+            match self.span.next() {
+                Some(node) => self.front = Some((&node.value, node.count)),
+                // the other end may still hold copies of
+                // the last node.
+                None => return take_copy(&mut self.back),
+            }
+        }
+    }
+}
+
+impl<T> DoubleEndedIterator for Range<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(value) = take_copy(&mut self.back) {
+                return Some(value);
+            }
+            match self.span.next_back() {
+                Some(node) => self.back = Some((&node.value, node.count)),
+                None => return take_copy(&mut self.front),
+            }
+        }
+    }
+}
+
+impl<T, C> Bst<T, C> {
+    pub fn iter(&self) -> Range<'_, T> {
+        Range::new(Span::new(self.root.as_deref(), |_| true, |_| true))
+    }
+
+    pub(crate) fn runs(&self) -> Runs<'_, T> {
+        Runs {
+            span: Span::new(self.root.as_deref(), |_| true, |_| true),
+        }
+    }
+}
+
+impl<T, C> Bst<T, C> {
+    /// Returns the values that fall inside `range`, in order.
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, T>
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+        R: RangeBounds<Q>,
+    {
+        Range::new(Span::new(
+            self.root.as_deref(),
+            |node| after_start(&self.cmp, range.start_bound(), node.value.borrow()),
+            |node| before_end(&self.cmp, range.end_bound(), node.value.borrow()),
+        ))
+    }
+
+    /// Counts the values inside `range` in O(log n) using
+    /// the subtree sizes, without visiting them.
+    pub fn count_range<Q, R>(&self, range: R) -> usize
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let cmp = &self.cmp;
+        let up_to_end = self.count_prefix(|v| before_end(cmp, range.end_bound(), v.borrow()));
+        let before_start =
+            self.count_prefix(|v| !after_start(cmp, range.start_bound(), v.borrow()));
+        up_to_end.saturating_sub(before_start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Range;
+    use crate::Bst;
+    use std::ops::Bound;
+
+    fn tree() -> Bst<i32> {
+        let mut tree = Bst::from_value(50);
+        for value in [30, 70, 20, 40, 60, 80, 35, 45, 65] {
+            tree.insert(value);
+        }
+        tree
+    }
+
+    #[test]
+    fn all_bound_forms() {
+        let tree = tree();
+        let range = |r: Range<'_, i32>| r.copied().collect::<Vec<_>>();
+
+        assert_eq!(
+            range(tree.range(..)),
+            [20, 30, 35, 40, 45, 50, 60, 65, 70, 80]
+        );
+        assert_eq!(range(tree.range(40..)), [40, 45, 50, 60, 65, 70, 80]);
+        assert_eq!(range(tree.range(..40)), [20, 30, 35]);
+        assert_eq!(range(tree.range(..=40)), [20, 30, 35, 40]);
+        assert_eq!(range(tree.range(36..65)), [40, 45, 50, 60]);
+        assert_eq!(range(tree.range(35..=65)), [35, 40, 45, 50, 60, 65]);
+        assert_eq!(
+            range(tree.range((Bound::Excluded(35), Bound::Excluded(65)))),
+            [40, 45, 50, 60]
+        );
+    }
+
+    #[test]
+    fn empty_ranges() {
+        let tree = tree();
+        assert_eq!(tree.range(41..45).next(), None);
+        assert_eq!(tree.range(81..).next(), None);
+        assert_eq!(tree.range(..20).next_back(), None);
+        assert_eq!(
+            tree.range((Bound::Included(60), Bound::Excluded(40)))
+                .next(),
+            None
+        );
+        assert_eq!(Bst::<i32>::default().range(..).next(), None);
+    }
+
+    #[test]
+    fn double_ended() {
+        let tree = tree();
+        let mut range = tree.range(30..=60);
+        assert_eq!(range.next(), Some(&30));
+        assert_eq!(range.next_back(), Some(&60));
+        assert_eq!(range.next_back(), Some(&50));
+        assert_eq!(range.next(), Some(&35));
+        assert_eq!(range.next(), Some(&40));
+        assert_eq!(range.next_back(), Some(&45));
+        assert_eq!(range.next(), None);
+        assert_eq!(range.next_back(), None);
+
+        let reversed: Vec<_> = tree.range(..45).rev().copied().collect();
+        assert_eq!(reversed, [40, 35, 30, 20]);
+    }
+
+    #[test]
+    fn repeats_counted_values_from_both_ends() {
+        let mut tree = Bst::from_value(2);
+        tree.insert_n(1, 2);
+        tree.insert_n(3, 3);
+
+        let mut range = tree.range(..);
+        assert_eq!(range.next_back(), Some(&3));
+        assert_eq!(range.next(), Some(&1));
+        assert_eq!(range.next(), Some(&1));
+        assert_eq!(range.next(), Some(&2));
+        assert_eq!(range.next(), Some(&3));
+        assert_eq!(range.next_back(), Some(&3));
+        assert_eq!(range.next_back(), None);
+        assert_eq!(tree.count_range(2..), 4);
+    }
+
+    #[test]
+    fn count_range() {
+        let tree = tree();
+        assert_eq!(tree.count_range(..), 10);
+        assert_eq!(tree.count_range(35..=65), 6);
+        assert_eq!(tree.count_range(90..), 0);
+    }
+}
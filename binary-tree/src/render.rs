@@ -0,0 +1,454 @@
+/*
+ *  Rendering trees for humans
+ *
+ *  `to_dot` writes a Graphviz digraph, and `pretty`
+ *  draws the tree as plain text, either sideways (the
+ *  default, also used by `Display`) or top-down. Both
+ *  text layouts can label every node with its subtree
+ *  size and its balance factor, the height of the right
+ *  subtree minus the height of the left one. A node
+ *  holding several copies of its value is shown as
+ *  "value xN" in every format.
+ */
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Write};
+
+use crate::{Bst, Link, Node};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    Sideways,
+    TopDown,
+}
+
+pub struct Pretty<'a, T, C = crate::Natural> {
+    tree: &'a Bst<T, C>,
+    layout: Layout,
+    sizes: bool,
+    balance: bool,
+}
+
+impl<T, C> Bst<T, C>
+where
+    T: Display,
+{
+    /// Writes the tree as a Graphviz digraph. A node with a
+    /// single child also gets a point for the missing one,
+    /// so left and right children can be told apart.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph Bst {\n    node [shape=circle];\n");
+        let mut ids = 0;
+        let mut stack = Vec::new();
+        if let Some(root) = self.root.as_deref() {
+            stack.push((root, ids));
+        }
+
+        while let Some((node, id)) = stack.pop() {
+            let label = copies(node).replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(dot, "    n{id} [label=\"{label}\"];").unwrap();
+
+            let has_child = node.left.is_some() || node.right.is_some();
+            for child in [&node.left, &node.right] {
+                ids += 1;
+                match child.as_deref() {
+                    Some(child) => {
+                        writeln!(dot, "    n{id} -> n{ids};").unwrap();
+                        stack.push((child, ids));
+                    }
+                    None if has_child => {
+                        writeln!(dot, "    null{ids} [shape=point];").unwrap();
+                        writeln!(dot, "    n{id} -> null{ids};").unwrap();
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn pretty(&self) -> Pretty<'_, T, C> {
+        Pretty {
+            tree: self,
+            layout: Layout::Sideways,
+            sizes: false,
+            balance: false,
+        }
+    }
+}
+
+fn copies<T: Display>(node: &Node<T>) -> String {
+    if node.count > 1 {
+        format!("{} x{}", node.value, node.count)
+    } else {
+        node.value.to_string()
+    }
+}
+
+impl<T: Display, C> Display for Bst<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.pretty().fmt(f)
+    }
+}
+
+impl<T, C> Pretty<'_, T, C>
+where
+    T: Display,
+{
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn top_down(self) -> Self {
+        self.layout(Layout::TopDown)
+    }
+
+    pub fn with_sizes(mut self) -> Self {
+        self.sizes = true;
+        self
+    }
+
+    pub fn with_balance(mut self) -> Self {
+        self.balance = true;
+        self
+    }
+
+    fn label(&self, node: &Node<T>, balance: isize) -> String {
+        let mut label = copies(node);
+        match (self.sizes, self.balance) {
+            (true, true) => write!(label, " [n={} b={balance:+}]", node.size),
+            (true, false) => write!(label, " [n={}]", node.size),
+            (false, true) => write!(label, " [b={balance:+}]"),
+            (false, false) => Ok(()),
+        }
+        .unwrap();
+        label
+    }
+
+    // the height of every subtree, by node, for the balance
+    // labels. empty when they aren't wanted.
+    fn heights(&self) -> HashMap<*const Node<T>, usize> {
+        let mut heights = HashMap::new();
+        if !self.balance {
+            return heights;
+        }
+        let height = |heights: &HashMap<_, _>, link: &Link<T>| {
+            link.as_deref()
+                .map_or(0, |child| heights[&(child as *const Node<T>)])
+        };
+        // children before their parents
+        let mut stack: Vec<(&Node<T>, bool)> = self
+            .tree
+            .root
+            .as_deref()
+            .map(|root| (root, false))
+            .into_iter()
+            .collect();
+        while let Some((node, children_done)) = stack.pop() {
+            if children_done {
+                let h = 1 + height(&heights, &node.left).max(height(&heights, &node.right));
+                heights.insert(node as *const Node<T>, h);
+                continue;
+            }
+            stack.push((node, true));
+            stack.extend(node.left.as_deref().map(|left| (left, false)));
+            stack.extend(node.right.as_deref().map(|right| (right, false)));
+        }
+        heights
+    }
+
+    fn balance(heights: &HashMap<*const Node<T>, usize>, node: &Node<T>) -> isize {
+        let height = |link: &Link<T>| {
+            link.as_deref()
+                .and_then(|child| heights.get(&(child as *const Node<T>)))
+                .map_or(0, |&h| h as isize)
+        };
+        height(&node.right) - height(&node.left)
+    }
+
+    // draws the tree with the right side on top, so turning
+    // the output clockwise shows the usual tree. walks it in
+    // reverse order with a stack, like `to_dot`, so a long
+    // chain can't overflow the call stack.
+    fn sideways_lines(&self) -> Vec<String> {
+        enum Step<'a, T> {
+            Visit(&'a Node<T>, String, Edge),
+            Draw(&'a Node<T>, String, Edge),
+        }
+
+        let heights = self.heights();
+        let mut lines = Vec::new();
+        let mut stack: Vec<Step<T>> = self
+            .tree
+            .root
+            .as_deref()
+            .map(|root| Step::Visit(root, String::new(), Edge::Root))
+            .into_iter()
+            .collect();
+        while let Some(step) = stack.pop() {
+            match step {
+                Step::Visit(node, prefix, edge) => {
+                    if let Some(left) = node.left.as_deref() {
+                        let bar = if edge == Edge::Right { "|   " } else { "    " };
+                        stack.push(Step::Visit(left, format!("{prefix}{bar}"), Edge::Left));
+                    }
+                    let right = node.right.as_deref().map(|right| {
+                        let bar = if edge == Edge::Left { "|   " } else { "    " };
+                        Step::Visit(right, format!("{prefix}{bar}"), Edge::Right)
+                    });
+                    stack.push(Step::Draw(node, prefix, edge));
+                    stack.extend(right);
+                }
+                Step::Draw(node, prefix, edge) => {
+                    let connector = match edge {
+                        Edge::Root => "",
+                        Edge::Right => "/-- ",
+                        Edge::Left => "\\-- ",
+                    };
+                    let label = self.label(node, Self::balance(&heights, node));
+                    lines.push(format!("{prefix}{connector}{label}"));
+                }
+            }
+        }
+        lines
+    }
+
+    // builds the blocks bottom up with a stack, each parent
+    // joining the two its children left behind.
+    fn top_down_lines(&self) -> Vec<String> {
+        enum Step<'a, T> {
+            Enter(Option<&'a Node<T>>),
+            Join(&'a Node<T>),
+        }
+
+        let mut blocks: Vec<Option<Block>> = Vec::new();
+        let mut stack = vec![Step::Enter(self.tree.root.as_deref())];
+        while let Some(step) = stack.pop() {
+            match step {
+                Step::Enter(None) => blocks.push(None),
+                Step::Enter(Some(node)) => {
+                    stack.push(Step::Join(node));
+                    stack.push(Step::Enter(node.right.as_deref()));
+                    stack.push(Step::Enter(node.left.as_deref()));
+                }
+                Step::Join(node) => {
+                    let right = blocks.pop().unwrap();
+                    let left = blocks.pop().unwrap();
+                    let height = |block: &Option<Block>| block.as_ref().map_or(0, |b| b.height);
+                    let balance = height(&right) as isize - height(&left) as isize;
+                    let label = self.label(node, balance);
+                    blocks.push(Some(Block::join(left, label, right)));
+                }
+            }
+        }
+        blocks
+            .pop()
+            .flatten()
+            .map_or_else(Vec::new, |block| block.lines)
+    }
+}
+
+impl<T: Display, C> Display for Pretty<'_, T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = match self.layout {
+            Layout::Sideways => self.sideways_lines(),
+            Layout::TopDown => self.top_down_lines(),
+        };
+        if lines.is_empty() {
+            return writeln!(f, "(empty)");
+        }
+        for line in lines {
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Edge {
+    Root,
+    Left,
+    Right,
+}
+
+// a rectangle of text holding a drawn subtree. `middle`
+// is the column its root hangs from.
+struct Block {
+    lines: Vec<String>,
+    width: usize,
+    middle: usize,
+    height: usize,
+}
+
+impl Block {
+    fn join(left: Option<Block>, mut label: String, right: Option<Block>) -> Block {
+        // every block is at least a column wide, so its
+        // middle is always inside it.
+        if label.is_empty() {
+            label.push(' ');
+        }
+        let len = label.chars().count();
+        let pad = |n: usize| " ".repeat(n);
+        let bar = |n: usize| "_".repeat(n);
+
+        match (left, right) {
+            (None, None) => Block {
+                lines: vec![label],
+                width: len,
+                middle: len / 2,
+                height: 1,
+            },
+            (Some(l), None) => {
+                let mut lines = vec![
+                    format!(
+                        "{}{}{label}",
+                        pad(l.middle + 1),
+                        bar(l.width - l.middle - 1)
+                    ),
+                    format!("{}/{}", pad(l.middle), pad(l.width - l.middle - 1 + len)),
+                ];
+                lines.extend(l.lines.into_iter().map(|line| line + &pad(len)));
+                Block {
+                    lines,
+                    width: l.width + len,
+                    middle: l.width + len / 2,
+                    height: l.height + 1,
+                }
+            }
+            (None, Some(r)) => {
+                let mut lines = vec![
+                    format!("{label}{}{}", bar(r.middle), pad(r.width - r.middle)),
+                    format!("{}\\{}", pad(len + r.middle), pad(r.width - r.middle - 1)),
+                ];
+                lines.extend(r.lines.into_iter().map(|line| pad(len) + &line));
+                Block {
+                    lines,
+                    width: r.width + len,
+                    middle: len / 2,
+                    height: r.height + 1,
+                }
+            }
+            (Some(l), Some(r)) => {
+                let mut lines = vec![
+                    format!(
+                        "{}{}{label}{}{}",
+                        pad(l.middle + 1),
+                        bar(l.width - l.middle - 1),
+                        bar(r.middle),
+                        pad(r.width - r.middle)
+                    ),
+                    format!(
+                        "{}/{}\\{}",
+                        pad(l.middle),
+                        pad(l.width - l.middle - 1 + len + r.middle),
+                        pad(r.width - r.middle - 1)
+                    ),
+                ];
+                let rows = l.lines.len().max(r.lines.len());
+                let mut left_lines = l.lines.into_iter();
+                let mut right_lines = r.lines.into_iter();
+                for _ in 0..rows {
+                    let a = left_lines.next().unwrap_or_else(|| pad(l.width));
+                    let b = right_lines.next().unwrap_or_else(|| pad(r.width));
+                    lines.push(format!("{a}{}{b}", pad(len)));
+                }
+                Block {
+                    lines,
+                    width: l.width + r.width + len,
+                    middle: l.width + len / 2,
+                    height: l.height.max(r.height) + 1,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Bst;
+
+    fn tree() -> Bst<i32> {
+        let mut tree = Bst::from_value(50);
+        for value in [30, 70, 20, 40, 80] {
+            tree.insert(value);
+        }
+        tree
+    }
+
+    #[test]
+    fn sideways() {
+        let drawing = r"        /-- 80
+    /-- 70
+50
+    |   /-- 40
+    \-- 30
+        \-- 20
+";
+        assert_eq!(tree().to_string(), drawing);
+    }
+
+    #[test]
+    fn top_down_with_annotations() {
+        let tree = tree();
+        let drawing = r"    __50_
+   /     \
+  30_   70_
+ /   \     \
+20  40    80
+";
+        assert_eq!(tree.pretty().top_down().to_string(), drawing);
+
+        let annotated = tree.pretty().with_sizes().with_balance().to_string();
+        assert!(annotated.contains("50 [n=6 b=+0]"));
+        assert!(annotated.contains("70 [n=2 b=+1]"));
+        assert!(annotated.contains("80 [n=1 b=+0]"));
+
+        assert_eq!(Bst::<i32>::default().to_string(), "(empty)\n");
+    }
+
+    #[test]
+    fn empty_labels_take_a_column() {
+        let mut tree = Bst::from_value(String::from("b"));
+        for value in ["", "c", "a"] {
+            tree.insert(value.to_string());
+        }
+        // the empty string under `b` is drawn as a blank
+        let drawing = r" _b
+/  \
+   c
+ \
+ a
+";
+        assert_eq!(tree.pretty().top_down().to_string(), drawing);
+    }
+
+    #[test]
+    fn dot() {
+        let dot = tree().to_dot();
+        assert!(dot.starts_with("digraph Bst {\n"));
+        assert!(dot.contains("n0 [label=\"50\"];"));
+        assert_eq!(dot.matches(" -> ").count(), 6);
+        // 70 has no left child, so it gets exactly one null marker
+        assert_eq!(dot.matches("[shape=point]").count(), 1);
+
+        let mut quoted = Bst::from_value("say \"hi\"");
+        quoted.insert("a");
+        assert!(quoted.to_dot().contains(r#"[label="say \"hi\""]"#));
+    }
+
+    #[test]
+    fn copies_are_labelled() {
+        let mut tree = tree();
+        tree.insert_n(70, 2);
+        assert!(tree.to_string().contains("/-- 70 x3"));
+        assert!(tree.to_dot().contains("[label=\"70 x3\"]"));
+        assert!(tree
+            .pretty()
+            .with_sizes()
+            .to_string()
+            .contains("70 x3 [n=4]"));
+    }
+}
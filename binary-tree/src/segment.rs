@@ -0,0 +1,464 @@
+/*
+ *  Segment tree
+ *
+ *  A complete binary tree over a fixed row of values,
+ *  where each node holds the aggregate of the values
+ *  under it. A query folds the O(log n) nodes that tile
+ *  the range, and changing a value fixes up the nodes
+ *  on its path to the root.
+ *
+ *  An update to a whole range stops at the same O(log n)
+ *  nodes a query would visit, brings their aggregates up
+ *  to date, and leaves the update pending on them. It is
+ *  only passed down to the children when a later update
+ *  needs to go further, and a query folds the pending
+ *  updates above a node into what it reads from it, so
+ *  queries never have to write.
+ *
+ *  The nodes live in two arrays, heap-ordered from 1:
+ *  node `i` has its children at `2i` and `2i + 1`.
+ */
+
+use std::fmt;
+use std::ops::RangeBounds;
+
+use crate::monoid::Monoid;
+use crate::range::positions;
+use crate::validate::{InvariantViolation, Rule};
+
+pub struct SegmentTree<T, Op: Monoid<T>> {
+    op: Op,
+    len: usize,
+    // each aggregate is up to date with every update
+    // except those still pending on the node's ancestors.
+    tree: Vec<T>,
+    // an update the node has taken but its children
+    // haven't. leaves never hold one.
+    pending: Vec<Option<Op::Update>>,
+}
+
+impl<T: Clone, Op: Monoid<T> + Default> SegmentTree<T, Op> {
+    /// Builds the tree over `values` in O(n).
+    pub fn build_from(values: impl IntoIterator<Item = T>) -> Self {
+        Self::build_from_with(values, Op::default())
+    }
+}
+
+impl<T: Clone, Op: Monoid<T>> SegmentTree<T, Op> {
+    /// Builds the tree over `values` in O(n), folding them
+    /// with `op`.
+    pub fn build_from_with(values: impl IntoIterator<Item = T>, op: Op) -> Self {
+        let values: Vec<T> = values.into_iter().collect();
+        let len = values.len();
+        // splitting down the middle leaves no path longer
+        // than with the length rounded up to a power of two.
+        let nodes = 2 * len.next_power_of_two();
+        let mut tree = Self {
+            tree: (0..nodes).map(|_| op.identity()).collect(),
+            pending: (0..nodes).map(|_| None).collect(),
+            op,
+            len,
+        };
+        if len > 0 {
+            tree.build(1, 0, len, &mut values.into_iter());
+        }
+        tree
+    }
+
+    fn build(&mut self, node: usize, lo: usize, hi: usize, values: &mut impl Iterator<Item = T>) {
+        if hi - lo == 1 {
+            self.tree[node] = values.next().unwrap();
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        self.build(2 * node, lo, mid, values);
+        self.build(2 * node + 1, mid, hi, values);
+        self.pull(node);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> T {
+        assert!(
+            index < self.len,
+            "index {index} out of bounds for length {}",
+            self.len
+        );
+        self.query(index..=index)
+    }
+
+    /// The aggregate of the values in `range`, or the
+    /// identity if it is empty. O(log n).
+    pub fn query(&self, range: impl RangeBounds<usize>) -> T {
+        let (start, end) = positions(range, self.len);
+        if start == end {
+            return self.op.identity();
+        }
+        self.fold(1, 0, self.len, start, end, None)
+    }
+
+    fn fold(
+        &self,
+        node: usize,
+        lo: usize,
+        hi: usize,
+        start: usize,
+        end: usize,
+        above: Option<&Op::Update>,
+    ) -> T {
+        if start <= lo && hi <= end {
+            return self.current(node, hi - lo, above);
+        }
+        let below = self.below(node, above);
+        let mid = lo + (hi - lo) / 2;
+        if end <= mid {
+            return self.fold(2 * node, lo, mid, start, end, below.as_ref());
+        }
+        if mid <= start {
+            return self.fold(2 * node + 1, mid, hi, start, end, below.as_ref());
+        }
+        let left = self.fold(2 * node, lo, mid, start, end, below.as_ref());
+        let right = self.fold(2 * node + 1, mid, hi, start, end, below.as_ref());
+        self.op.combine(&left, &right)
+    }
+
+    // the aggregate of a node once `above`, everything
+    // pending on its ancestors, has reached it.
+    fn current(&self, node: usize, len: usize, above: Option<&Op::Update>) -> T {
+        match above {
+            Some(update) => self.op.apply(update, &self.tree[node], len),
+            None => self.tree[node].clone(),
+        }
+    }
+
+    // what is pending on the children of a node: its own
+    // update, followed by the newer ones above it.
+    fn below(&self, node: usize, above: Option<&Op::Update>) -> Option<Op::Update> {
+        match (&self.pending[node], above) {
+            (Some(own), Some(above)) => Some(self.op.compose(own, above)),
+            (Some(own), None) => Some(own.clone()),
+            (None, above) => above.cloned(),
+        }
+    }
+
+    /// Sets the value at `index`. O(log n).
+    pub fn update(&mut self, index: usize, value: T) {
+        assert!(
+            index < self.len,
+            "index {index} out of bounds for length {}",
+            self.len
+        );
+        let (mut node, mut lo, mut hi) = (1, 0, self.len);
+        while hi - lo > 1 {
+            self.push(node, lo, hi);
+            let mid = lo + (hi - lo) / 2;
+            if index < mid {
+                (node, hi) = (2 * node, mid);
+            } else {
+                (node, lo) = (2 * node + 1, mid);
+            }
+        }
+        self.tree[node] = value;
+        while node > 1 {
+            node /= 2;
+            self.pull(node);
+        }
+    }
+
+    /// Applies `update` to every value in `range`. O(log n).
+    pub fn update_range(&mut self, range: impl RangeBounds<usize>, update: Op::Update) {
+        let (start, end) = positions(range, self.len);
+        if start < end {
+            self.spread(1, 0, self.len, start, end, &update);
+        }
+    }
+
+    fn spread(
+        &mut self,
+        node: usize,
+        lo: usize,
+        hi: usize,
+        start: usize,
+        end: usize,
+        update: &Op::Update,
+    ) {
+        if end <= lo || hi <= start {
+            return;
+        }
+        if start <= lo && hi <= end {
+            self.take(node, hi - lo, update);
+            return;
+        }
+        self.push(node, lo, hi);
+        let mid = lo + (hi - lo) / 2;
+        self.spread(2 * node, lo, mid, start, end, update);
+        self.spread(2 * node + 1, mid, hi, start, end, update);
+        self.pull(node);
+    }
+
+    // applies an update to a whole node, leaving it
+    // pending for the children.
+    fn take(&mut self, node: usize, len: usize, update: &Op::Update) {
+        self.tree[node] = self.op.apply(update, &self.tree[node], len);
+        if len > 1 {
+            let pending = match self.pending[node].take() {
+                Some(own) => self.op.compose(&own, update),
+                None => update.clone(),
+            };
+            self.pending[node] = Some(pending);
+        }
+    }
+
+    // hands a node's pending update to its children.
+    fn push(&mut self, node: usize, lo: usize, hi: usize) {
+        if let Some(update) = self.pending[node].take() {
+            let mid = lo + (hi - lo) / 2;
+            self.take(2 * node, mid - lo, &update);
+            self.take(2 * node + 1, hi - mid, &update);
+        }
+    }
+
+    fn pull(&mut self, node: usize) {
+        self.tree[node] = self
+            .op
+            .combine(&self.tree[2 * node], &self.tree[2 * node + 1]);
+    }
+
+    /// How many values from the front can be folded
+    /// before `pred` turns false on the aggregate: the
+    /// first `i` where `pred(query(..=i))` doesn't hold,
+    /// or `len()` if it always does. O(log n).
+    ///
+    /// `pred` must hold for the identity, and once false
+    /// for a prefix, stay false for every longer one.
+    pub fn partition_point(&self, mut pred: impl FnMut(&T) -> bool) -> usize {
+        let mut prefix = self.op.identity();
+        if self.len == 0 {
+            return 0;
+        }
+        self.search(1, 0, self.len, None, &mut prefix, &mut pred)
+    }
+
+    // returns where the prefix ends inside this node, or
+    // `hi` if all of it fits, adding what fits to `prefix`.
+    fn search(
+        &self,
+        node: usize,
+        lo: usize,
+        hi: usize,
+        above: Option<&Op::Update>,
+        prefix: &mut T,
+        pred: &mut impl FnMut(&T) -> bool,
+    ) -> usize {
+        let whole = self.op.combine(prefix, &self.current(node, hi - lo, above));
+        if pred(&whole) {
+            *prefix = whole;
+            return hi;
+        }
+        if hi - lo == 1 {
+            return lo;
+        }
+        let below = self.below(node, above);
+        let mid = lo + (hi - lo) / 2;
+        let end = self.search(2 * node, lo, mid, below.as_ref(), prefix, pred);
+        if end < mid {
+            return end;
+        }
+        self.search(2 * node + 1, mid, hi, below.as_ref(), prefix, pred)
+    }
+
+    /// Checks that every node's aggregate folds those of
+    /// its children, once its pending update reaches them.
+    pub fn validate(&self) -> Result<(), InvariantViolation>
+    where
+        T: PartialEq,
+    {
+        if self.len == 0 {
+            return Ok(());
+        }
+        self.check(1, 0, self.len)
+    }
+
+    fn check(&self, node: usize, lo: usize, hi: usize) -> Result<(), InvariantViolation>
+    where
+        T: PartialEq,
+    {
+        if hi - lo == 1 {
+            return Ok(());
+        }
+        let mid = lo + (hi - lo) / 2;
+        self.check(2 * node, lo, mid)?;
+        self.check(2 * node + 1, mid, hi)?;
+        let pending = self.pending[node].as_ref();
+        let left = self.current(2 * node, mid - lo, pending);
+        let right = self.current(2 * node + 1, hi - mid, pending);
+        if self.tree[node] != self.op.combine(&left, &right) {
+            // the bits of the index below the leading one
+            // are the turns taken from the root.
+            let path = (0..node.ilog2()).rev().map(|bit| node >> bit & 1).collect();
+            return Err(InvariantViolation {
+                path,
+                rule: Rule::Aggregate,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<T: Clone + fmt::Debug, Op: Monoid<T>> fmt::Debug for SegmentTree<T, Op> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries((0..self.len).map(|index| self.get(index)))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monoid::{Max, Min, Sum, Update};
+    use std::convert::Infallible;
+
+    #[test]
+    fn queries_and_updates() {
+        let mut tree: SegmentTree<i64, Sum> = SegmentTree::build_from([5, 3, 8, 1, 4]);
+        assert_eq!(tree.query(..), 21);
+        assert_eq!(tree.query(1..4), 12);
+        assert_eq!(tree.query(2..2), 0);
+        tree.update(2, 10);
+        assert_eq!(tree.query(1..=2), 13);
+        tree.update_range(1..4, Update::Add(2));
+        assert_eq!(format!("{tree:?}"), "[5, 5, 12, 3, 4]");
+        tree.update_range(..2, Update::Assign(0));
+        assert_eq!(tree.query(..), 19);
+        assert_eq!(tree.validate(), Ok(()));
+
+        let mut min: SegmentTree<i32, Min> = SegmentTree::build_from([4, 2, 7]);
+        assert_eq!(min.query(..), 2);
+        min.update_range(1.., Update::Add(-5));
+        assert_eq!(min.query(..1), 4);
+        assert_eq!(min.query(2..), 2);
+        assert_eq!(SegmentTree::<i32, Max>::build_from([]).query(..), i32::MIN);
+    }
+
+    #[test]
+    fn partition_point_finds_the_prefix() {
+        let tree: SegmentTree<u32, Sum> = SegmentTree::build_from([2, 0, 3, 1, 4]);
+        assert_eq!(tree.partition_point(|&sum| sum < 1), 0);
+        assert_eq!(tree.partition_point(|&sum| sum <= 2), 2);
+        assert_eq!(tree.partition_point(|&sum| sum < 6), 3);
+        assert_eq!(tree.partition_point(|&sum| sum <= 10), 5);
+
+        let max: SegmentTree<i32, Max> = SegmentTree::build_from([1, 3, 2, 9, 4]);
+        // the first value above 2
+        assert_eq!(max.partition_point(|&max| max <= 2), 1);
+    }
+
+    #[test]
+    fn matches_a_vec() {
+        let mut seed = 99u64;
+        let mut random = move |below: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % below
+        };
+        for len in [1, 2, 3, 7, 64, 100] {
+            let mut model: Vec<i64> = (0..len).map(|_| random(50) as i64).collect();
+            let mut tree: SegmentTree<i64, Sum> = SegmentTree::build_from(model.clone());
+            for step in 0..2_000 {
+                let a = random(len as u64) as usize;
+                let b = random(len as u64 + 1) as usize;
+                let (start, end) = (a.min(b), a.max(b));
+                let value = random(20) as i64;
+                match random(4) {
+                    0 => {
+                        tree.update(a, value);
+                        model[a] = value;
+                    }
+                    1 => {
+                        tree.update_range(start..end, Update::Add(value));
+                        model[start..end].iter_mut().for_each(|v| *v += value);
+                    }
+                    2 => {
+                        tree.update_range(start..end, Update::Assign(value));
+                        model[start..end].fill(value);
+                    }
+                    _ => {
+                        let total = model[..a].iter().sum::<i64>() + value;
+                        let expected = model
+                            .iter()
+                            .scan(0, |sum, v| {
+                                *sum += v;
+                                Some(*sum)
+                            })
+                            .take_while(|&sum| sum <= total)
+                            .count();
+                        assert_eq!(tree.partition_point(|&sum| sum <= total), expected);
+                    }
+                }
+                assert_eq!(
+                    tree.query(start..end),
+                    model[start..end].iter().sum::<i64>()
+                );
+                if step % 100 == 0 {
+                    assert_eq!(tree.validate(), Ok(()));
+                }
+            }
+        }
+    }
+
+    // string concatenation has no range update.
+    #[derive(Default)]
+    struct Concat;
+
+    impl Monoid<String> for Concat {
+        type Update = Infallible;
+
+        fn identity(&self) -> String {
+            String::new()
+        }
+
+        fn combine(&self, a: &String, b: &String) -> String {
+            a.clone() + b
+        }
+
+        fn apply(&self, update: &Infallible, _: &String, _: usize) -> String {
+            match *update {}
+        }
+
+        fn compose(&self, first: &Infallible, _: &Infallible) -> Infallible {
+            match *first {}
+        }
+    }
+
+    #[test]
+    fn a_monoid_that_doesnt_commute() {
+        let mut tree: SegmentTree<String, Concat> =
+            SegmentTree::build_from("abcde".chars().map(String::from));
+        assert_eq!(tree.query(1..4), "bcd");
+        tree.update(2, "X".into());
+        assert_eq!(tree.query(..), "abXde");
+        assert_eq!(tree.partition_point(|s| !s.contains('X')), 2);
+    }
+
+    #[test]
+    fn validate_points_at_the_broken_node() {
+        let mut tree: SegmentTree<i64, Sum> = SegmentTree::build_from([1, 2, 3, 4]);
+        // node 3 covers the right half
+        tree.tree[3] = 0;
+        assert_eq!(tree.validate().unwrap_err().path, [1]);
+        tree.tree[3] = 7;
+        tree.tree[1] = 0;
+        assert_eq!(
+            tree.validate().unwrap_err(),
+            InvariantViolation::at_root(Rule::Aggregate)
+        );
+    }
+}
@@ -0,0 +1,83 @@
+/*
+ *  Serde support, behind the `serde` feature
+ *
+ *  A tree is written as the sequence of its values in
+ *  order, so the output doesn't depend on the shape.
+ *  Loading rebuilds a balanced tree from that sequence.
+ */
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use crate::{Bst, Compare};
+
+impl<T: Serialize, C> Serialize for Bst<T, C> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de, T, C> Deserialize<'de> for Bst<T, C>
+where
+    T: Deserialize<'de>,
+    C: Compare<T> + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(BstVisitor(PhantomData))
+    }
+}
+
+struct BstVisitor<T, C>(PhantomData<(T, C)>);
+
+impl<'de, T, C> Visitor<'de> for BstVisitor<T, C>
+where
+    T: Deserialize<'de>,
+    C: Compare<T> + Default,
+{
+    type Value = Bst<T, C>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a sequence of tree values")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        // input written by `serialize` is already sorted,
+        // which makes this sort a single linear pass.
+        Ok(values.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Bst;
+
+    #[test]
+    fn json_round_trip() {
+        let mut tree = Bst::from_value(1);
+        for value in 2..50 {
+            tree.insert(value);
+        }
+        let json = serde_json::to_string(&tree).unwrap();
+        assert!(json.starts_with("[1,2,3,"));
+
+        let back: Bst<i32> = serde_json::from_str(&json).unwrap();
+        assert!(back.iter().eq(tree.iter()));
+        // the degenerate input chain comes back balanced
+        assert_eq!(back.to_string().lines().count(), 49);
+        assert!(back.pretty().top_down().to_string().lines().count() <= 2 * 6);
+    }
+
+    #[test]
+    fn unsorted_input_is_sorted_on_load() {
+        let back: Bst<i32> = serde_json::from_str("[3, 1, 2]").unwrap();
+        assert_eq!(back.iter().copied().collect::<Vec<_>>(), [1, 2, 3]);
+        assert!(serde_json::from_str::<Bst<i32>>("{\"a\": 1}").is_err());
+    }
+}
@@ -0,0 +1,465 @@
+/*
+ *  Set algebra
+ *
+ *  The lazy iterators walk both trees in order at the
+ *  same time, like the merge step of merge sort. Every
+ *  distinct value comes with its number of copies, and
+ *  the operations follow multiset rules: a union keeps
+ *  the larger count, an intersection the smaller one,
+ *  and a difference subtracts. Trees that reject
+ *  duplicates only ever hold one copy, so for them this
+ *  is plain set algebra.
+ */
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::marker::PhantomData;
+use std::ops::{BitAnd, BitOr, BitXor, Sub};
+
+use crate::range::Runs;
+use crate::{Bst, Compare, Link, Natural, Nodes};
+
+enum Merged<V> {
+    Left(V, usize),
+    Right(V, usize),
+    Both(V, usize, usize),
+}
+
+// pairs up two in-order streams of values and counts.
+// the streams yield either `T` or `&T`, ordered by `C`.
+struct MergeJoin<'c, T, C, L: Iterator, R: Iterator> {
+    left: Peekable<L>,
+    right: Peekable<R>,
+    cmp: &'c C,
+    value: PhantomData<fn(&T)>,
+}
+
+impl<'c, T, C, L: Iterator, R: Iterator> MergeJoin<'c, T, C, L, R> {
+    fn new(left: L, right: R, cmp: &'c C) -> Self {
+        Self {
+            left: left.peekable(),
+            right: right.peekable(),
+            cmp,
+            value: PhantomData,
+        }
+    }
+}
+
+impl<T, C, V, L, R> Iterator for MergeJoin<'_, T, C, L, R>
+where
+    C: Compare<T>,
+    V: Borrow<T>,
+    L: Iterator<Item = (V, usize)>,
+    R: Iterator<Item = (V, usize)>,
+{
+    type Item = Merged<V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ord = match (self.left.peek(), self.right.peek()) {
+            (Some((left, _)), Some((right, _))) => self.cmp.compare(left.borrow(), right.borrow()),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return None,
+        };
+        Some(match ord {
+            Ordering::Less => {
+                let (value, count) = self.left.next()?;
+                Merged::Left(value, count)
+            }
+            Ordering::Greater => {
+                let (value, count) = self.right.next()?;
+                Merged::Right(value, count)
+            }
+            Ordering::Equal => {
+                let (value, left) = self.left.next()?;
+                let (_, right) = self.right.next()?;
+                Merged::Both(value, left, right)
+            }
+        })
+    }
+}
+
+fn union<V>(merged: Merged<V>) -> Option<(V, usize)> {
+    match merged {
+        Merged::Left(value, count) | Merged::Right(value, count) => Some((value, count)),
+        Merged::Both(value, left, right) => Some((value, left.max(right))),
+    }
+}
+
+fn intersection<V>(merged: Merged<V>) -> Option<(V, usize)> {
+    match merged {
+        Merged::Both(value, left, right) => Some((value, left.min(right))),
+        _ => None,
+    }
+}
+
+fn difference<V>(merged: Merged<V>) -> Option<(V, usize)> {
+    match merged {
+        Merged::Left(value, count) => Some((value, count)),
+        Merged::Both(value, left, right) if left > right => Some((value, left - right)),
+        _ => None,
+    }
+}
+
+fn symmetric_difference<V>(merged: Merged<V>) -> Option<(V, usize)> {
+    match merged {
+        Merged::Left(value, count) | Merged::Right(value, count) => Some((value, count)),
+        Merged::Both(value, left, right) if left != right => Some((value, left.abs_diff(right))),
+        Merged::Both(..) => None,
+    }
+}
+
+type Keep<V> = fn(Merged<V>) -> Option<(V, usize)>;
+
+// expands the kept runs back into one item per copy.
+struct Lazy<'a, T, C> {
+    merged: MergeJoin<'a, T, C, Runs<'a, T>, Runs<'a, T>>,
+    keep: Keep<&'a T>,
+    run: Option<(&'a T, usize)>,
+}
+
+impl<'a, T, C: Compare<T>> Lazy<'a, T, C> {
+    fn new(left: &'a Bst<T, C>, right: &'a Bst<T, C>, keep: Keep<&'a T>) -> Self {
+        Self {
+            merged: MergeJoin::new(left.runs(), right.runs(), &left.cmp),
+            keep,
+            run: None,
+        }
+    }
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            if let Some((value, left @ 1..)) = &mut self.run {
+                *left -= 1;
+                return Some(*value);
+            }
+            self.run = Some(self.merged.find_map(self.keep)?);
+        }
+    }
+}
+
+pub struct Union<'a, T, C = Natural>(Lazy<'a, T, C>);
+pub struct Intersection<'a, T, C = Natural>(Lazy<'a, T, C>);
+pub struct Difference<'a, T, C = Natural>(Lazy<'a, T, C>);
+pub struct SymmetricDifference<'a, T, C = Natural>(Lazy<'a, T, C>);
+
+impl<'a, T, C: Compare<T>> Iterator for Union<'a, T, C> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl<'a, T, C: Compare<T>> Iterator for Intersection<'a, T, C> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl<'a, T, C: Compare<T>> Iterator for Difference<'a, T, C> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl<'a, T, C: Compare<T>> Iterator for SymmetricDifference<'a, T, C> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+// both trees are taken to be ordered the same way, and
+// results are ordered by the comparator of `self`.
+impl<T, C> Bst<T, C>
+where
+    C: Compare<T>,
+{
+    pub fn union<'a>(&'a self, other: &'a Self) -> Union<'a, T, C> {
+        Union(Lazy::new(self, other, union))
+    }
+
+    pub fn intersection<'a>(&'a self, other: &'a Self) -> Intersection<'a, T, C> {
+        Intersection(Lazy::new(self, other, intersection))
+    }
+
+    pub fn difference<'a>(&'a self, other: &'a Self) -> Difference<'a, T, C> {
+        Difference(Lazy::new(self, other, difference))
+    }
+
+    pub fn symmetric_difference<'a>(&'a self, other: &'a Self) -> SymmetricDifference<'a, T, C> {
+        SymmetricDifference(Lazy::new(self, other, symmetric_difference))
+    }
+
+    // merges two owned trees, moving every kept value
+    // into a new balanced tree with `self`'s policy.
+    fn combine(mut self, mut other: Self, keep: Keep<T>) -> Self {
+        let left = Nodes::new(self.root.take()).map(|node| (node.value, node.count));
+        let right = Nodes::new(other.root.take()).map(|node| (node.value, node.count));
+        let runs = MergeJoin::new(left, right, &self.cmp)
+            .filter_map(keep)
+            .collect();
+        self.replace_with_runs(runs);
+        self
+    }
+
+    // builds a new tree out of borrowed values.
+    fn collect_runs<'a>(&'a self, other: &'a Self, keep: Keep<&'a T>) -> Self
+    where
+        T: Clone,
+        C: Clone,
+    {
+        let runs = MergeJoin::new(self.runs(), other.runs(), &self.cmp)
+            .filter_map(keep)
+            .map(|(value, count)| (value.clone(), count))
+            .collect();
+        Self::from_sorted_runs(runs, self.policy, self.cmp.clone())
+    }
+
+    /// Splits the tree into the values less than `key` and
+    /// the values greater than or equal to it. Nodes are
+    /// moved between the trees, never cloned.
+    pub fn split<Q>(mut self, key: &Q) -> (Self, Self)
+    where
+        T: Borrow<Q>,
+        C: Compare<Q> + Clone,
+        Q: ?Sized,
+    {
+        let (left, right) = Self::split_link(&self.cmp, self.root.take(), key);
+        let mut less = Self::with_policy_and_comparator(self.policy, self.cmp.clone());
+        less.root = left;
+        self.root = right;
+        (less, self)
+    }
+
+    // walks down the search path for `key`, sorting its
+    // nodes into the two sides, then relinks each side
+    // bottom-up so the sizes can be fixed on the way.
+    fn split_link<Q>(cmp: &C, mut link: Link<T>, key: &Q) -> (Link<T>, Link<T>)
+    where
+        T: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        let mut less_path = Vec::new();
+        let mut rest_path = Vec::new();
+        while let Some(mut node) = link {
+            if cmp.compare(node.value.borrow(), key).is_lt() {
+                link = node.right.take();
+                less_path.push(node);
+            } else {
+                link = node.left.take();
+                rest_path.push(node);
+            }
+        }
+
+        let mut less = None;
+        for mut node in less_path.into_iter().rev() {
+            node.right = less;
+            node.fix_size();
+            less = Some(node);
+        }
+        let mut rest = None;
+        for mut node in rest_path.into_iter().rev() {
+            node.left = rest;
+            node.fix_size();
+            rest = Some(node);
+        }
+        (less, rest)
+    }
+
+    /// Joins two trees where every value in `left` is less
+    /// than every value in `right`, the inverse of `split`.
+    /// The result keeps the policy of `left`.
+    pub fn join(mut left: Self, mut right: Self) -> Self {
+        debug_assert!(
+            match (left.iter().next_back(), right.iter().next()) {
+                (Some(max), Some(min)) => left.cmp.compare(max, min).is_lt(),
+                _ => true,
+            },
+            "every value in `left` must be < every value in `right`"
+        );
+        let Some(mut root) = Self::pop_max(&mut left.root) else {
+            right.policy = left.policy;
+            return right;
+        };
+        root.left = left.root.take();
+        root.right = right.root.take();
+        root.fix_size();
+        left.root = Some(root);
+        left
+    }
+}
+
+impl<T, C: Compare<T>> BitOr for Bst<T, C> {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.combine(rhs, union)
+    }
+}
+
+impl<T, C: Compare<T>> BitAnd for Bst<T, C> {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        self.combine(rhs, intersection)
+    }
+}
+
+impl<T, C: Compare<T>> Sub for Bst<T, C> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self.combine(rhs, difference)
+    }
+}
+
+impl<T, C: Compare<T>> BitXor for Bst<T, C> {
+    type Output = Self;
+
+    fn bitxor(self, rhs: Self) -> Self {
+        self.combine(rhs, symmetric_difference)
+    }
+}
+
+impl<T, C> BitOr<&Bst<T, C>> for &Bst<T, C>
+where
+    T: Clone,
+    C: Compare<T> + Clone,
+{
+    type Output = Bst<T, C>;
+
+    fn bitor(self, rhs: &Bst<T, C>) -> Bst<T, C> {
+        self.collect_runs(rhs, union)
+    }
+}
+
+impl<T, C> BitAnd<&Bst<T, C>> for &Bst<T, C>
+where
+    T: Clone,
+    C: Compare<T> + Clone,
+{
+    type Output = Bst<T, C>;
+
+    fn bitand(self, rhs: &Bst<T, C>) -> Bst<T, C> {
+        self.collect_runs(rhs, intersection)
+    }
+}
+
+impl<T, C> Sub<&Bst<T, C>> for &Bst<T, C>
+where
+    T: Clone,
+    C: Compare<T> + Clone,
+{
+    type Output = Bst<T, C>;
+
+    fn sub(self, rhs: &Bst<T, C>) -> Bst<T, C> {
+        self.collect_runs(rhs, difference)
+    }
+}
+
+impl<T, C> BitXor<&Bst<T, C>> for &Bst<T, C>
+where
+    T: Clone,
+    C: Compare<T> + Clone,
+{
+    type Output = Bst<T, C>;
+
+    fn bitxor(self, rhs: &Bst<T, C>) -> Bst<T, C> {
+        self.collect_runs(rhs, symmetric_difference)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Bst, Link};
+
+    fn check_sizes<T>(link: &Link<T>) -> usize {
+        match link {
+            None => 0,
+            Some(node) => {
+                let size = node.count + check_sizes(&node.left) + check_sizes(&node.right);
+                assert_eq!(node.size, size);
+                size
+            }
+        }
+    }
+
+    fn values(tree: &Bst<i32>) -> Vec<i32> {
+        tree.iter().copied().collect()
+    }
+
+    #[test]
+    fn lazy_iterators() {
+        let a: Bst<i32> = [1, 3, 5, 7, 9].into_iter().collect();
+        let b: Bst<i32> = [3, 4, 5, 6].into_iter().collect();
+
+        let collect = |it: &mut dyn Iterator<Item = &i32>| it.copied().collect::<Vec<_>>();
+        assert_eq!(collect(&mut a.union(&b)), [1, 3, 4, 5, 6, 7, 9]);
+        assert_eq!(collect(&mut a.intersection(&b)), [3, 5]);
+        assert_eq!(collect(&mut a.difference(&b)), [1, 7, 9]);
+        assert_eq!(collect(&mut b.difference(&a)), [4, 6]);
+        assert_eq!(collect(&mut a.symmetric_difference(&b)), [1, 4, 6, 7, 9]);
+    }
+
+    #[test]
+    fn duplicates_count_like_a_multiset() {
+        let a: Bst<i32> = [1, 1, 1, 2].into_iter().collect();
+        let b: Bst<i32> = [1, 2, 2].into_iter().collect();
+
+        assert_eq!(a.union(&b).count(), 5);
+        assert_eq!(a.intersection(&b).copied().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(a.difference(&b).copied().collect::<Vec<_>>(), [1, 1]);
+        assert_eq!(
+            a.symmetric_difference(&b).copied().collect::<Vec<_>>(),
+            [1, 1, 2]
+        );
+
+        let owned = a - b;
+        assert_eq!(owned.count(&1), 2);
+        assert_eq!(owned.len(), 2);
+    }
+
+    #[test]
+    fn operators_build_new_trees() {
+        let a: Bst<i32> = (0..10).collect();
+        let b: Bst<i32> = (5..15).collect();
+
+        assert_eq!(values(&(&a | &b)), (0..15).collect::<Vec<_>>());
+        assert_eq!(values(&(&a & &b)), (5..10).collect::<Vec<_>>());
+        assert_eq!(values(&(&a - &b)), (0..5).collect::<Vec<_>>());
+
+        let owned = a ^ b;
+        assert_eq!(owned.len(), 10);
+        check_sizes(&owned.root);
+        assert_eq!(values(&owned), (0..5).chain(10..15).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn split_and_join() {
+        let tree: Bst<i32> = (0..20).collect();
+
+        let (left, right) = tree.split(&7);
+        check_sizes(&left.root);
+        check_sizes(&right.root);
+        assert_eq!(values(&left), (0..7).collect::<Vec<_>>());
+        assert_eq!(values(&right), (7..20).collect::<Vec<_>>());
+
+        let (empty, all) = right.split(&0);
+        assert!(empty.is_empty());
+        assert_eq!(all.len(), 13);
+
+        let joined = Bst::join(left, all);
+        check_sizes(&joined.root);
+        assert_eq!(values(&joined), (0..20).collect::<Vec<_>>());
+        assert_eq!(joined.select(7), Some(&7));
+    }
+}
@@ -0,0 +1,278 @@
+/*
+ *  Trait impls that walk the whole tree
+ *
+ *  The derived versions of these recurse once per
+ *  level, which overflows the stack on a degenerate
+ *  tree, like the chain sorted input builds. These walk
+ *  the tree with loops and explicit stacks instead.
+ *
+ *  Equality, ordering and hashing look at the values in
+ *  order, like `BTreeSet`, so two trees holding the same
+ *  values are equal whatever their shapes.
+ *  `structurally_eq` also compares the shapes.
+ */
+
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+use crate::range::Children;
+use crate::{Bst, Node, Nodes};
+
+// a node that owns its children, which is all the
+// drop and clone loops below need. the other trees in
+// the crate implement it to share them.
+pub(crate) trait ChildrenMut: Children + Sized {
+    fn left_mut(&mut self) -> &mut Option<Box<Self>>;
+    fn right_mut(&mut self) -> &mut Option<Box<Self>>;
+}
+
+impl<T> ChildrenMut for Node<T> {
+    fn left_mut(&mut self) -> &mut Option<Box<Self>> {
+        &mut self.left
+    }
+
+    fn right_mut(&mut self) -> &mut Option<Box<Self>> {
+        &mut self.right
+    }
+}
+
+// rotates every left child up until the root has none,
+// then frees the root and moves on to its right child.
+// each node is rotated at most once, so this is O(n)
+// and needs no extra memory.
+pub(crate) fn drop_link<N: ChildrenMut>(mut link: Option<Box<N>>) {
+    while let Some(mut node) = link {
+        link = match node.left_mut().take() {
+            Some(mut left) => {
+                *node.left_mut() = left.right_mut().take();
+                *left.right_mut() = Some(node);
+                Some(left)
+            }
+            None => node.right_mut().take(),
+        };
+    }
+}
+
+// copies a tree post-order, so both children are copied
+// before their parent. `copy` makes a childless copy of
+// one node and finished subtrees wait in `done`.
+pub(crate) fn clone_link<N: ChildrenMut>(
+    root: Option<&N>,
+    mut copy: impl FnMut(&N) -> N,
+) -> Option<Box<N>> {
+    let mut stack: Vec<(&N, bool)> = root.map(|n| (n, false)).into_iter().collect();
+    let mut done: Vec<Box<N>> = Vec::new();
+    while let Some((node, expanded)) = stack.pop() {
+        if !expanded {
+            stack.push((node, true));
+            stack.extend(node.right().map(|n| (n, false)));
+            stack.extend(node.left().map(|n| (n, false)));
+            continue;
+        }
+        // the left child finished first, so the right
+        // one is on top.
+        let right = node.right().and_then(|_| done.pop());
+        let left = node.left().and_then(|_| done.pop());
+        let mut parent = Box::new(copy(node));
+        *parent.left_mut() = left;
+        *parent.right_mut() = right;
+        done.push(parent);
+    }
+    done.pop()
+}
+
+impl<T, C> Drop for Bst<T, C> {
+    fn drop(&mut self) {
+        drop_link(self.root.take());
+    }
+}
+
+// the nodes still waiting in the stack hold their
+// right subtrees.
+impl<T> Drop for Nodes<T> {
+    fn drop(&mut self) {
+        while let Some(mut node) = self.stack.pop() {
+            drop_link(node.right.take());
+        }
+    }
+}
+
+impl<T: Clone, C: Clone> Clone for Bst<T, C> {
+    fn clone(&self) -> Self {
+        let mut tree = Self::with_policy_and_comparator(self.policy, self.cmp.clone());
+        tree.root = clone_link(self.root.as_deref(), |node| Node {
+            value: node.value.clone(),
+            count: node.count,
+            size: node.size,
+            left: None,
+            right: None,
+        });
+        tree
+    }
+}
+
+impl<T, C> Bst<T, C> {
+    /// Whether both trees hold the same values in the same
+    /// shape, with the same duplicate policy.
+    pub fn structurally_eq(&self, other: &Self) -> bool
+    where
+        T: PartialEq,
+    {
+        if self.policy != other.policy || self.len() != other.len() {
+            return false;
+        }
+        let mut stack = vec![(&self.root, &other.root)];
+        while let Some(pair) = stack.pop() {
+            match pair {
+                (None, None) => {}
+                (Some(a), Some(b)) if a.value == b.value && a.count == b.count => {
+                    stack.push((&a.right, &b.right));
+                    stack.push((&a.left, &b.left));
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl<T: PartialEq, C> PartialEq for Bst<T, C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq, C> Eq for Bst<T, C> {}
+
+impl<T: PartialOrd, C> PartialOrd for Bst<T, C> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<T: Ord, C> Ord for Bst<T, C> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<T: Hash, C> Hash for Bst<T, C> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // the length keeps a tree from hashing like the
+        // same values split across two trees.
+        state.write_usize(self.len());
+        for value in self {
+            value.hash(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashSet;
+    use std::hash::{Hash, Hasher};
+
+    use crate::{Bst, Node};
+
+    const N: usize = 1_000_000;
+
+    // the tree inserting 0..n in order builds, put together
+    // directly since inserting into a chain is O(n) each.
+    fn chain(n: usize) -> Bst<usize> {
+        let mut root = None;
+        for value in (0..n).rev() {
+            let mut node = Node::leaf(value);
+            node.right = root;
+            node.fix_size();
+            root = Some(Box::new(node));
+        }
+        let mut tree = Bst::default();
+        tree.root = root;
+        tree
+    }
+
+    fn hash(tree: &Bst<usize>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        tree.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn a_million_node_chain() {
+        let mut tree = chain(N);
+        assert!(tree.insert(N));
+        assert!(tree.contains(&N));
+        assert_eq!(tree.len(), N + 1);
+        assert_eq!(tree.iter().next_back(), Some(&N));
+
+        let copy = tree.clone();
+        assert!(copy == tree);
+        assert_eq!(hash(&copy), hash(&tree));
+
+        assert_eq!(tree.remove(&N), Some(N));
+        assert!(copy != tree);
+
+        let (less, rest) = tree.split(&(N / 2));
+        assert_eq!(less.len(), N / 2);
+        assert_eq!(rest.len(), N / 2);
+        drop(copy);
+        drop(rest);
+        drop(less);
+    }
+
+    #[test]
+    fn rendering_a_long_chain() {
+        // the drawing of a chain grows with the square of its
+        // length, so this one is kept short and drawn on a
+        // stack far too small to recurse down it.
+        let (sideways, top_down) = std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(|| {
+                let sideways = chain(2_000).pretty().with_balance().to_string();
+                let top_down = chain(400).pretty().top_down().to_string();
+                (sideways, top_down)
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(sideways.lines().count(), 2_000);
+        assert_eq!(sideways.lines().last(), Some("0 [b=+1999]"));
+        assert!(sideways.starts_with(&format!("{}/-- 1999 [b=+0]\n", " ".repeat(4 * 1_999))));
+        assert_eq!(top_down.lines().count(), 2 * 400 - 1);
+        assert!(top_down.starts_with("0\n \\\n 1\n  \\\n  2\n"));
+    }
+
+    #[test]
+    fn dropping_a_half_used_into_iter() {
+        let mut values = chain(N).into_iter();
+        assert_eq!(values.next(), Some(0));
+    }
+
+    #[test]
+    fn equality_ignores_the_shape() {
+        let mut a = Bst::from_value(2usize);
+        a.insert(1);
+        let mut b = Bst::from_value(1);
+        b.insert(2);
+        assert!(a == b);
+        assert_eq!(hash(&a), hash(&b));
+        assert!(!a.structurally_eq(&b));
+        assert!(a.clone().structurally_eq(&a));
+
+        let mut set = HashSet::new();
+        set.insert(a);
+        assert!(!set.insert(b));
+
+        let three = chain(3);
+        assert!(three == [0, 1, 2].into_iter().collect());
+        assert_ne!(hash(&three), hash(&chain(4)));
+
+        // copies count, and trees order like their sequences
+        let mut more = Bst::from_value(0);
+        more.insert(0);
+        assert!(more != Bst::from_value(0));
+        assert!(Bst::from_value(0) < more);
+        assert!(more < three);
+    }
+}
@@ -0,0 +1,438 @@
+/*
+ *  Splay tree
+ *
+ *  Every lookup, insert and removal rotates the key it
+ *  reaches up to the root, so keys used often stay near
+ *  the top and a small hot set is found in a few steps.
+ *  Nothing is kept to bound the depth: one operation can
+ *  cost O(n), but any sequence of m of them costs
+ *  O(m log n).
+ *
+ *  The splay is the top-down one. On the way down the
+ *  nodes smaller than the key are set aside on one stack
+ *  and the larger ones on another, then both are hung
+ *  under the node that was reached, so nothing recurses.
+ */
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::RangeBounds;
+
+use crate::range::{after_start, before_end, Children, Span};
+use crate::shape::{drop_link, ChildrenMut};
+use crate::validate::{check_subtrees, order, InvariantViolation, Rule};
+use crate::Natural;
+
+type Link<K, V> = Option<Box<Node<K, V>>>;
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+impl<K, V> Children for Node<K, V> {
+    fn left(&self) -> Option<&Self> {
+        self.left.as_deref()
+    }
+
+    fn right(&self) -> Option<&Self> {
+        self.right.as_deref()
+    }
+}
+
+impl<K, V> ChildrenMut for Node<K, V> {
+    fn left_mut(&mut self) -> &mut Option<Box<Self>> {
+        &mut self.left
+    }
+
+    fn right_mut(&mut self) -> &mut Option<Box<Self>> {
+        &mut self.right
+    }
+}
+
+// brings the node holding `key` to the top of the tree,
+// or the last node met looking for it if there is none.
+fn splay<K, V, Q>(mut node: Box<Node<K, V>>, key: &Q) -> Box<Node<K, V>>
+where
+    K: Borrow<Q>,
+    Q: Ord + ?Sized,
+{
+    // nodes whose right (or left) child is still to be
+    // filled in by the next one on the same stack.
+    let mut smaller: Vec<Box<Node<K, V>>> = Vec::new();
+    let mut larger: Vec<Box<Node<K, V>>> = Vec::new();
+    loop {
+        match key.cmp(node.key.borrow()) {
+            Ordering::Less => {
+                let Some(mut left) = node.left.take() else {
+                    break;
+                };
+                // zig-zig, rotate before stepping down
+                if key < left.key.borrow() && left.left.is_some() {
+                    node.left = left.right.take();
+                    left.right = Some(node);
+                    node = left;
+                    left = node.left.take().unwrap();
+                }
+                larger.push(node);
+                node = left;
+            }
+            Ordering::Greater => {
+                let Some(mut right) = node.right.take() else {
+                    break;
+                };
+                if key > right.key.borrow() && right.right.is_some() {
+                    node.right = right.left.take();
+                    right.left = Some(node);
+                    node = right;
+                    right = node.right.take().unwrap();
+                }
+                smaller.push(node);
+                node = right;
+            }
+            Ordering::Equal => break,
+        }
+    }
+
+    let mut left = node.left.take();
+    while let Some(mut parent) = smaller.pop() {
+        parent.right = left;
+        left = Some(parent);
+    }
+    let mut right = node.right.take();
+    while let Some(mut parent) = larger.pop() {
+        parent.left = right;
+        right = Some(parent);
+    }
+    node.left = left;
+    node.right = right;
+    node
+}
+
+/// An ordered map that moves whatever it touches to the root.
+pub struct SplayTree<K, V> {
+    root: Link<K, V>,
+    len: usize,
+}
+
+impl<K, V> Default for SplayTree<K, V> {
+    fn default() -> Self {
+        Self { root: None, len: 0 }
+    }
+}
+
+impl<K, V> SplayTree<K, V> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> Range<'_, K, V> {
+        Range {
+            span: Span::new(self.root.as_deref(), |_| true, |_| true),
+        }
+    }
+}
+
+impl<K, V> SplayTree<K, V>
+where
+    K: Ord,
+{
+    // splays `key` to the root and says whether it is there.
+    fn splay_root<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let Some(root) = self.root.take() else {
+            return false;
+        };
+        let root = self.root.insert(splay(root, key));
+        root.key.borrow() == key
+    }
+
+    /// Looks `key` up and moves it to the root.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.get_mut(key).map(|value| &*value)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        if self.splay_root(key) {
+            self.root.as_mut().map(|root| &mut root.value)
+        } else {
+            None
+        }
+    }
+
+    pub fn contains_key<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.splay_root(key)
+    }
+
+    /// Looks `key` up without changing the tree.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut next = self.root.as_deref();
+        while let Some(node) = next {
+            next = match key.cmp(node.key.borrow()) {
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Greater => node.right.as_deref(),
+            };
+        }
+        None
+    }
+
+    /// Inserts `value` under `key`, returning the value it
+    /// replaced. The key ends up at the root.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if self.splay_root(&key) {
+            let root = self.root.as_mut().unwrap();
+            return Some(std::mem::replace(&mut root.value, value));
+        }
+        let mut node = Box::new(Node {
+            key,
+            value,
+            left: None,
+            right: None,
+        });
+        // the old root is the new key's neighbour, so one
+        // of its sides goes under the new root with it.
+        if let Some(mut root) = self.root.take() {
+            if node.key < root.key {
+                node.left = root.left.take();
+                node.right = Some(root);
+            } else {
+                node.right = root.right.take();
+                node.left = Some(root);
+            }
+        }
+        self.root = Some(node);
+        self.len += 1;
+        None
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        if !self.splay_root(key) {
+            return None;
+        }
+        let mut root = self.root.take().unwrap();
+        // everything on the left is smaller than `key`, so
+        // splaying it brings its largest node up with no
+        // right child, leaving room for the right side.
+        self.root = match root.left.take() {
+            None => root.right.take(),
+            Some(left) => {
+                let mut left = splay(left, key);
+                left.right = root.right.take();
+                Some(left)
+            }
+        };
+        self.len -= 1;
+        Some(root.value)
+    }
+
+    /// Checks that the keys are in order and that the
+    /// length matches the entries.
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        let (_, actual) = check_subtrees(
+            self.root.as_deref(),
+            || (None, 0),
+            |node, (left, left_len), (right, right_len)| {
+                let bounds = order(|a: &K, b: &K| a < b, left, &node.key, right)?;
+                Ok((Some(bounds), left_len + 1 + right_len))
+            },
+        )?;
+        if actual != self.len {
+            return Err(InvariantViolation::at_root(Rule::Len {
+                cached: self.len,
+                actual,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Returns the entries whose keys fall inside `range`,
+    /// in order, without changing the tree.
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        Range {
+            span: Span::new(
+                self.root.as_deref(),
+                |node| after_start(&Natural, range.start_bound(), node.key.borrow()),
+                |node| before_end(&Natural, range.end_bound(), node.key.borrow()),
+            ),
+        }
+    }
+}
+
+// inserting keys in order leaves a chain, so this is
+// taken apart with a loop.
+impl<K, V> Drop for SplayTree<K, V> {
+    fn drop(&mut self) {
+        drop_link(self.root.take());
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for SplayTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = Self::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for SplayTree<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for SplayTree<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a SplayTree<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Range<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Range<'a, K, V> {
+    span: Span<'a, Node<K, V>>,
+}
+
+impl<'a, K, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.span.next().map(|node| (&node.key, &node.value))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Range<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.span.next_back().map(|node| (&node.key, &node.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Bound;
+
+    fn depth_of<K: Ord, V>(tree: &SplayTree<K, V>, key: &K) -> Option<usize> {
+        let mut next = tree.root.as_deref();
+        let mut depth = 0;
+        while let Some(node) = next {
+            next = match key.cmp(&node.key) {
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Equal => return Some(depth),
+                Ordering::Greater => node.right.as_deref(),
+            };
+            depth += 1;
+        }
+        None
+    }
+
+    #[test]
+    fn lookups_move_keys_to_the_root() {
+        let mut tree: SplayTree<u32, u32> = (0..1000).map(|k| (k, k * 2)).collect();
+        // sorted inserts leave a chain with 0 at the bottom
+        assert_eq!(depth_of(&tree, &0), Some(999));
+        assert_eq!(tree.peek(&0), Some(&0));
+        assert_eq!(depth_of(&tree, &0), Some(999));
+
+        assert_eq!(tree.get(&0), Some(&0));
+        assert_eq!(depth_of(&tree, &0), Some(0));
+        // and splaying roughly halved the depth of the path
+        assert_eq!(depth_of(&tree, &1), Some(500));
+        assert_eq!(depth_of(&tree, &500), Some(250));
+        assert_eq!(tree.get(&1), Some(&2));
+        assert_eq!(depth_of(&tree, &0), Some(1));
+
+        assert_eq!(tree.get(&1000), None);
+        assert_eq!(tree.len(), 1000);
+        assert_eq!(tree.validate(), Ok(()));
+        assert!(tree.iter().map(|(k, _)| *k).eq(0..1000));
+    }
+
+    #[test]
+    fn insert_replace_remove() {
+        let mut tree = SplayTree::new();
+        assert_eq!(tree.insert("b", 1), None);
+        assert_eq!(tree.insert("a", 2), None);
+        assert_eq!(tree.insert("c", 3), None);
+        assert_eq!(tree.insert("b", 4), Some(1));
+        assert_eq!(tree.len(), 3);
+        assert_eq!(format!("{tree:?}"), r#"{"a": 2, "b": 4, "c": 3}"#);
+
+        assert_eq!(tree.remove("b"), Some(4));
+        assert_eq!(tree.remove("b"), None);
+        assert!(!tree.contains_key("b"));
+        if let Some(value) = tree.get_mut("c") {
+            *value += 10;
+        }
+        assert_eq!(tree.iter().collect::<Vec<_>>(), [(&"a", &2), (&"c", &13)]);
+        assert_eq!(tree.remove("a"), Some(2));
+        assert_eq!(tree.remove("c"), Some(13));
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn ranges() {
+        let tree: SplayTree<u32, ()> = [5, 1, 9, 3, 7].map(|k| (k, ())).into_iter().collect();
+        let keys = |r: Range<'_, u32, ()>| r.map(|(k, _)| *k).collect::<Vec<_>>();
+        assert_eq!(keys(tree.range(3..8)), [3, 5, 7]);
+        assert_eq!(
+            keys(tree.range((Bound::Excluded(5), Bound::Unbounded))),
+            [7, 9]
+        );
+        assert_eq!(tree.range(..=5).next_back(), Some((&5, &())));
+    }
+
+    #[test]
+    fn dropping_a_long_chain() {
+        let tree: SplayTree<u32, ()> = (0..1_000_000).map(|k| (k, ())).collect();
+        assert_eq!(tree.len(), 1_000_000);
+    }
+}
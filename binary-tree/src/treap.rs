@@ -0,0 +1,456 @@
+/*
+ *  Treap
+ *
+ *  A search tree on the keys and a max-heap on random
+ *  priorities at the same time. The shape is the one
+ *  inserting the keys in priority order would build, so
+ *  whatever order they really come in, the tree is as
+ *  deep as a random BST: O(log n) expected.
+ *
+ *  Everything is built on `split` and `join`. The
+ *  priorities come from a small seedable generator, so a
+ *  seeded treap builds the same shape on every run.
+ */
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::RangeBounds;
+
+use crate::range::{after_start, before_end, Children, Span};
+use crate::validate::{check_subtrees, order, InvariantViolation, Rule};
+use crate::Natural;
+
+type Link<K, V> = Option<Box<Node<K, V>>>;
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    priority: u64,
+    // number of entries in the subtree rooted here.
+    size: usize,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+impl<K, V> Children for Node<K, V> {
+    fn left(&self) -> Option<&Self> {
+        self.left.as_deref()
+    }
+
+    fn right(&self) -> Option<&Self> {
+        self.right.as_deref()
+    }
+}
+
+impl<K, V> Node<K, V> {
+    fn size(link: &Link<K, V>) -> usize {
+        link.as_ref().map_or(0, |node| node.size)
+    }
+
+    fn fix_size(&mut self) {
+        self.size = 1 + Self::size(&self.left) + Self::size(&self.right);
+    }
+}
+
+// splitmix64, which is fine with any seed, zero included.
+#[derive(Clone, Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+// splits a subtree into the keys less than `key` and the
+// rest.
+fn split_link<K, V, Q>(link: Link<K, V>, key: &Q) -> (Link<K, V>, Link<K, V>)
+where
+    K: Borrow<Q>,
+    Q: Ord + ?Sized,
+{
+    let Some(mut node) = link else {
+        return (None, None);
+    };
+    if node.key.borrow() < key {
+        let (less, rest) = split_link(node.right.take(), key);
+        node.right = less;
+        node.fix_size();
+        (Some(node), rest)
+    } else {
+        let (less, rest) = split_link(node.left.take(), key);
+        node.left = rest;
+        node.fix_size();
+        (less, Some(node))
+    }
+}
+
+// joins two subtrees where every key in `left` is less
+// than every key in `right`. the higher priority root
+// stays on top.
+fn join_links<K, V>(left: Link<K, V>, right: Link<K, V>) -> Link<K, V> {
+    match (left, right) {
+        (None, link) | (link, None) => link,
+        (Some(mut left), Some(mut right)) => {
+            if left.priority > right.priority {
+                left.right = join_links(left.right.take(), Some(right));
+                left.fix_size();
+                Some(left)
+            } else {
+                right.left = join_links(Some(left), right.left.take());
+                right.fix_size();
+                Some(right)
+            }
+        }
+    }
+}
+
+/// An ordered map kept balanced by random priorities.
+pub struct Treap<K, V> {
+    root: Link<K, V>,
+    rng: Rng,
+}
+
+impl<K, V> Default for Treap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> Treap<K, V> {
+    /// An empty treap seeded from the process's random state.
+    pub fn new() -> Self {
+        Self::with_seed(RandomState::new().build_hasher().finish())
+    }
+
+    /// An empty treap whose priorities are drawn from `seed`.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            root: None,
+            rng: Rng(seed),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        Node::size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn iter(&self) -> Range<'_, K, V> {
+        Range {
+            span: Span::new(self.root.as_deref(), |_| true, |_| true),
+        }
+    }
+
+    /// Joins two treaps where every key in `left` is less
+    /// than every key in `right`, the inverse of `split`.
+    /// The result keeps the generator of `left`.
+    pub fn join(mut left: Self, mut right: Self) -> Self
+    where
+        K: Ord,
+    {
+        debug_assert!(
+            match (left.iter().next_back(), right.iter().next()) {
+                (Some((max, _)), Some((min, _))) => max < min,
+                _ => true,
+            },
+            "every key in `left` must be < every key in `right`"
+        );
+        left.root = join_links(left.root.take(), right.root.take());
+        left
+    }
+}
+
+impl<K, V> Treap<K, V>
+where
+    K: Ord,
+{
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut next = self.root.as_deref();
+        while let Some(node) = next {
+            next = match key.cmp(node.key.borrow()) {
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Greater => node.right.as_deref(),
+            };
+        }
+        None
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut next = self.root.as_deref_mut();
+        while let Some(node) = next {
+            next = match key.cmp(node.key.borrow()) {
+                Ordering::Less => node.left.as_deref_mut(),
+                Ordering::Equal => return Some(&mut node.value),
+                Ordering::Greater => node.right.as_deref_mut(),
+            };
+        }
+        None
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Inserts `value` under `key`, returning the value it replaced.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(old) = self.get_mut(&key) {
+            return Some(std::mem::replace(old, value));
+        }
+        let node = Box::new(Node {
+            key,
+            value,
+            priority: self.rng.next(),
+            size: 1,
+            left: None,
+            right: None,
+        });
+        let (less, rest) = split_link(self.root.take(), &node.key);
+        self.root = join_links(join_links(less, Some(node)), rest);
+        None
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Self::remove_link(&mut self.root, key).map(|node| node.value)
+    }
+
+    // unlinks the node holding `key`, fixing the sizes on
+    // the way back up.
+    fn remove_link<Q>(link: &mut Link<K, V>, key: &Q) -> Option<Box<Node<K, V>>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let node = link.as_mut()?;
+        let removed = match key.cmp(node.key.borrow()) {
+            Ordering::Less => Self::remove_link(&mut node.left, key),
+            Ordering::Greater => Self::remove_link(&mut node.right, key),
+            Ordering::Equal => {
+                let mut node = link.take().unwrap();
+                *link = join_links(node.left.take(), node.right.take());
+                return Some(node);
+            }
+        };
+        node.size -= removed.is_some() as usize;
+        removed
+    }
+
+    /// Splits the treap into the keys less than `key` and
+    /// the keys greater than or equal to it. Both halves
+    /// keep drawing priorities from independent streams.
+    pub fn split<Q>(mut self, key: &Q) -> (Self, Self)
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (less, rest) = split_link(self.root.take(), key);
+        let mut right = Self::with_seed(self.rng.next());
+        right.root = rest;
+        self.root = less;
+        (self, right)
+    }
+
+    /// Checks that the keys are in order, that no child
+    /// outranks its parent, and that every cached size is
+    /// right.
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        check_subtrees(
+            self.root.as_deref(),
+            || (None, 0),
+            |node, (left, left_size), (right, right_size)| {
+                let bounds = order(|a: &K, b: &K| a < b, left, &node.key, right)?;
+                let mut children = [&node.left, &node.right].into_iter().flatten();
+                if children.any(|child| child.priority > node.priority) {
+                    return Err(Rule::Priority);
+                }
+                let actual = left_size + 1 + right_size;
+                if node.size != actual {
+                    return Err(Rule::Size {
+                        cached: node.size,
+                        actual,
+                    });
+                }
+                Ok((Some(bounds), actual))
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Returns the entries whose keys fall inside `range`, in order.
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        Range {
+            span: Span::new(
+                self.root.as_deref(),
+                |node| after_start(&Natural, range.start_bound(), node.key.borrow()),
+                |node| before_end(&Natural, range.end_bound(), node.key.borrow()),
+            ),
+        }
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for Treap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut treap = Self::new();
+        treap.extend(iter);
+        treap
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for Treap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Treap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a Treap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Range<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Range<'a, K, V> {
+    span: Span<'a, Node<K, V>>,
+}
+
+impl<'a, K, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.span.next().map(|node| (&node.key, &node.value))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Range<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.span.next_back().map(|node| (&node.key, &node.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // checks the heap order and sizes, returning the depth.
+    fn check<K: Ord, V>(link: &Link<K, V>) -> usize {
+        let Some(node) = link else {
+            return 0;
+        };
+        for child in [&node.left, &node.right].into_iter().flatten() {
+            assert!(child.priority <= node.priority);
+        }
+        assert_eq!(
+            node.size,
+            1 + Node::size(&node.left) + Node::size(&node.right)
+        );
+        1 + check(&node.left).max(check(&node.right))
+    }
+
+    fn keys<V>(treap: &Treap<u32, V>) -> Vec<u32> {
+        treap.iter().map(|(k, _)| *k).collect()
+    }
+
+    #[test]
+    fn sorted_inserts_stay_shallow() {
+        let mut treap = Treap::with_seed(1);
+        for key in 0..10_000u32 {
+            assert_eq!(treap.insert(key, key), None);
+        }
+        // a random BST of 10k keys is around 30 deep
+        assert!(check(&treap.root) < 60);
+        assert_eq!(treap.len(), 10_000);
+        assert_eq!(treap.insert(5, 50), Some(5));
+        assert_eq!(treap.get(&5), Some(&50));
+
+        for key in (0..10_000).step_by(2) {
+            assert!(treap.remove(&key).is_some());
+        }
+        check(&treap.root);
+        assert_eq!(treap.len(), 5000);
+        assert_eq!(treap.remove(&0), None);
+        assert!(treap.range(10..16).map(|(k, _)| *k).eq([11, 13, 15]));
+    }
+
+    #[test]
+    fn a_seed_fixes_the_shape() {
+        // the keys in pre-order, which pins down the shape
+        let build = |seed| {
+            let mut treap = Treap::with_seed(seed);
+            treap.extend((0..100u32).map(|k| (k, ())));
+            assert_eq!(treap.validate(), Ok(()));
+            let mut shape = Vec::new();
+            let mut stack: Vec<&Node<u32, ()>> = treap.root.as_deref().into_iter().collect();
+            while let Some(node) = stack.pop() {
+                shape.push(node.key);
+                stack.extend(node.right.as_deref());
+                stack.extend(node.left.as_deref());
+            }
+            shape
+        };
+        assert_eq!(build(7), build(7));
+        assert_eq!(build(0), build(0));
+    }
+
+    #[test]
+    fn split_and_join() {
+        let treap: Treap<u32, char> = (0..26).map(|k| (k, (b'a' + k as u8) as char)).collect();
+        let (less, rest) = treap.split(&10);
+        check(&less.root);
+        check(&rest.root);
+        assert_eq!(keys(&less), (0..10).collect::<Vec<_>>());
+        assert_eq!(keys(&rest), (10..26).collect::<Vec<_>>());
+        assert_eq!(rest.len(), 16);
+
+        let (mut middle, mut high) = rest.split(&20);
+        assert_eq!(middle.insert(15, 'x'), Some('p'));
+        high.insert(30, 'z');
+        let joined = Treap::join(Treap::join(less, middle), high);
+        check(&joined.root);
+        assert_eq!(joined.validate(), Ok(()));
+        assert_eq!(joined.len(), 27);
+        assert_eq!(joined.get(&15), Some(&'x'));
+        assert_eq!(joined.iter().next_back(), Some((&30, &'z')));
+    }
+}
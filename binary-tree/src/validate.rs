@@ -0,0 +1,283 @@
+/*
+ *  Invariant checks
+ *
+ *  Every tree in the crate has a `validate` method that
+ *  walks the whole structure and checks what its own
+ *  operations rely on: the order of the keys, and the
+ *  sizes, heights, priorities or fill levels it caches
+ *  or keeps balanced. A failure names the rule and the
+ *  node that broke it, found by its path from the root.
+ *
+ *  These are O(n) and meant for tests and fuzzing, not
+ *  for the hot path.
+ */
+
+use std::error::Error;
+use std::fmt;
+
+use crate::range::Children;
+use crate::{Bst, Compare, DuplicatePolicy};
+
+/// A rule of a tree's structure that some node breaks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Rule {
+    /// A key is not after everything in its left subtree
+    /// and before everything in its right one.
+    Order,
+    /// The subtree size cached on the node is wrong.
+    Size { cached: usize, actual: usize },
+    /// The subtree height cached on the node is wrong.
+    Height { cached: usize, actual: usize },
+    /// The node's subtrees are further out of balance than
+    /// the tree allows.
+    Balance { left: usize, right: usize },
+    /// A `Bst` node holds no copies, or more than one in a
+    /// tree whose policy doesn't count them.
+    Count(usize),
+    /// The largest end cached on an interval tree node is wrong.
+    MaxEnd,
+    /// A treap node has a higher priority than its parent.
+    Priority,
+    /// A B-tree node holds too few or too many keys.
+    KeyCount(usize),
+    /// A B-tree node has the wrong number of children, or
+    /// its leaves aren't all at the same depth.
+    Shape,
+    /// The length the tree keeps disagrees with what it holds.
+    Len { cached: usize, actual: usize },
+    /// A segment tree node's aggregate isn't the fold of its
+    /// children's.
+    Aggregate,
+    /// A heap entry doesn't know where in the heap it sits.
+    Position,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::Order => write!(f, "key is out of order with its subtrees"),
+            Rule::Size { cached, actual } => {
+                write!(f, "cached size is {cached} but the subtree holds {actual}")
+            }
+            Rule::Height { cached, actual } => {
+                write!(
+                    f,
+                    "cached height is {cached} but the subtree is {actual} deep"
+                )
+            }
+            Rule::Balance { left, right } => {
+                write!(f, "subtrees of {left} and {right} are out of balance")
+            }
+            Rule::Count(count) => write!(f, "node holds {count} copies"),
+            Rule::MaxEnd => write!(f, "cached max end is wrong"),
+            Rule::Priority => write!(f, "a child outranks its parent"),
+            Rule::KeyCount(len) => write!(f, "node holds {len} keys"),
+            Rule::Shape => write!(f, "children don't match the keys or leaves are uneven"),
+            Rule::Len { cached, actual } => {
+                write!(f, "length is {cached} but the tree holds {actual}")
+            }
+            Rule::Aggregate => write!(f, "cached aggregate is wrong"),
+            Rule::Position => write!(f, "cached heap position is wrong"),
+        }
+    }
+}
+
+/// Where a tree broke one of its rules.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvariantViolation {
+    /// The child taken at each step down from the root to
+    /// the offending node. In a binary tree 0 is left and
+    /// 1 is right. Empty for the root, or the tree itself.
+    pub path: Vec<usize>,
+    pub rule: Rule,
+}
+
+impl InvariantViolation {
+    pub(crate) fn at_root(rule: Rule) -> Self {
+        Self {
+            path: Vec::new(),
+            rule,
+        }
+    }
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "node at {:?}: {}", self.path, self.rule)
+    }
+}
+
+impl Error for InvariantViolation {}
+
+// the smallest and largest key of a subtree, if it has any.
+pub(crate) type Bounds<'a, K> = Option<(&'a K, &'a K)>;
+
+// checks that `key` sits between its subtrees, and returns
+// the bounds of the subtree it heads. `before` says whether
+// one key may come before another, so a tree that allows
+// equal keys passes `<=`.
+pub(crate) fn order<'a, K: ?Sized>(
+    before: impl Fn(&K, &K) -> bool,
+    left: Bounds<'a, K>,
+    key: &'a K,
+    right: Bounds<'a, K>,
+) -> Result<(&'a K, &'a K), Rule> {
+    let after_left = left.is_none_or(|(_, max)| before(max, key));
+    let before_right = right.is_none_or(|(min, _)| before(key, min));
+    if !(after_left && before_right) {
+        return Err(Rule::Order);
+    }
+    Ok((
+        left.map_or(key, |(min, _)| min),
+        right.map_or(key, |(_, max)| max),
+    ))
+}
+
+// folds a binary tree from the leaves up, handing `check`
+// each node with what it returned for the two children,
+// or `empty` for a missing one. the walk keeps its own
+// stack, so a degenerate tree is fine, and that stack
+// is also the path to a node that fails.
+pub(crate) fn check_subtrees<'a, N: Children, R>(
+    root: Option<&'a N>,
+    empty: impl Fn() -> R,
+    mut check: impl FnMut(&'a N, R, R) -> Result<R, Rule>,
+) -> Result<R, InvariantViolation> {
+    let Some(root) = root else {
+        return Ok(empty());
+    };
+    // each node with how many of its children were entered.
+    let mut stack: Vec<(&'a N, usize)> = vec![(root, 0)];
+    let mut done: Vec<R> = Vec::new();
+    while let Some(top) = stack.last_mut() {
+        let (node, entered) = *top;
+        if entered < 2 {
+            top.1 += 1;
+            let child = if entered == 0 {
+                node.left()
+            } else {
+                node.right()
+            };
+            match child {
+                Some(child) => stack.push((child, 0)),
+                None => done.push(empty()),
+            }
+            continue;
+        }
+        let right = done.pop().unwrap();
+        let left = done.pop().unwrap();
+        match check(node, left, right) {
+            Ok(summary) => {
+                stack.pop();
+                done.push(summary);
+            }
+            Err(rule) => {
+                stack.pop();
+                let path = stack.iter().map(|&(_, entered)| entered - 1).collect();
+                return Err(InvariantViolation { path, rule });
+            }
+        }
+    }
+    Ok(done.pop().unwrap())
+}
+
+impl<T, C: Compare<T>> Bst<T, C> {
+    /// Checks that the values are in order, that every
+    /// cached size is right, and that each node holds as
+    /// many copies as the duplicate policy allows.
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        let less = |a: &T, b: &T| self.cmp.compare(a, b).is_lt();
+        check_subtrees(
+            self.root.as_deref(),
+            || (None, 0),
+            |node, (left, left_size), (right, right_size)| {
+                let counted = self.policy == DuplicatePolicy::Count;
+                if node.count == 0 || (!counted && node.count > 1) {
+                    return Err(Rule::Count(node.count));
+                }
+                let bounds = order(less, left, &node.value, right)?;
+                let actual = node.count + left_size + right_size;
+                if node.size != actual {
+                    return Err(Rule::Size {
+                        cached: node.size,
+                        actual,
+                    });
+                }
+                Ok((Some(bounds), actual))
+            },
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Node;
+
+    #[test]
+    fn a_healthy_tree_passes() {
+        let mut tree: Bst<u32> = (0..100).map(|v| v * 7 % 100).collect();
+        tree.insert(5);
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(Bst::<u32>::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn reports_the_node_and_the_rule() {
+        let mut tree = Bst::from_value(4u32);
+        for value in [2, 6, 1, 3] {
+            tree.insert(value);
+        }
+        // 3 is the right child of 2, which is left of the root
+        tree.root
+            .as_mut()
+            .unwrap()
+            .left
+            .as_mut()
+            .unwrap()
+            .right
+            .as_mut()
+            .unwrap()
+            .value = 0;
+        assert_eq!(
+            tree.validate(),
+            Err(InvariantViolation {
+                path: vec![0],
+                rule: Rule::Order,
+            })
+        );
+
+        let mut tree = Bst::from_value(4u32);
+        tree.insert(2);
+        tree.insert(6);
+        tree.root.as_mut().unwrap().right.as_mut().unwrap().size = 3;
+        let err = tree.validate().unwrap_err();
+        assert_eq!(err.path, [1]);
+        assert_eq!(
+            err.to_string(),
+            "node at [1]: cached size is 3 but the subtree holds 1"
+        );
+
+        let mut tree = Bst::with_policy(DuplicatePolicy::Reject);
+        tree.insert(1);
+        let root = tree.root.as_mut().unwrap();
+        root.count = 2;
+        root.size = 2;
+        assert_eq!(tree.validate().unwrap_err().rule, Rule::Count(2));
+    }
+
+    #[test]
+    fn walks_a_long_chain() {
+        let mut root = None;
+        for value in (0..1_000_000u32).rev() {
+            let mut node = Node::leaf(value);
+            node.right = root;
+            node.fix_size();
+            root = Some(Box::new(node));
+        }
+        let mut tree = Bst::default();
+        tree.root = root;
+        assert_eq!(tree.validate(), Ok(()));
+    }
+}
@@ -0,0 +1,694 @@
+/*
+ *  MyArc and MyWeak
+ *
+ *  `MyRc` with atomic counts, so the references can be
+ *  shared between threads. The layout and the counting
+ *  are the same: the strong references share one weak
+ *  reference, and whoever lets go of the last weak one
+ *  frees the memory.
+ *
+ *  What's new is the memory ordering. A thread's last use
+ *  of the value has to happen before another thread drops
+ *  it. So every decrement is a `Release`, and whoever sees
+ *  a count reach zero puts an `Acquire` fence in front of
+ *  the drop or free that follows. Increments only need
+ *  the reference they were made from to stay alive, so
+ *  they are `Relaxed`.
+ *
+ *  `get_mut` has to be sure nobody can upgrade a weak
+ *  reference while it checks the strong count, so it
+ *  briefly locks the weak count by setting it to
+ *  `usize::MAX`, and `downgrade` waits that out.
+ *
+ *  A count that passes `isize::MAX` aborts the process,
+ *  like std's. It could only get there by leaking
+ *  references, and wrapping around would free the value
+ *  while they are in use.
+ *
+ *  The tests at the bottom run every interleaving of a few
+ *  small programs under loom:
+ *
+ *      RUSTFLAGS="--cfg loom" cargo test -p smart-pointers --release arc
+ */
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::ptr::{self, NonNull};
+
+#[cfg(loom)]
+use loom::hint::spin_loop;
+#[cfg(loom)]
+use loom::sync::atomic::{fence, AtomicUsize, Ordering};
+#[cfg(not(loom))]
+use std::hint::spin_loop;
+#[cfg(not(loom))]
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use crate::MyBox;
+
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+// the weak count while `get_mut` holds it still.
+const LOCKED: usize = usize::MAX;
+
+struct ArcInner<T> {
+    strong: AtomicUsize,
+    weak: AtomicUsize,
+    // dropped by hand once the strong count reaches zero.
+    value: ManuallyDrop<T>,
+}
+
+pub struct MyArc<T> {
+    ptr: NonNull<ArcInner<T>>,
+    // owns a `T`, as far as the drop checker is concerned.
+    _owns: PhantomData<ArcInner<T>>,
+}
+
+/// A reference that doesn't keep the value alive. `None`
+/// for one made by `MyWeak::new`, which points nowhere.
+pub struct MyWeak<T> {
+    ptr: Option<NonNull<ArcInner<T>>>,
+}
+
+// any thread holding a reference may drop the value or
+// reach it through `Deref`, hence `Send + Sync` for both.
+unsafe impl<T: Send + Sync> Send for MyArc<T> {}
+unsafe impl<T: Send + Sync> Sync for MyArc<T> {}
+unsafe impl<T: Send + Sync> Send for MyWeak<T> {}
+unsafe impl<T: Send + Sync> Sync for MyWeak<T> {}
+
+// how many `ArcInner`s are allocated, for finding leaks.
+#[cfg(feature = "trace")]
+static LIVE: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+// the counts, reached without making a reference to the
+// whole `ArcInner`, whose value may be gone or be moved
+// out by another thread.
+fn counts<'a, T>(ptr: NonNull<ArcInner<T>>) -> (&'a AtomicUsize, &'a AtomicUsize) {
+    let raw = ptr.as_ptr();
+    // SAFETY: callers only pass pointers to allocations a
+    // reference of theirs keeps alive.
+    unsafe { (&*ptr::addr_of!((*raw).strong), &*ptr::addr_of!((*raw).weak)) }
+}
+
+// gives up one weak reference, freeing the allocation if
+// it was the last.
+fn release_weak<T>(ptr: NonNull<ArcInner<T>>) {
+    let (_, weak) = counts(ptr);
+    if weak.fetch_sub(1, Ordering::Release) != 1 {
+        return;
+    }
+    // every other reference's last use of the counts
+    // happens before the free.
+    fence(Ordering::Acquire);
+    #[cfg(feature = "trace")]
+    LIVE.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+    crate::trace!("MyArc {ptr:p}: freed");
+    // SAFETY: the pointer came from `MyBox::into_raw` and
+    // nothing refers to it any more. the value is in a
+    // `ManuallyDrop`, so only the counts drop.
+    drop(unsafe { MyBox::from_raw(ptr.as_ptr()) });
+}
+
+// adds one to a count, which must not be zero.
+fn increment(count: &AtomicUsize) -> usize {
+    let old = count.fetch_add(1, Ordering::Relaxed);
+    if old > MAX_REFCOUNT {
+        std::process::abort();
+    }
+    old + 1
+}
+
+impl<T> MyArc<T> {
+    pub fn new(value: T) -> Self {
+        let inner = MyBox::new(ArcInner {
+            strong: AtomicUsize::new(1),
+            weak: AtomicUsize::new(1),
+            value: ManuallyDrop::new(value),
+        });
+        // SAFETY: `into_raw` never returns null.
+        let ptr = unsafe { NonNull::new_unchecked(MyBox::into_raw(inner)) };
+        #[cfg(feature = "trace")]
+        LIVE.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        crate::trace!("MyArc {ptr:p}: created");
+        Self {
+            ptr,
+            _owns: PhantomData,
+        }
+    }
+
+    /// How many allocations are alive, counting ones only
+    /// weak references hold. A number that keeps growing
+    /// is a leak.
+    #[cfg(feature = "trace")]
+    pub fn live() -> usize {
+        LIVE.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// The strong count. Other threads may change it at
+    /// any moment, so it is a hint rather than a fact.
+    pub fn strong_count(this: &Self) -> usize {
+        counts(this.ptr).0.load(Ordering::Relaxed)
+    }
+
+    /// How many `MyWeak`s point here, not counting the one
+    /// the strong references share. A hint, like
+    /// `strong_count`.
+    pub fn weak_count(this: &Self) -> usize {
+        match counts(this.ptr).1.load(Ordering::Relaxed) {
+            // `get_mut` only locks when there are none.
+            LOCKED => 0,
+            weak => weak - 1,
+        }
+    }
+
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    pub fn downgrade(this: &Self) -> MyWeak<T> {
+        let (_, weak) = counts(this.ptr);
+        let mut current = weak.load(Ordering::Relaxed);
+        loop {
+            if current == LOCKED {
+                spin_loop();
+                current = weak.load(Ordering::Relaxed);
+                continue;
+            }
+            if current > MAX_REFCOUNT {
+                std::process::abort();
+            }
+            // `Acquire` pairs with the `Release` that unlocks
+            // the count in `is_unique`.
+            match weak.compare_exchange_weak(
+                current,
+                current + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+        crate::trace!("MyArc {:p}: downgraded", this.ptr);
+        MyWeak {
+            ptr: Some(this.ptr),
+        }
+    }
+
+    // whether this is the only reference, weak ones
+    // included.
+    fn is_unique(this: &Self) -> bool {
+        let (strong, weak) = counts(this.ptr);
+        // locking the weak count at one means nobody holds
+        // a `MyWeak` to upgrade, and nobody can downgrade
+        // until we unlock. `Acquire` sees the last weak
+        // reference's drop.
+        if weak
+            .compare_exchange(1, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        // `Acquire` sees the other strong references' last
+        // uses of the value, should they all be gone.
+        let unique = strong.load(Ordering::Acquire) == 1;
+        weak.store(1, Ordering::Release);
+        unique
+    }
+
+    /// A mutable reference to the value, if this is the
+    /// only reference to it, weak ones included.
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if !Self::is_unique(this) {
+            return None;
+        }
+        // SAFETY: nothing else can reach the value, and
+        // `&mut this` keeps it that way.
+        Some(unsafe { &mut (*this.ptr.as_ptr()).value })
+    }
+
+    /// The value, if this was the only strong reference.
+    /// Weak ones are left pointing at nothing. Otherwise
+    /// hands the reference back.
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        let (strong, _) = counts(this.ptr);
+        if strong
+            .compare_exchange(1, 0, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(this);
+        }
+        // pairs with the `Release` of the strong references
+        // dropped before, as in `drop`.
+        fence(Ordering::Acquire);
+        let ptr = this.ptr;
+        std::mem::forget(this);
+        // SAFETY: the strong count is zero, so nobody else
+        // can reach the value, and it is moved out once.
+        let value = unsafe { ManuallyDrop::take(&mut (*ptr.as_ptr()).value) };
+        crate::trace!("MyArc {ptr:p}: unwrapped");
+        release_weak(ptr);
+        Ok(value)
+    }
+}
+
+impl<T: Clone> MyArc<T> {
+    /// A mutable reference to the value, cloning it into a
+    /// fresh allocation first if other strong references
+    /// share it. Weak ones are cut loose rather than
+    /// cloned for.
+    pub fn make_mut(this: &mut Self) -> &mut T {
+        let (strong, weak) = counts(this.ptr);
+        // dropping the strong count to zero stops weak
+        // references upgrading while we look.
+        if strong
+            .compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            *this = Self::new((**this).clone());
+        } else if weak.load(Ordering::Relaxed) != 1 {
+            // weak references only: move the value out from
+            // under them, as `try_unwrap` would.
+            let ptr = this.ptr;
+            // SAFETY: the strong count is zero and ours was
+            // the last strong reference. `this` is
+            // overwritten without being dropped.
+            unsafe {
+                let value = ManuallyDrop::take(&mut (*ptr.as_ptr()).value);
+                ptr::write(this, Self::new(value));
+            }
+            release_weak(ptr);
+        } else {
+            // unique after all. no `MyWeak` exists to have
+            // seen the zero.
+            strong.store(1, Ordering::Release);
+        }
+        // SAFETY: `this` is now the only reference.
+        unsafe { &mut (*this.ptr.as_ptr()).value }
+    }
+}
+
+impl<T> Clone for MyArc<T> {
+    fn clone(&self) -> Self {
+        let (strong, _) = counts(self.ptr);
+        let _count = increment(strong);
+        crate::trace!("MyArc {:p}: cloned, strong {_count}", self.ptr);
+        Self {
+            ptr: self.ptr,
+            _owns: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for MyArc<T> {
+    fn drop(&mut self) {
+        let (strong, _) = counts(self.ptr);
+        let old = strong.fetch_sub(1, Ordering::Release);
+        crate::trace!("MyArc {:p}: dropped, strong {}", self.ptr, old - 1);
+        if old != 1 {
+            return;
+        }
+        // every other thread's use of the value happens
+        // before it is dropped.
+        fence(Ordering::Acquire);
+        // SAFETY: that was the last strong reference, so
+        // nothing can reach the value again.
+        unsafe { ManuallyDrop::drop(&mut (*self.ptr.as_ptr()).value) };
+        release_weak(self.ptr);
+    }
+}
+
+impl<T> Deref for MyArc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: a strong reference keeps the value alive,
+        // and only shared references to it are handed out
+        // while others might exist.
+        unsafe { &(*self.ptr.as_ptr()).value }
+    }
+}
+
+impl<T: Default> Default for MyArc<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MyArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> MyWeak<T> {
+    /// A weak reference that never upgrades.
+    pub fn new() -> Self {
+        Self { ptr: None }
+    }
+
+    /// A strong reference, unless the value is gone.
+    pub fn upgrade(&self) -> Option<MyArc<T>> {
+        let ptr = self.ptr?;
+        let (strong, _) = counts(ptr);
+        let mut current = strong.load(Ordering::Relaxed);
+        loop {
+            // gone, or `make_mut` is moving it away.
+            if current == 0 {
+                return None;
+            }
+            if current > MAX_REFCOUNT {
+                std::process::abort();
+            }
+            // `Acquire` pairs with `make_mut` putting the
+            // count back, after it had changed the value.
+            match strong.compare_exchange_weak(
+                current,
+                current + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+        Some(MyArc {
+            ptr,
+            _owns: PhantomData,
+        })
+    }
+
+    pub fn strong_count(&self) -> usize {
+        self.ptr
+            .map_or(0, |ptr| counts(ptr).0.load(Ordering::Relaxed))
+    }
+
+    /// How many `MyWeak`s point here, or 0 once the value
+    /// is gone, like std's. A hint, like `strong_count`.
+    pub fn weak_count(&self) -> usize {
+        let Some((strong, weak)) = self.ptr.map(counts) else {
+            return 0;
+        };
+        let weak = weak.load(Ordering::Relaxed);
+        if strong.load(Ordering::Relaxed) == 0 || weak == LOCKED {
+            return 0;
+        }
+        weak - 1
+    }
+
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr
+    }
+}
+
+impl<T> Default for MyWeak<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Clone for MyWeak<T> {
+    fn clone(&self) -> Self {
+        if let Some(ptr) = self.ptr {
+            // we hold a weak reference, so the count can't be
+            // locked: that needs it at one, with only the
+            // strong references' share.
+            increment(counts(ptr).1);
+        }
+        Self { ptr: self.ptr }
+    }
+}
+
+impl<T> Drop for MyWeak<T> {
+    fn drop(&mut self) {
+        if let Some(ptr) = self.ptr {
+            release_weak(ptr);
+        }
+    }
+}
+
+impl<T> fmt::Debug for MyWeak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(MyWeak)")
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::{Arc, Weak};
+    use std::thread;
+
+    // counts its drops, so a test can tell a value went
+    // exactly once.
+    struct Tracked(Arc<AtomicUsize>);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn counts_match_std() {
+        let mine = MyArc::new(5);
+        let std = Arc::new(5);
+        let counts = |a: &MyArc<i32>, b: &Arc<i32>| {
+            assert_eq!(MyArc::strong_count(a), Arc::strong_count(b));
+            assert_eq!(MyArc::weak_count(a), Arc::weak_count(b));
+        };
+
+        let (mine2, std2) = (mine.clone(), std.clone());
+        let (mine_weak, std_weak) = (MyArc::downgrade(&mine), Arc::downgrade(&std));
+        let (mine_weak2, std_weak2) = (mine_weak.clone(), std_weak.clone());
+        counts(&mine, &std);
+        assert_eq!(mine_weak.strong_count(), std_weak.strong_count());
+        assert_eq!(mine_weak.weak_count(), std_weak.weak_count());
+        assert!(MyArc::ptr_eq(&mine, &mine2) && mine_weak.ptr_eq(&mine_weak2));
+
+        drop((mine2, std2, mine_weak2, std_weak2));
+        counts(&mine, &std);
+        assert_eq!(*mine_weak.upgrade().unwrap(), *std_weak.upgrade().unwrap());
+
+        drop((mine, std));
+        assert_eq!(mine_weak.upgrade().is_none(), std_weak.upgrade().is_none());
+        assert_eq!(mine_weak.weak_count(), std_weak.weak_count());
+        assert_eq!(
+            MyWeak::<i32>::new().upgrade().is_none(),
+            Weak::<i32>::new().upgrade().is_none()
+        );
+    }
+
+    #[test]
+    fn unique_access_matches_std() {
+        let mut mine = MyArc::new(vec![1]);
+        let mut std = Arc::new(vec![1]);
+        MyArc::get_mut(&mut mine).unwrap().push(2);
+        Arc::get_mut(&mut std).unwrap().push(2);
+
+        // a weak reference blocks `get_mut`, and loses the
+        // value to `make_mut`
+        let (mine_weak, std_weak) = (MyArc::downgrade(&mine), Arc::downgrade(&std));
+        assert_eq!(MyArc::get_mut(&mut mine), Arc::get_mut(&mut std));
+        MyArc::make_mut(&mut mine).push(3);
+        Arc::make_mut(&mut std).push(3);
+        assert_eq!(*mine, *std);
+        assert_eq!(mine_weak.upgrade().is_none(), std_weak.upgrade().is_none());
+
+        // a strong one makes `make_mut` clone
+        let (mine2, std2) = (mine.clone(), std.clone());
+        MyArc::make_mut(&mut mine).push(4);
+        Arc::make_mut(&mut std).push(4);
+        assert_eq!((&*mine, &*mine2), (&*std, &*std2));
+
+        let mine = MyArc::try_unwrap(mine).unwrap();
+        let std = Arc::try_unwrap(std).unwrap();
+        assert_eq!(mine, std);
+        let back = MyArc::try_unwrap(mine2.clone()).unwrap_err();
+        assert!(MyArc::ptr_eq(&back, &mine2));
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let shared = MyArc::new(Tracked(Arc::clone(&drops)));
+        let weak = MyArc::downgrade(&shared);
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (shared, weak) = (shared.clone(), weak.clone());
+                thread::spawn(move || {
+                    for _ in 0..1_000 {
+                        drop(shared.clone());
+                        drop(weak.upgrade());
+                        drop(MyArc::downgrade(&shared));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(MyArc::strong_count(&shared), 1);
+        assert_eq!(MyArc::weak_count(&shared), 1);
+        drop(shared);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert!(weak.upgrade().is_none());
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use loom::cell::UnsafeCell;
+    use loom::sync::Arc;
+    use loom::thread;
+
+    // the value each model shares. loom checks every touch
+    // of the cell against the others, so a read that isn't
+    // ordered before the drop's write fails the model.
+    struct Value {
+        cell: UnsafeCell<usize>,
+        drops: Arc<AtomicUsize>,
+    }
+
+    impl Value {
+        fn new(n: usize, drops: &Arc<AtomicUsize>) -> MyArc<Self> {
+            MyArc::new(Self {
+                cell: UnsafeCell::new(n),
+                drops: Arc::clone(drops),
+            })
+        }
+
+        fn read(&self) -> usize {
+            // SAFETY: loom checks this.
+            self.cell.with(|n| unsafe { *n })
+        }
+
+        fn write(&self, value: usize) {
+            // SAFETY: loom checks this.
+            self.cell.with_mut(|n| unsafe { *n = value })
+        }
+    }
+
+    impl Clone for Value {
+        fn clone(&self) -> Self {
+            Self {
+                cell: UnsafeCell::new(self.read()),
+                drops: Arc::clone(&self.drops),
+            }
+        }
+    }
+
+    impl Drop for Value {
+        fn drop(&mut self) {
+            self.write(usize::MAX);
+            self.drops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn last_drop_frees_once() {
+        loom::model(|| {
+            let drops = Arc::new(AtomicUsize::new(0));
+            let a = Value::new(0, &drops);
+            let b = a.clone();
+            let other = thread::spawn(move || assert_eq!(b.read(), 0));
+            assert_eq!(a.read(), 0);
+            drop(a);
+            other.join().unwrap();
+            assert_eq!(drops.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn upgrade_races_the_last_drop() {
+        loom::model(|| {
+            let drops = Arc::new(AtomicUsize::new(0));
+            let strong = Value::new(0, &drops);
+            let weak = MyArc::downgrade(&strong);
+            let other = thread::spawn(move || {
+                if let Some(value) = weak.upgrade() {
+                    assert_eq!(value.read(), 0);
+                }
+            });
+            drop(strong);
+            other.join().unwrap();
+            // whichever side let go last dropped it, once
+            assert_eq!(drops.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn try_unwrap_on_both_sides() {
+        loom::model(|| {
+            let drops = Arc::new(AtomicUsize::new(0));
+            let a = Value::new(0, &drops);
+            let b = a.clone();
+            let other = thread::spawn(move || MyArc::try_unwrap(b).map(|value| value.read()).ok());
+            let here = MyArc::try_unwrap(a).map(|value| value.read()).ok();
+            let there = other.join().unwrap();
+            // at most one side gets the value, and it is
+            // dropped exactly once whoever ends up with it
+            assert!(here.is_none() || there.is_none());
+            assert_eq!(drops.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn get_mut_excludes_downgrade() {
+        loom::model(|| {
+            let drops = Arc::new(AtomicUsize::new(0));
+            let mut a = Value::new(0, &drops);
+            let b = a.clone();
+            let other = thread::spawn(move || {
+                let weak = MyArc::downgrade(&b);
+                drop(b);
+                weak.upgrade().map(|value| value.read())
+            });
+            // unique only once the other side has let go of
+            // everything, weak reference included, so the
+            // write never races its read.
+            if let Some(value) = MyArc::get_mut(&mut a) {
+                value.write(1);
+            }
+            assert_ne!(other.join().unwrap(), Some(1));
+        });
+    }
+
+    #[test]
+    fn get_mut_after_the_other_drop() {
+        loom::model(|| {
+            let drops = Arc::new(AtomicUsize::new(0));
+            let mut a = Value::new(0, &drops);
+            let b = a.clone();
+            let other = thread::spawn(move || assert_eq!(b.read(), 0));
+            // only a strong reference's drop orders its read
+            // before the write here.
+            if let Some(value) = MyArc::get_mut(&mut a) {
+                value.write(1);
+            }
+            other.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn make_mut_races_an_upgrade() {
+        loom::model(|| {
+            let drops = Arc::new(AtomicUsize::new(0));
+            let mut a = Value::new(0, &drops);
+            let weak = MyArc::downgrade(&a);
+            let other = thread::spawn(move || weak.upgrade().map(|value| value.read()));
+            let value = MyArc::make_mut(&mut a);
+            value.write(value.read() + 1);
+            // an upgrade that won the race holds the old
+            // value, and `make_mut` copied it away.
+            if let Some(seen) = other.join().unwrap() {
+                assert_eq!(seen, 0);
+            }
+            assert_eq!(a.read(), 1);
+        });
+    }
+}
@@ -0,0 +1,181 @@
+/*
+ *  MyBox
+ *
+ *  A value on the heap with a single owner. All a box
+ *  really is: a pointer from the allocator, `Deref` to
+ *  reach through it, and a `Drop` that drops the value
+ *  and hands the memory back.
+ *
+ *  A zero-sized value needs no memory, and asking the
+ *  allocator for zero bytes is undefined, so those get a
+ *  dangling but well-aligned pointer instead.
+ */
+
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::ptr::{self, NonNull};
+
+pub struct MyBox<T> {
+    ptr: NonNull<T>,
+    // tells the drop checker we own a `T`, not just point
+    // at one.
+    _owns: PhantomData<T>,
+}
+
+// the box owns its value outright, so it can cross
+// threads whenever the value can.
+unsafe impl<T: Send> Send for MyBox<T> {}
+unsafe impl<T: Sync> Sync for MyBox<T> {}
+
+impl<T> MyBox<T> {
+    pub fn new(value: T) -> Self {
+        let layout = Layout::new::<T>();
+        let ptr = if layout.size() == 0 {
+            NonNull::dangling()
+        } else {
+            // SAFETY: the layout isn't zero-sized.
+            let raw = unsafe { alloc::alloc(layout) }.cast::<T>();
+            NonNull::new(raw).unwrap_or_else(|| alloc::handle_alloc_error(layout))
+        };
+        // SAFETY: the pointer is fresh, aligned and big
+        // enough for a `T`.
+        unsafe { ptr.as_ptr().write(value) };
+        crate::trace!("MyBox {ptr:p}: allocated");
+        Self {
+            ptr,
+            _owns: PhantomData,
+        }
+    }
+
+    /// Moves the value back out, freeing the memory.
+    pub fn into_inner(this: Self) -> T {
+        let raw = Self::into_raw(this);
+        // SAFETY: the pointer came from a box, and the
+        // value is read once before the memory goes.
+        unsafe {
+            let value = raw.read();
+            Self::free(raw);
+            value
+        }
+    }
+
+    /// Gives up ownership without dropping anything. The
+    /// pointer has to go back through `from_raw` to be
+    /// freed.
+    pub fn into_raw(this: Self) -> *mut T {
+        let raw = this.ptr.as_ptr();
+        std::mem::forget(this);
+        raw
+    }
+
+    /// Takes back ownership of a pointer from `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must have come from `MyBox::into_raw`, hold a
+    /// live value, and not be used again.
+    pub unsafe fn from_raw(raw: *mut T) -> Self {
+        Self {
+            ptr: NonNull::new_unchecked(raw),
+            _owns: PhantomData,
+        }
+    }
+
+    // returns the memory to the allocator without dropping
+    // the value in it.
+    unsafe fn free(raw: *mut T) {
+        crate::trace!("MyBox {raw:p}: freed");
+        let layout = Layout::new::<T>();
+        if layout.size() != 0 {
+            alloc::dealloc(raw.cast(), layout);
+        }
+    }
+}
+
+impl<T> Deref for MyBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the box owns a live value, and the
+        // borrow can't outlive the box.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for MyBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: as for `deref`, and `&mut self` makes the
+        // borrow unique.
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for MyBox<T> {
+    fn drop(&mut self) {
+        let raw = self.ptr.as_ptr();
+        // SAFETY: the value is live until here, and nothing
+        // touches the memory afterwards.
+        unsafe {
+            ptr::drop_in_place(raw);
+            Self::free(raw);
+        }
+    }
+}
+
+impl<T: Clone> Clone for MyBox<T> {
+    fn clone(&self) -> Self {
+        Self::new((**self).clone())
+    }
+}
+
+impl<T: Default> Default for MyBox<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MyBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn behaves_like_box() {
+        let mut mine = MyBox::new(vec![1, 2]);
+        let mut std = Box::new(vec![1, 2]);
+        mine.push(3);
+        std.push(3);
+        assert_eq!(*mine, *std);
+        assert_eq!(format!("{mine:?}"), format!("{std:?}"));
+        assert_eq!(MyBox::into_inner(mine.clone()), *std);
+
+        // nothing to allocate, but it still derefs and drops
+        let unit = MyBox::new(());
+        assert_eq!(*unit, ());
+        let raw = MyBox::into_raw(MyBox::new(String::from("back")));
+        // SAFETY: straight from `into_raw`.
+        assert_eq!(*unsafe { MyBox::from_raw(raw) }, "back");
+    }
+
+    #[test]
+    fn drops_its_value_once() {
+        let value = Rc::new(());
+        let boxed = MyBox::new(Rc::clone(&value));
+        assert_eq!(Rc::strong_count(&value), 2);
+        drop(boxed);
+        assert_eq!(Rc::strong_count(&value), 1);
+
+        let inner = MyBox::into_inner(MyBox::new(Rc::clone(&value)));
+        assert_eq!(Rc::strong_count(&value), 2);
+        drop(inner);
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,453 @@
+/*
+ *  MyCell and MyRefCell
+ *
+ *  Both change a value through a shared reference, which
+ *  the borrow checker normally forbids. `UnsafeCell` is
+ *  the one type allowed to do that, and each cell wraps it
+ *  with its own rule for staying sound.
+ *
+ *  `MyCell` never lends out a reference to what's inside.
+ *  Values only go in and out by copy or swap, so nobody
+ *  can be holding a reference when it changes.
+ *
+ *  `MyRefCell` does lend references, and counts them at
+ *  runtime instead: any number of `Ref`s, or one `RefMut`.
+ *  Asking for a borrow that breaks the rule panics, or
+ *  comes back as an error from the `try_` versions.
+ *
+ *  Neither is `Sync`, since the bookkeeping isn't atomic.
+ */
+
+use std::cell::UnsafeCell;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+pub struct MyCell<T> {
+    value: UnsafeCell<T>,
+}
+
+impl<T> MyCell<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn set(&self, value: T) {
+        drop(self.replace(value));
+    }
+
+    pub fn replace(&self, value: T) -> T {
+        // SAFETY: no reference into the cell ever escapes,
+        // and it's not `Sync`, so this is the only access.
+        mem::replace(unsafe { &mut *self.value.get() }, value)
+    }
+
+    pub fn swap(&self, other: &Self) {
+        if std::ptr::eq(self, other) {
+            return;
+        }
+        // SAFETY: as in `replace`, and the two are apart.
+        unsafe { std::ptr::swap(self.value.get(), other.value.get()) }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: Copy> MyCell<T> {
+    pub fn get(&self) -> T {
+        // SAFETY: as in `replace`.
+        unsafe { *self.value.get() }
+    }
+}
+
+impl<T: Default> MyCell<T> {
+    pub fn take(&self) -> T {
+        self.replace(T::default())
+    }
+}
+
+impl<T: Copy> Clone for MyCell<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.get())
+    }
+}
+
+impl<T: Default> Default for MyCell<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for MyCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MyCell")
+            .field("value", &self.get())
+            .finish()
+    }
+}
+
+// positive: that many `Ref`s. -1: one `RefMut`.
+type BorrowState = isize;
+const UNUSED: BorrowState = 0;
+const WRITING: BorrowState = -1;
+
+pub struct MyRefCell<T> {
+    borrow: MyCell<BorrowState>,
+    value: UnsafeCell<T>,
+}
+
+/// Returned by `try_borrow` while the value is borrowed
+/// mutably.
+#[derive(Debug)]
+pub struct BorrowError;
+
+/// Returned by `try_borrow_mut` while the value is
+/// borrowed at all.
+#[derive(Debug)]
+pub struct BorrowMutError;
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("already mutably borrowed")
+    }
+}
+
+impl fmt::Display for BorrowMutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("already borrowed")
+    }
+}
+
+impl Error for BorrowError {}
+
+impl Error for BorrowMutError {}
+
+impl<T> MyRefCell<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            borrow: MyCell::new(UNUSED),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Borrows the value, panicking if it's borrowed
+    /// mutably.
+    #[track_caller]
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.try_borrow()
+            .unwrap_or_else(|err| panic!("MyRefCell {err}"))
+    }
+
+    /// Borrows the value mutably, panicking if it's
+    /// borrowed at all.
+    #[track_caller]
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.try_borrow_mut()
+            .unwrap_or_else(|err| panic!("MyRefCell {err}"))
+    }
+
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
+        let borrow = BorrowRef::new(&self.borrow).ok_or(BorrowError)?;
+        crate::trace!("MyRefCell {:p}: borrowed, {} now", self, self.borrow.get());
+        Ok(Ref {
+            // SAFETY: `UnsafeCell::get` is never null.
+            value: unsafe { NonNull::new_unchecked(self.value.get()) },
+            borrow,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, BorrowMutError> {
+        let borrow = BorrowRefMut::new(&self.borrow).ok_or(BorrowMutError)?;
+        crate::trace!("MyRefCell {:p}: borrowed mutably", self);
+        Ok(RefMut {
+            // SAFETY: as in `try_borrow`.
+            value: unsafe { NonNull::new_unchecked(self.value.get()) },
+            borrow,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Swaps in `value` and returns the old one. Panics if
+    /// the value is borrowed.
+    #[track_caller]
+    pub fn replace(&self, value: T) -> T {
+        mem::replace(&mut *self.borrow_mut(), value)
+    }
+
+    /// No borrows can be out while we hold `&mut self`, so
+    /// this skips the count.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: Default> MyRefCell<T> {
+    #[track_caller]
+    pub fn take(&self) -> T {
+        self.replace(T::default())
+    }
+}
+
+impl<T: Clone> Clone for MyRefCell<T> {
+    #[track_caller]
+    fn clone(&self) -> Self {
+        Self::new(self.borrow().clone())
+    }
+}
+
+impl<T: Default> Default for MyRefCell<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MyRefCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("MyRefCell");
+        match self.try_borrow() {
+            Ok(value) => d.field("value", &&*value),
+            Err(_) => d.field("value", &format_args!("<borrowed>")),
+        };
+        d.finish()
+    }
+}
+
+// a shared borrow, counted for as long as it lives.
+struct BorrowRef<'b> {
+    borrow: &'b MyCell<BorrowState>,
+}
+
+impl<'b> BorrowRef<'b> {
+    fn new(borrow: &'b MyCell<BorrowState>) -> Option<Self> {
+        let count = borrow.get();
+        if count == WRITING {
+            return None;
+        }
+        borrow.set(count.checked_add(1).expect("too many borrows"));
+        Some(Self { borrow })
+    }
+}
+
+impl Clone for BorrowRef<'_> {
+    fn clone(&self) -> Self {
+        Self::new(self.borrow).unwrap()
+    }
+}
+
+impl Drop for BorrowRef<'_> {
+    fn drop(&mut self) {
+        self.borrow.set(self.borrow.get() - 1);
+    }
+}
+
+// the exclusive borrow.
+struct BorrowRefMut<'b> {
+    borrow: &'b MyCell<BorrowState>,
+}
+
+impl<'b> BorrowRefMut<'b> {
+    fn new(borrow: &'b MyCell<BorrowState>) -> Option<Self> {
+        if borrow.get() != UNUSED {
+            return None;
+        }
+        borrow.set(WRITING);
+        Some(Self { borrow })
+    }
+}
+
+impl Drop for BorrowRefMut<'_> {
+    fn drop(&mut self) {
+        self.borrow.set(UNUSED);
+    }
+}
+
+// the guards hold a pointer rather than a reference so
+// that `map` can narrow it to part of the value, and the
+// reference handed out never outlives the guard.
+
+/// A shared borrow of a `MyRefCell`'s value.
+pub struct Ref<'b, T: ?Sized> {
+    value: NonNull<T>,
+    borrow: BorrowRef<'b>,
+    _marker: PhantomData<&'b T>,
+}
+
+impl<'b, T: ?Sized> Ref<'b, T> {
+    /// Another borrow of the same value. Not `Clone`, so
+    /// that `r.clone()` still clones what's inside.
+    #[allow(clippy::should_implement_trait)]
+    pub fn clone(this: &Self) -> Self {
+        Self {
+            value: this.value,
+            borrow: this.borrow.clone(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Narrows the borrow to a part of the value.
+    pub fn map<U: ?Sized>(this: Self, f: impl FnOnce(&T) -> &U) -> Ref<'b, U> {
+        Ref {
+            value: NonNull::from(f(&*this)),
+            borrow: this.borrow,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for Ref<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the counted borrow keeps any `RefMut` away
+        // for as long as this guard lives.
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Ref<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// The exclusive borrow of a `MyRefCell`'s value.
+pub struct RefMut<'b, T: ?Sized> {
+    value: NonNull<T>,
+    borrow: BorrowRefMut<'b>,
+    // `&mut`, so the guard is invariant in `T` like the
+    // reference it stands for.
+    _marker: PhantomData<&'b mut T>,
+}
+
+impl<'b, T: ?Sized> RefMut<'b, T> {
+    /// Narrows the borrow to a part of the value.
+    pub fn map<U: ?Sized>(mut this: Self, f: impl FnOnce(&mut T) -> &mut U) -> RefMut<'b, U> {
+        let value = NonNull::from(f(&mut *this));
+        RefMut {
+            value,
+            borrow: this.borrow,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RefMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: this guard is the only borrow out.
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: as in `deref`, and `&mut self` keeps the
+        // reference unique.
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RefMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{self, Cell, RefCell};
+
+    #[test]
+    fn cell_matches_std() {
+        let mine = MyCell::new(1);
+        let std = Cell::new(1);
+        assert_eq!(mine.replace(2), std.replace(2));
+        mine.set(mine.get() * 10);
+        std.set(std.get() * 10);
+        assert_eq!(mine.get(), std.get());
+
+        let other = MyCell::new(7);
+        mine.swap(&other);
+        mine.swap(&mine);
+        assert_eq!((mine.get(), other.get()), (7, 20));
+        assert_eq!(format!("{mine:?}"), "MyCell { value: 7 }");
+
+        let words = MyCell::new(vec!["a"]);
+        assert_eq!(words.take(), ["a"]);
+        assert!(words.into_inner().is_empty());
+    }
+
+    #[test]
+    fn refcell_matches_std() {
+        let mine = MyRefCell::new(vec![1]);
+        let std = RefCell::new(vec![1]);
+
+        {
+            let (a, b) = (mine.borrow(), Ref::clone(&mine.borrow()));
+            let (c, d) = (std.borrow(), cell::Ref::clone(&std.borrow()));
+            assert_eq!((&*a, &*b), (&*c, &*d));
+            assert_eq!(
+                mine.try_borrow_mut().is_err(),
+                std.try_borrow_mut().is_err()
+            );
+            assert_eq!(mine.try_borrow().is_ok(), std.try_borrow().is_ok());
+            assert_eq!(format!("{mine:?}"), "MyRefCell { value: [1] }");
+        }
+        {
+            let (mut a, mut b) = (mine.borrow_mut(), std.borrow_mut());
+            a.push(2);
+            b.push(2);
+            assert_eq!(mine.try_borrow().is_err(), std.try_borrow().is_err());
+            assert_eq!(
+                mine.try_borrow_mut().is_err(),
+                std.try_borrow_mut().is_err()
+            );
+            assert_eq!(format!("{mine:?}"), "MyRefCell { value: <borrowed> }");
+        }
+        assert_eq!(mine.replace(vec![3]), std.replace(vec![3]));
+
+        let first = Ref::map(mine.borrow(), |v| &v[0]);
+        assert_eq!(*first, 3);
+        drop(first);
+        *RefMut::map(mine.borrow_mut(), |v| &mut v[0]) += 1;
+        assert_eq!(mine.take(), [4]);
+        assert!(mine.try_borrow_mut().is_ok());
+
+        assert_eq!(BorrowError.to_string(), "already mutably borrowed");
+        assert_eq!(BorrowMutError.to_string(), "already borrowed");
+    }
+
+    #[test]
+    #[should_panic = "MyRefCell already borrowed"]
+    fn borrow_mut_while_borrowed_panics() {
+        let cell = MyRefCell::new(0);
+        let _reading = cell.borrow();
+        let _ = cell.borrow_mut();
+    }
+
+    #[test]
+    #[should_panic = "MyRefCell already mutably borrowed"]
+    fn borrow_while_borrowed_mutably_panics() {
+        let cell = MyRefCell::new(0);
+        let _writing = cell.borrow_mut();
+        let _ = cell.borrow();
+    }
+}
@@ -0,0 +1,47 @@
+/*
+ *  Smart pointers
+ *
+ *  `Box` gives a value one owner. `Rc` lets several
+ *  owners share it, `RefCell` lets them mutate it
+ *  through a shared reference, checking the borrows at
+ *  runtime, and `Weak` points at it without keeping it
+ *  alive, which is how a child can know its parent
+ *  without the two keeping each other around forever.
+ *
+ *  `boxed`, `cell` and `rc` build each of those by hand,
+ *  out of raw allocations and `UnsafeCell`, to show there
+ *  is no magic in them. They are tested against std's,
+ *  and under Miri with
+ *
+ *      cargo +nightly miri test -p smart-pointers
+ *
+ *  `arc` makes the counts atomic, so the references can
+ *  cross threads, and checks the orderings under loom.
+ *
+ *  Built with the `trace` feature, they log every
+ *  allocation, count change and borrow to stderr.
+ */
+
+pub mod arc;
+pub mod boxed;
+pub mod cell;
+pub mod rc;
+pub mod tree;
+
+pub use arc::MyArc;
+pub use boxed::MyBox;
+pub use cell::{MyCell, MyRefCell};
+pub use rc::{MyRc, MyWeak};
+pub use tree::NodeRef;
+
+// logs a line to stderr when built with the `trace`
+// feature. the arguments are still type-checked without
+// it, then optimised away.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if cfg!(feature = "trace") {
+            eprintln!($($arg)*);
+        }
+    };
+}
+pub(crate) use trace;
@@ -0,0 +1,444 @@
+/*
+ *  MyRc and MyWeak
+ *
+ *  One allocation holds the value and two counts. Every
+ *  `MyRc` is a strong reference and keeps the value
+ *  alive. A `MyWeak` only keeps the allocation alive, so
+ *  it can still read the counts and see that the value is
+ *  gone.
+ *
+ *  As in std, all the strong references together hold one
+ *  weak reference between them. When the last strong one
+ *  goes, the value is dropped and that weak reference let
+ *  go, and whichever weak reference is last frees the
+ *  memory. The counts live in `MyCell`s, which is why
+ *  none of this can cross threads.
+ *
+ *  A count that would wrap aborts the process, as in
+ *  std. Leaking references with `mem::forget` is safe, so
+ *  a count can get there, and after wrapping the value
+ *  would be freed while references to it remain.
+ */
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::ptr::{self, NonNull};
+
+use crate::{MyBox, MyCell};
+
+struct RcBox<T> {
+    strong: MyCell<usize>,
+    weak: MyCell<usize>,
+    // dropped by hand when the strong count runs out,
+    // while the counts are still needed.
+    value: ManuallyDrop<T>,
+}
+
+pub struct MyRc<T> {
+    ptr: NonNull<RcBox<T>>,
+    // owns a `T`, as far as the drop checker is concerned.
+    _owns: PhantomData<RcBox<T>>,
+}
+
+/// A reference that doesn't keep the value alive. `None`
+/// for one made by `MyWeak::new`, which points nowhere.
+pub struct MyWeak<T> {
+    ptr: Option<NonNull<RcBox<T>>>,
+}
+
+// the counts, reached without making a reference to the
+// whole `RcBox`, whose value may have been dropped
+// already.
+fn counts<'a, T>(ptr: NonNull<RcBox<T>>) -> (&'a MyCell<usize>, &'a MyCell<usize>) {
+    let raw = ptr.as_ptr();
+    // SAFETY: callers only pass pointers to allocations a
+    // reference of theirs keeps alive.
+    unsafe { (&*ptr::addr_of!((*raw).strong), &*ptr::addr_of!((*raw).weak)) }
+}
+
+// adds one to a count, aborting rather than wrapping.
+fn increment(count: &MyCell<usize>) {
+    match count.get().checked_add(1) {
+        Some(next) => count.set(next),
+        None => std::process::abort(),
+    }
+}
+
+// gives up one weak reference, freeing the allocation if
+// it was the last.
+fn release_weak<T>(ptr: NonNull<RcBox<T>>) {
+    let (_, weak) = counts(ptr);
+    weak.set(weak.get() - 1);
+    if weak.get() == 0 {
+        // SAFETY: the pointer came from `MyBox::into_raw`
+        // and nothing refers to it any more. the value is
+        // in a `ManuallyDrop`, so only the counts drop.
+        drop(unsafe { MyBox::from_raw(ptr.as_ptr()) });
+    }
+}
+
+impl<T> MyRc<T> {
+    pub fn new(value: T) -> Self {
+        let inner = MyBox::new(RcBox {
+            strong: MyCell::new(1),
+            weak: MyCell::new(1),
+            value: ManuallyDrop::new(value),
+        });
+        // SAFETY: `into_raw` never returns null.
+        let ptr = unsafe { NonNull::new_unchecked(MyBox::into_raw(inner)) };
+        crate::trace!("MyRc {ptr:p}: created");
+        Self {
+            ptr,
+            _owns: PhantomData,
+        }
+    }
+
+    fn inner(&self) -> &RcBox<T> {
+        // SAFETY: a strong reference keeps both the memory
+        // and the value alive.
+        unsafe { self.ptr.as_ref() }
+    }
+
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().strong.get()
+    }
+
+    /// How many `MyWeak`s point here, not counting the
+    /// one the strong references share.
+    pub fn weak_count(this: &Self) -> usize {
+        this.inner().weak.get() - 1
+    }
+
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    pub fn downgrade(this: &Self) -> MyWeak<T> {
+        let weak = &this.inner().weak;
+        increment(weak);
+        crate::trace!("MyRc {:p}: downgraded, weak {}", this.ptr, weak.get() - 1);
+        MyWeak {
+            ptr: Some(this.ptr),
+        }
+    }
+
+    /// A mutable reference to the value, if this is the
+    /// only reference to it, weak ones included.
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if Self::strong_count(this) != 1 || Self::weak_count(this) != 0 {
+            return None;
+        }
+        // SAFETY: nothing else can reach the value, and
+        // `&mut this` keeps it that way.
+        Some(unsafe { &mut (*this.ptr.as_ptr()).value })
+    }
+
+    /// The value, if this was the only strong reference.
+    /// Weak ones are left pointing at nothing. Otherwise
+    /// hands the reference back.
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        if Self::strong_count(&this) != 1 {
+            return Err(this);
+        }
+        let ptr = this.ptr;
+        std::mem::forget(this);
+        let (strong, _) = counts(ptr);
+        strong.set(0);
+        // SAFETY: the last strong reference is gone, so the
+        // value is ours to move out, once.
+        let value = unsafe { ManuallyDrop::take(&mut (*ptr.as_ptr()).value) };
+        crate::trace!("MyRc {ptr:p}: unwrapped");
+        release_weak(ptr);
+        Ok(value)
+    }
+}
+
+impl<T: Clone> MyRc<T> {
+    /// A mutable reference to the value, cloning it into a
+    /// fresh allocation first if other strong references
+    /// share it. Weak ones are cut loose rather than
+    /// cloned for.
+    pub fn make_mut(this: &mut Self) -> &mut T {
+        if Self::strong_count(this) != 1 {
+            *this = Self::new((**this).clone());
+        } else if Self::weak_count(this) != 0 {
+            // move the value out from under the weak
+            // references, which then find it dropped.
+            let ptr = this.ptr;
+            let (strong, _) = counts(ptr);
+            strong.set(0);
+            // SAFETY: as in `try_unwrap`. `this` is
+            // overwritten without being dropped.
+            let value = unsafe { ManuallyDrop::take(&mut (*ptr.as_ptr()).value) };
+            unsafe { ptr::write(this, Self::new(value)) };
+            release_weak(ptr);
+        }
+        // SAFETY: `this` is now the only reference.
+        unsafe { &mut (*this.ptr.as_ptr()).value }
+    }
+}
+
+impl<T> Clone for MyRc<T> {
+    fn clone(&self) -> Self {
+        let strong = &self.inner().strong;
+        increment(strong);
+        crate::trace!("MyRc {:p}: cloned, strong {}", self.ptr, strong.get());
+        Self {
+            ptr: self.ptr,
+            _owns: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for MyRc<T> {
+    fn drop(&mut self) {
+        let (strong, _) = counts(self.ptr);
+        strong.set(strong.get() - 1);
+        crate::trace!("MyRc {:p}: dropped, strong {}", self.ptr, strong.get());
+        if strong.get() != 0 {
+            return;
+        }
+        // SAFETY: that was the last strong reference, so
+        // nothing can reach the value again.
+        unsafe { ManuallyDrop::drop(&mut (*self.ptr.as_ptr()).value) };
+        release_weak(self.ptr);
+    }
+}
+
+impl<T> Deref for MyRc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner().value
+    }
+}
+
+impl<T: Default> Default for MyRc<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MyRc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> MyWeak<T> {
+    /// A weak reference that never upgrades.
+    pub fn new() -> Self {
+        Self { ptr: None }
+    }
+
+    /// A strong reference, unless the value is gone.
+    pub fn upgrade(&self) -> Option<MyRc<T>> {
+        let ptr = self.ptr?;
+        let (strong, _) = counts(ptr);
+        if strong.get() == 0 {
+            return None;
+        }
+        increment(strong);
+        Some(MyRc {
+            ptr,
+            _owns: PhantomData,
+        })
+    }
+
+    pub fn strong_count(&self) -> usize {
+        self.ptr.map_or(0, |ptr| counts(ptr).0.get())
+    }
+
+    /// How many `MyWeak`s point here, or 0 once the value
+    /// is gone, like std's.
+    pub fn weak_count(&self) -> usize {
+        match self.ptr.map(counts) {
+            Some((strong, weak)) if strong.get() > 0 => weak.get() - 1,
+            _ => 0,
+        }
+    }
+
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr
+    }
+}
+
+impl<T> Default for MyWeak<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Clone for MyWeak<T> {
+    fn clone(&self) -> Self {
+        if let Some(ptr) = self.ptr {
+            let (_, weak) = counts(ptr);
+            increment(weak);
+        }
+        Self { ptr: self.ptr }
+    }
+}
+
+impl<T> Drop for MyWeak<T> {
+    fn drop(&mut self) {
+        if let Some(ptr) = self.ptr {
+            release_weak(ptr);
+        }
+    }
+}
+
+impl<T> fmt::Debug for MyWeak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(MyWeak)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MyRefCell;
+    use std::rc::{Rc, Weak};
+
+    // counts its drops, so a test can tell a value went
+    // exactly once.
+    struct Tracked(Rc<MyCell<usize>>);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn counts_match_std() {
+        let mine = MyRc::new(5);
+        let std = Rc::new(5);
+        let counts = |a: &MyRc<i32>, b: &Rc<i32>| {
+            assert_eq!(MyRc::strong_count(a), Rc::strong_count(b));
+            assert_eq!(MyRc::weak_count(a), Rc::weak_count(b));
+        };
+
+        let (mine2, std2) = (mine.clone(), std.clone());
+        counts(&mine, &std);
+        let (mine_weak, std_weak) = (MyRc::downgrade(&mine), Rc::downgrade(&std));
+        let (mine_weak2, std_weak2) = (mine_weak.clone(), std_weak.clone());
+        counts(&mine, &std);
+        assert_eq!(mine_weak.strong_count(), std_weak.strong_count());
+        assert_eq!(mine_weak.weak_count(), std_weak.weak_count());
+        assert!(MyRc::ptr_eq(&mine, &mine2) && mine_weak.ptr_eq(&mine_weak2));
+
+        drop((mine2, std2, mine_weak2, std_weak2));
+        counts(&mine, &std);
+        assert_eq!(*mine_weak.upgrade().unwrap(), *std_weak.upgrade().unwrap());
+
+        drop((mine, std));
+        assert_eq!(mine_weak.upgrade().is_none(), std_weak.upgrade().is_none());
+        assert_eq!(mine_weak.strong_count(), std_weak.strong_count());
+        assert_eq!(mine_weak.weak_count(), std_weak.weak_count());
+
+        let (nowhere, std_nowhere) = (MyWeak::<i32>::new(), Weak::<i32>::new());
+        assert_eq!(nowhere.upgrade().is_none(), std_nowhere.upgrade().is_none());
+        assert_eq!(nowhere.strong_count(), std_nowhere.strong_count());
+    }
+
+    #[test]
+    fn get_mut_and_try_unwrap_match_std() {
+        let mut mine = MyRc::new(String::from("a"));
+        let mut std = Rc::new(String::from("a"));
+        MyRc::get_mut(&mut mine).unwrap().push('b');
+        Rc::get_mut(&mut std).unwrap().push('b');
+
+        let (mine_weak, std_weak) = (MyRc::downgrade(&mine), Rc::downgrade(&std));
+        assert_eq!(MyRc::get_mut(&mut mine), Rc::get_mut(&mut std));
+        drop((mine_weak, std_weak));
+
+        let (mine2, std2) = (mine.clone(), std.clone());
+        let mine = MyRc::try_unwrap(mine).unwrap_err();
+        let std = Rc::try_unwrap(std).unwrap_err();
+        drop((mine2, std2));
+
+        let (mine_weak, std_weak) = (MyRc::downgrade(&mine), Rc::downgrade(&std));
+        assert_eq!(
+            MyRc::try_unwrap(mine).unwrap(),
+            Rc::try_unwrap(std).unwrap()
+        );
+        assert_eq!(mine_weak.upgrade().is_none(), std_weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn make_mut_matches_std() {
+        let mut mine = MyRc::new(vec![1]);
+        let mut std = Rc::new(vec![1]);
+
+        // shared: the other reference keeps the old value
+        let (mine2, std2) = (mine.clone(), std.clone());
+        MyRc::make_mut(&mut mine).push(2);
+        Rc::make_mut(&mut std).push(2);
+        assert_eq!((&*mine, &*mine2), (&*std, &*std2));
+        assert!(!MyRc::ptr_eq(&mine, &mine2));
+
+        // only weak references: they lose the value
+        let (mine_weak, std_weak) = (MyRc::downgrade(&mine), Rc::downgrade(&std));
+        MyRc::make_mut(&mut mine).push(3);
+        Rc::make_mut(&mut std).push(3);
+        assert_eq!(*mine, *std);
+        assert_eq!(mine_weak.upgrade().is_none(), std_weak.upgrade().is_none());
+        assert_eq!(MyRc::weak_count(&mine), Rc::weak_count(&std));
+
+        // unique: changed in place
+        let before = mine.ptr;
+        MyRc::make_mut(&mut mine).push(4);
+        assert_eq!(mine.ptr, before);
+    }
+
+    #[test]
+    fn drops_the_value_once() {
+        let drops = Rc::new(MyCell::new(0));
+        let rc = MyRc::new(Tracked(Rc::clone(&drops)));
+        let weak = MyRc::downgrade(&rc);
+        let rc2 = rc.clone();
+        drop(rc);
+        assert_eq!(drops.get(), 0);
+        drop(rc2);
+        assert_eq!(drops.get(), 1);
+        drop(weak);
+        assert_eq!(drops.get(), 1);
+
+        let value = MyRc::try_unwrap(MyRc::new(Tracked(Rc::clone(&drops))));
+        assert_eq!(drops.get(), 1);
+        drop(value);
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[test]
+    fn weak_parents_break_cycles() {
+        struct Node {
+            parent: MyRefCell<MyWeak<Node>>,
+            children: MyRefCell<Vec<MyRc<Node>>>,
+        }
+
+        let leaf = MyRc::new(Node {
+            parent: MyRefCell::new(MyWeak::new()),
+            children: MyRefCell::new(Vec::new()),
+        });
+        {
+            let branch = MyRc::new(Node {
+                parent: MyRefCell::new(MyWeak::new()),
+                children: MyRefCell::new(vec![leaf.clone()]),
+            });
+            *leaf.parent.borrow_mut() = MyRc::downgrade(&branch);
+            assert!(leaf.parent.borrow().upgrade().is_some());
+            assert_eq!(branch.children.borrow().len(), 1);
+            assert_eq!(
+                (MyRc::strong_count(&branch), MyRc::weak_count(&branch)),
+                (1, 1)
+            );
+            assert_eq!(MyRc::strong_count(&leaf), 2);
+        }
+        // the branch had no other owner, so it's gone
+        assert!(leaf.parent.borrow().upgrade().is_none());
+        assert_eq!(MyRc::strong_count(&leaf), 1);
+    }
+}
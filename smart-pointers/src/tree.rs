@@ -0,0 +1,324 @@
+/*
+ *  A parent-linked tree
+ *
+ *  Each node owns its children through `Rc`, and points
+ *  back at its parent through `Weak`. Ownership only
+ *  flows downwards, so detaching a subtree, or dropping
+ *  the last handle to the root, frees every node nobody
+ *  else holds. A strong link upwards would form a cycle
+ *  that `Rc` can never free.
+ *
+ *  The one way to build a cycle anyway is to make a node
+ *  a child of its own descendant, so `append` refuses to.
+ */
+
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+struct Node<T> {
+    value: T,
+    parent: Weak<RefCell<Node<T>>>,
+    children: Vec<Rc<RefCell<Node<T>>>>,
+}
+
+// dropping a node drops its children, then theirs, one
+// stack frame per level. a long chain is taken apart
+// with a loop instead.
+impl<T> Drop for Node<T> {
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(&mut self.children);
+        while let Some(child) = stack.pop() {
+            // a child someone else still holds lives on.
+            if let Ok(cell) = Rc::try_unwrap(child) {
+                stack.append(&mut cell.into_inner().children);
+            }
+        }
+    }
+}
+
+/// A shared handle to a node. Cloning the handle shares
+/// the node, and a node lives while its parent or any
+/// handle holds it.
+pub struct NodeRef<T>(Rc<RefCell<Node<T>>>);
+
+impl<T> Clone for NodeRef<T> {
+    fn clone(&self) -> Self {
+        Self(Rc::clone(&self.0))
+    }
+}
+
+impl<T> NodeRef<T> {
+    /// Creates a node with no parent and no children.
+    pub fn new(value: T) -> Self {
+        Self(Rc::new(RefCell::new(Node {
+            value,
+            parent: Weak::new(),
+            children: Vec::new(),
+        })))
+    }
+
+    pub fn value(&self) -> Ref<'_, T> {
+        Ref::map(self.0.borrow(), |node| &node.value)
+    }
+
+    pub fn value_mut(&self) -> RefMut<'_, T> {
+        RefMut::map(self.0.borrow_mut(), |node| &mut node.value)
+    }
+
+    /// Whether both handles point at the same node.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// The parent, unless this is a root or the parent was dropped.
+    pub fn parent(&self) -> Option<Self> {
+        self.0.borrow().parent.upgrade().map(Self)
+    }
+
+    pub fn children(&self) -> Vec<Self> {
+        self.0.borrow().children.iter().cloned().map(Self).collect()
+    }
+
+    /// Walks up from the parent to the root.
+    pub fn ancestors(&self) -> Ancestors<T> {
+        Ancestors {
+            next: self.parent(),
+        }
+    }
+
+    /// Visits this node and everything below it, parents
+    /// before their children.
+    pub fn descendants(&self) -> Descendants<T> {
+        Descendants {
+            stack: vec![self.clone()],
+        }
+    }
+
+    pub fn root(&self) -> Self {
+        self.ancestors().last().unwrap_or_else(|| self.clone())
+    }
+
+    /// How many steps up the root is.
+    pub fn depth(&self) -> usize {
+        self.ancestors().count()
+    }
+
+    /// Whether `self` is `other` or one of its ancestors.
+    pub fn contains(&self, other: &Self) -> bool {
+        other.ptr_eq(self) || other.ancestors().any(|a| a.ptr_eq(self))
+    }
+
+    /// The deepest node that is an ancestor of both, each
+    /// node counting as its own ancestor. `None` if they
+    /// are in different trees.
+    pub fn lowest_common_ancestor(&self, other: &Self) -> Option<Self> {
+        let (mut a, mut b) = (self.clone(), other.clone());
+        let (mut depth_a, mut depth_b) = (a.depth(), b.depth());
+        // bring the deeper one up level with the other,
+        // then step both up together until they meet.
+        while depth_a > depth_b {
+            a = a.parent()?;
+            depth_a -= 1;
+        }
+        while depth_b > depth_a {
+            b = b.parent()?;
+            depth_b -= 1;
+        }
+        while !a.ptr_eq(&b) {
+            a = a.parent()?;
+            b = b.parent()?;
+        }
+        Some(a)
+    }
+
+    /// Makes `child` the last child of this node, moving it
+    /// and its subtree from wherever it was.
+    ///
+    /// Panics if `child` is this node or one of its ancestors.
+    pub fn append(&self, child: &Self) {
+        // a leaf's subtree is just the leaf, which saves
+        // the walk up when building a tree top down.
+        let is_leaf = child.0.borrow().children.is_empty();
+        let would_cycle = child.ptr_eq(self) || (!is_leaf && child.contains(self));
+        assert!(
+            !would_cycle,
+            "a node can't become a child of its own subtree"
+        );
+        child.detach();
+        child.0.borrow_mut().parent = Rc::downgrade(&self.0);
+        self.0.borrow_mut().children.push(Rc::clone(&child.0));
+    }
+
+    /// Cuts this node and its subtree loose from its
+    /// parent. The subtree is freed once no handle holds
+    /// it.
+    pub fn detach(&self) {
+        let parent = std::mem::take(&mut self.0.borrow_mut().parent);
+        if let Some(parent) = parent.upgrade() {
+            parent
+                .borrow_mut()
+                .children
+                .retain(|child| !Rc::ptr_eq(child, &self.0));
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for NodeRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let node = self.0.borrow();
+        f.debug_struct("NodeRef")
+            .field("value", &node.value)
+            .field("children", &node.children.len())
+            .finish()
+    }
+}
+
+pub struct Ancestors<T> {
+    next: Option<NodeRef<T>>,
+}
+
+impl<T> Iterator for Ancestors<T> {
+    type Item = NodeRef<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.next.take()?;
+        self.next = node.parent();
+        Some(node)
+    }
+}
+
+pub struct Descendants<T> {
+    stack: Vec<NodeRef<T>>,
+}
+
+impl<T> Iterator for Descendants<T> {
+    type Item = NodeRef<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        // reversed, so the first child comes out first
+        self.stack.extend(node.children().into_iter().rev());
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strong(node: &NodeRef<&str>) -> usize {
+        Rc::strong_count(&node.0)
+    }
+
+    fn weak(node: &NodeRef<&str>) -> usize {
+        Rc::weak_count(&node.0)
+    }
+
+    fn child(parent: &NodeRef<&'static str>, value: &'static str) -> NodeRef<&'static str> {
+        let node = NodeRef::new(value);
+        parent.append(&node);
+        node
+    }
+
+    fn values(nodes: impl Iterator<Item = NodeRef<&'static str>>) -> Vec<&'static str> {
+        nodes.map(|node| *node.value()).collect()
+    }
+
+    #[test]
+    fn navigation() {
+        let html = NodeRef::new("html");
+        let head = child(&html, "head");
+        let body = child(&html, "body");
+        let div = child(&body, "div");
+        let p = child(&div, "p");
+        let span = child(&body, "span");
+
+        assert_eq!(values(p.ancestors()), ["div", "body", "html"]);
+        assert_eq!(p.depth(), 3);
+        assert_eq!(html.depth(), 0);
+        assert!(p.root().ptr_eq(&html));
+        assert_eq!(
+            values(html.descendants()),
+            ["html", "head", "body", "div", "p", "span"]
+        );
+
+        let lca = |a: &NodeRef<_>, b| a.lowest_common_ancestor(b).map(|n| *n.value());
+        assert_eq!(lca(&p, &span), Some("body"));
+        assert_eq!(lca(&p, &head), Some("html"));
+        assert_eq!(lca(&div, &p), Some("div"));
+        assert_eq!(lca(&p, &NodeRef::new("elsewhere")), None);
+
+        *span.value_mut() = "em";
+        assert_eq!(values(body.children().into_iter()), ["div", "em"]);
+    }
+
+    #[test]
+    fn reparenting_moves_the_subtree() {
+        let root = NodeRef::new("root");
+        let a = child(&root, "a");
+        let b = child(&root, "b");
+        let leaf = child(&a, "leaf");
+
+        b.append(&a);
+        assert!(a.parent().unwrap().ptr_eq(&b));
+        assert_eq!(values(root.children().into_iter()), ["b"]);
+        assert_eq!(values(leaf.ancestors()), ["a", "b", "root"]);
+        // the old parent no longer has a strong link to `a`
+        assert_eq!(strong(&a), 2);
+        assert_eq!(weak(&root), 1);
+    }
+
+    #[test]
+    fn nothing_leaks() {
+        let root = NodeRef::new("root");
+        let parent = child(&root, "parent");
+        let kept = child(&parent, "kept");
+        let dropped = Rc::downgrade(&child(&parent, "dropped").0);
+
+        // held by its parent and our handle, pointed at by
+        // each of its children
+        assert_eq!((strong(&parent), weak(&parent)), (2, 2));
+        assert!(dropped.upgrade().is_some());
+
+        parent.detach();
+        assert_eq!(strong(&parent), 1);
+        assert_eq!(weak(&root), 0);
+        assert!(parent.parent().is_none());
+
+        // only `kept` has a handle, so once the subtree goes
+        // everything else in it is freed
+        let parent_weak = Rc::downgrade(&parent.0);
+        drop(parent);
+        assert!(parent_weak.upgrade().is_none());
+        assert!(dropped.upgrade().is_none());
+        assert_eq!((strong(&kept), weak(&kept)), (1, 0));
+        assert!(kept.parent().is_none());
+
+        assert_eq!((strong(&root), weak(&root)), (1, 0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn a_node_cannot_adopt_its_ancestor() {
+        let root = NodeRef::new("root");
+        let leaf = child(&child(&root, "middle"), "leaf");
+        leaf.append(&root);
+    }
+
+    #[test]
+    fn dropping_a_long_chain() {
+        let root = NodeRef::new(0);
+        let mut last = root.clone();
+        // deep enough to overflow a recursive drop, and a
+        // lot shorter under Miri, which is far slower.
+        let len = if cfg!(miri) { 1_000 } else { 1_000_000 };
+        for i in 1..len {
+            let next = NodeRef::new(i);
+            last.append(&next);
+            last = next;
+        }
+        drop(last);
+        drop(root);
+    }
+}